@@ -0,0 +1,121 @@
+use crate::cache::CachedPost;
+use crate::links;
+use crate::markdown;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+/// Stable pseudonyms for usernames: the first name seen becomes `user1`, the
+/// next `user2`, and so on. Usernames are matched case-insensitively, as
+/// Discourse does.
+#[derive(Default)]
+pub struct Pseudonyms {
+    names: HashMap<String, String>,
+}
+
+impl Pseudonyms {
+    /// The pseudonym for `username`, assigning the next one if it's new.
+    pub fn get(&mut self, username: &str) -> String {
+        let next = self.names.len() + 1;
+        self.names
+            .entry(username.to_lowercase())
+            .or_insert_with(|| format!("user{}", next))
+            .clone()
+    }
+
+    /// Replace the post's author and the usernames in its Markdown. Discourse's
+    /// HTML names users too, so it's dropped.
+    pub fn anonymize_post(&mut self, post: &mut CachedPost) {
+        post.username = self.get(&post.username);
+        post.raw = self.anonymize_raw(&post.raw);
+        post.cooked = None;
+    }
+
+    /// Replace the usernames in a post's Markdown: `@mentions` and the authors
+    /// in quote attributions, `[quote="alice, post:3, topic:1"]`. Code is left
+    /// alone.
+    pub fn anonymize_raw(&mut self, raw: &str) -> String {
+        let mention = links::mention_regex();
+        let quote = Regex::new(r#"(?i)(\[quote=")([^"\]]*)"#).expect("quote regex is valid");
+        markdown::map_prose(raw, |text| {
+            let text = quote.replace_all(text, |caps: &Captures| {
+                format!("{}{}", &caps[1], self.anonymize_quote_attributes(&caps[2]))
+            });
+            mention
+                .replace_all(&text, |caps: &Captures| {
+                    format!("{}@{}", &caps["pre"], self.get(&caps["name"]))
+                })
+                .into_owned()
+        })
+    }
+
+    /// Replace the author in a quote's attributes, `alice, post:3, topic:1`.
+    /// Newer forums attribute quotes to the author's display name and give
+    /// their handle as `username:alice`; the handle picks the pseudonym then,
+    /// and both are replaced with it.
+    fn anonymize_quote_attributes(&mut self, attributes: &str) -> String {
+        let parts: Vec<&str> = attributes.split(',').collect();
+        let username = parts
+            .iter()
+            .find_map(|part| part.trim().strip_prefix("username:"))
+            .map(str::trim);
+        // `[quote="post:3, topic:1"]` names no one
+        let name = parts
+            .first()
+            .map(|part| part.trim())
+            .filter(|name| !name.is_empty() && !name.contains(':'));
+        let Some(author) = username.or(name) else {
+            return attributes.to_string();
+        };
+        let pseudonym = self.get(author);
+        parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                let trimmed = part.trim_start();
+                let leading = &part[..part.len() - trimmed.len()];
+                if trimmed.starts_with("username:") {
+                    format!("{}username:{}", leading, pseudonym)
+                } else if i == 0 && name.is_some() {
+                    format!("{}{}", leading, pseudonym)
+                } else {
+                    part.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_raw() {
+        let mut pseudonyms = Pseudonyms::default();
+        assert_eq!(pseudonyms.get("alice"), "user1");
+        let raw = "[quote=\"Bob, post:2, topic:1\"]\nhi @Alice\n[/quote]\n\
+                   cc @bob and @carol.dev, mail a@b.test, `@code`\n\
+                   [quote=\"post:4, topic:1\"]x[/quote]";
+        assert_eq!(
+            pseudonyms.anonymize_raw(raw),
+            "[quote=\"user2, post:2, topic:1\"]\nhi @user1\n[/quote]\n\
+             cc @user2 and @user3, mail a@b.test, `@code`\n\
+             [quote=\"post:4, topic:1\"]x[/quote]"
+        );
+        assert_eq!(pseudonyms.get("CAROL.dev"), "user3");
+    }
+
+    #[test]
+    fn test_anonymize_quote_display_name() {
+        let mut pseudonyms = Pseudonyms::default();
+        assert_eq!(pseudonyms.get("bob"), "user1");
+        let raw = "[quote=\"Alice Smith, post:3, topic:1, username:alice\"]\nhi\n[/quote]\n\
+                   @alice [quote=\"Bob B, post:4, topic:1, username:bob\"]x[/quote]";
+        assert_eq!(
+            pseudonyms.anonymize_raw(raw),
+            "[quote=\"user2, post:3, topic:1, username:user2\"]\nhi\n[/quote]\n\
+             @user2 [quote=\"user1, post:4, topic:1, username:user1\"]x[/quote]"
+        );
+    }
+}
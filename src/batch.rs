@@ -0,0 +1,239 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+
+/// Maximum number of topics scraped at once against a single host.
+///
+/// Each topic has at most `--post-concurrency` requests in flight, so this
+/// bounds the in-flight requests per domain regardless of
+/// `--topic-concurrency`; `--rate` bounds how often they start.
+pub const MAX_TOPICS_PER_DOMAIN: usize = 2;
+
+/// Scrape every URL with up to `concurrency` worker threads.
+///
+/// Returns each URL with its result, in input order.
+pub fn run<T, F>(urls: &[String], concurrency: usize, scrape: F) -> Vec<(String, Result<T>)>
+where
+    T: Send,
+    F: Fn(&str) -> Result<T> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let limiter = DomainLimiter::new(MAX_TOPICS_PER_DOMAIN);
+    let results = Mutex::new(Vec::new());
+
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.clamp(1, urls.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(url) = urls.get(i) else { break };
+                let _slot = limiter.acquire(&domain_of(url));
+                let result = scrape(url);
+                results.lock().unwrap().push((i, url.clone(), result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _, _)| *i);
+    results
+        .into_iter()
+        .map(|(_, url, result)| (url, result))
+        .collect()
+}
+
+/// Run `work` on every item with up to `concurrency` worker threads, handing the
+/// results to `consume` on the calling thread strictly in input order.
+///
+/// Results that complete early wait in a reorder buffer until every earlier one
+/// has been consumed, so each in-order prefix is processed as soon as it is
+/// complete. If `consume` fails, no further items are started and its error is
+/// returned.
+pub fn run_ordered<T, R, W, C>(
+    items: &[T],
+    concurrency: usize,
+    work: W,
+    mut consume: C,
+) -> Result<()>
+where
+    T: Sync,
+    R: Send,
+    W: Fn(usize, &T) -> R + Sync,
+    C: FnMut(usize, R) -> Result<()>,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.clamp(1, items.len().max(1)) {
+            let tx = tx.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            s.spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(i) else { break };
+                    if tx.send((i, work(i, item))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (i, result) in rx {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&expected) {
+                if let Err(e) = consume(expected, result) {
+                    stop.store(true, Ordering::SeqCst);
+                    return Err(e);
+                }
+                expected += 1;
+            }
+        }
+        Ok(())
+    })
+}
+
+fn domain_of(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Counting semaphore keyed by domain.
+struct DomainLimiter {
+    limit: usize,
+    in_flight: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+}
+
+impl DomainLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            in_flight: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until the domain has a free slot; the slot is held until the guard drops.
+    fn acquire(&self, domain: &str) -> DomainSlot<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while in_flight.get(domain).copied().unwrap_or(0) >= self.limit {
+            in_flight = self.released.wait(in_flight).unwrap();
+        }
+        *in_flight.entry(domain.to_string()).or_insert(0) += 1;
+        DomainSlot {
+            limiter: self,
+            domain: domain.to_string(),
+        }
+    }
+}
+
+struct DomainSlot<'a> {
+    limiter: &'a DomainLimiter,
+    domain: String,
+}
+
+impl Drop for DomainSlot<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.limiter.in_flight.lock().unwrap();
+        if let Some(n) = in_flight.get_mut(&self.domain) {
+            *n -= 1;
+        }
+        self.limiter.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn test_run_keeps_input_order() {
+        let urls: Vec<String> = (1..=6)
+            .map(|i| format!("https://discuss.example.com/t/{}", i))
+            .collect();
+        let results = run(&urls, 3, |url| {
+            if url.ends_with('2') || url.ends_with('5') {
+                bail!("boom");
+            }
+            Ok(())
+        });
+        let all: Vec<&str> = results.iter().map(|(u, _)| u.as_str()).collect();
+        assert_eq!(all, urls.iter().map(String::as_str).collect::<Vec<_>>());
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|(_, r)| r.is_err())
+            .map(|(u, _)| u.as_str())
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                "https://discuss.example.com/t/2",
+                "https://discuss.example.com/t/5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_ordered_consumes_in_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let mut seen = Vec::new();
+        run_ordered(
+            &items,
+            4,
+            |_, &n| {
+                // Later items finish first
+                std::thread::sleep(std::time::Duration::from_millis(20 - n));
+                n * 10
+            },
+            |i, result| {
+                seen.push((i, result));
+                Ok(())
+            },
+        )
+        .unwrap();
+        let expected: Vec<(usize, u64)> = (0..20).map(|n| (n as usize, n * 10)).collect();
+        assert_eq!(seen, expected);
+
+        let mut consumed = 0;
+        let result = run_ordered(
+            &items,
+            2,
+            |_, &n| n,
+            |_, n| {
+                consumed += 1;
+                if n == 3 {
+                    bail!("stop");
+                }
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(consumed, 4);
+    }
+
+    #[test]
+    fn test_run_caps_topics_per_domain() {
+        let urls: Vec<String> = (1..=8)
+            .map(|i| format!("https://discuss.example.com/t/{}", i))
+            .collect();
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let results = run(&urls, 8, |_| {
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            active.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        });
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(peak.load(Ordering::SeqCst) <= MAX_TOPICS_PER_DOMAIN);
+    }
+}
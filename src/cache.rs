@@ -1,6 +1,8 @@
+use crate::discourse::{Poll, Reaction};
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 /// A cached post with metadata and raw content.
 #[derive(Debug, Serialize, Deserialize)]
@@ -9,29 +11,261 @@ pub struct CachedPost {
     pub post_id: u64,
     pub username: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub reply_to_post_number: Option<u64>,
     pub raw: String,
     pub fetched_at: chrono::DateTime<chrono::Utc>,
+    /// Discourse's code for a moderator action post; see [`PostData::action_code`]
+    ///
+    /// [`PostData::action_code`]: crate::discourse::PostData::action_code
+    #[serde(default)]
+    pub action_code: Option<String>,
+    /// The HTML Discourse rendered the post to, for HTML output; missing from
+    /// posts cached before it was kept
+    #[serde(default)]
+    pub cooked: Option<String>,
+    /// Likes and reactions as of `fetched_at`
+    #[serde(default)]
+    pub like_count: u64,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    /// Poll results as of `fetched_at`
+    #[serde(default)]
+    pub polls: Vec<Poll>,
+    /// The author's standing on the forum when the post was fetched; see
+    /// [`PostData::role`]
+    ///
+    /// [`PostData::role`]: crate::discourse::PostData::role
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+/// A post by `username`, created and fetched at 2026-02-20 10:00 UTC, with ID
+/// `1000 + post_number`, for tests to adjust.
+#[cfg(test)]
+pub(crate) fn test_post(post_number: u64, username: &str, raw: &str) -> CachedPost {
+    let time = chrono::DateTime::parse_from_rfc3339("2026-02-20T10:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    CachedPost {
+        post_number,
+        post_id: 1000 + post_number,
+        username: username.to_string(),
+        created_at: time,
+        reply_to_post_number: None,
+        raw: raw.to_string(),
+        fetched_at: time,
+        action_code: None,
+        cooked: None,
+        like_count: 0,
+        reactions: Vec::new(),
+        polls: Vec::new(),
+        role: None,
+    }
+}
+
+/// Marker for a post that was found to be deleted (HTTP 404).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MissingPost {
+    pub post_number: u64,
+    pub checked_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Site-wide data shared by all topics of a forum.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedSite {
+    /// Category ID -> display name (`Parent / Child` for subcategories)
+    pub categories: HashMap<u64, String>,
+    pub fetched_at: chrono::DateTime<chrono::Utc>,
 }
 
 /// File-based cache for Discourse posts.
 ///
 /// Cache layout: `~/.cache/disc-scrape/{domain}/{topic_id}/{post_id}.json`
+/// (or `{post_id}.missing.json` for posts known to be deleted),
+/// with site-wide data (`site.json`, `topic-titles.json`) in `~/.cache/disc-scrape/{domain}/`.
 pub struct Cache {
     dir: PathBuf,
+    domain_dir: PathBuf,
+}
+
+/// A topic's directory in the cache, as found by [`list_topics`].
+#[derive(Debug)]
+pub struct CachedTopic {
+    pub domain: String,
+    pub topic_id: u64,
+    pub dir: PathBuf,
+    /// Newest `fetched_at` (or `checked_at` for missing markers) of its posts;
+    /// `None` if the directory holds no readable posts
+    pub last_fetched: Option<chrono::DateTime<chrono::Utc>>,
+    /// Total size of the files in the directory
+    pub bytes: u64,
+}
+
+/// Root of the cache, `~/.cache/disc-scrape`.
+fn cache_root() -> Result<PathBuf> {
+    let cache_base = directories::ProjectDirs::from("", "", "disc-scrape")
+        .context("Could not determine cache directory")?;
+    Ok(cache_base.cache_dir().to_path_buf())
+}
+
+/// List every cached topic, across all domains.
+pub fn list_topics() -> Result<Vec<CachedTopic>> {
+    list_topics_in(&cache_root()?)
+}
+
+fn list_topics_in(root: &Path) -> Result<Vec<CachedTopic>> {
+    let mut topics = Vec::new();
+    if !root.exists() {
+        return Ok(topics);
+    }
+    for domain in read_dir(root)? {
+        if !domain.is_dir() {
+            continue;
+        }
+        let domain_name = domain.file_name().unwrap_or_default().to_string_lossy();
+        for dir in read_dir(&domain)? {
+            let topic_id = dir
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u64>().ok());
+            let Some(topic_id) = topic_id.filter(|_| dir.is_dir()) else {
+                continue;
+            };
+            let mut last_fetched = None;
+            let mut bytes = 0;
+            for file in read_dir(&dir)? {
+                bytes += file.metadata().map(|m| m.len()).unwrap_or(0);
+                let fetched = if file.to_string_lossy().ends_with(".missing.json") {
+                    read_json::<MissingPost>(&file)
+                        .ok()
+                        .flatten()
+                        .map(|m| m.checked_at)
+                } else {
+                    read_json::<CachedPost>(&file)
+                        .ok()
+                        .flatten()
+                        .map(|p| p.fetched_at)
+                };
+                last_fetched = last_fetched.max(fetched);
+            }
+            topics.push(CachedTopic {
+                domain: domain_name.to_string(),
+                topic_id,
+                dir,
+                last_fetched,
+                bytes,
+            });
+        }
+    }
+    topics.sort_by(|a, b| (&a.domain, a.topic_id).cmp(&(&b.domain, b.topic_id)));
+    Ok(topics)
+}
+
+/// Changes made (or, in a dry run, needed) by [`migrate_topic`].
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// Post files renamed to `{post_id}.json`
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// Older copies of a post stored under another name, and missing markers
+    /// for posts that are cached
+    pub removed: Vec<PathBuf>,
+    /// Files that could not be parsed as posts; left untouched
+    pub unreadable: Vec<PathBuf>,
+}
+
+/// Make a topic's cache directory consistent: every post stored once, as
+/// `{post_id}.json` according to its own contents.
+///
+/// When several files hold the same post, the most recently fetched one is kept.
+pub fn migrate_topic(dir: &Path, dry_run: bool) -> Result<Migration> {
+    let mut migration = Migration::default();
+    let mut by_id: HashMap<u64, Vec<(PathBuf, CachedPost)>> = HashMap::new();
+    for path in read_dir(dir)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if !name.ends_with(".json") || name.ends_with(".missing.json") {
+            continue;
+        }
+        match read_json::<CachedPost>(&path) {
+            Ok(Some(post)) => by_id.entry(post.post_id).or_default().push((path, post)),
+            _ => migration.unreadable.push(path),
+        }
+    }
+
+    let mut ids: Vec<u64> = by_id.keys().copied().collect();
+    ids.sort();
+    for id in ids {
+        let expected = dir.join(format!("{}.json", id));
+        let mut copies = by_id.remove(&id).unwrap_or_default();
+        // Newest last; on a tie prefer the correctly named file
+        copies.sort_by_key(|(path, post)| (post.fetched_at, *path == expected));
+        let (keep, _) = copies.pop().expect("at least one copy per post ID");
+        for (path, _) in copies {
+            if !dry_run {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {:?}", path))?;
+            }
+            migration.removed.push(path);
+        }
+        if keep != expected {
+            migration.renamed.push((keep, expected));
+        }
+        let missing = dir.join(format!("{}.missing.json", id));
+        if missing.exists() {
+            if !dry_run {
+                std::fs::remove_file(&missing)
+                    .with_context(|| format!("Failed to remove {:?}", missing))?;
+            }
+            migration.removed.push(missing);
+        }
+    }
+
+    // Move every file aside before renaming any into place, since a target name
+    // may still hold another post that has yet to be moved (e.g. two swapped files)
+    if !dry_run {
+        let mut moved = Vec::new();
+        for (from, to) in &migration.renamed {
+            let aside = to.with_extension("migrating.json");
+            std::fs::rename(from, &aside)
+                .with_context(|| format!("Failed to rename {:?}", from))?;
+            moved.push((aside, to));
+        }
+        for (aside, to) in moved {
+            std::fs::rename(&aside, to).with_context(|| format!("Failed to rename {:?}", aside))?;
+        }
+    }
+    Ok(migration)
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        paths.push(
+            entry
+                .with_context(|| format!("Failed to read directory {:?}", dir))?
+                .path(),
+        );
+    }
+    Ok(paths)
 }
 
 impl Cache {
     /// Create a new cache for the given domain and topic.
     pub fn new(domain: &str, topic_id: u64) -> Result<Self> {
-        let cache_base = directories::ProjectDirs::from("", "", "disc-scrape")
-            .context("Could not determine cache directory")?;
-        let dir = cache_base
-            .cache_dir()
-            .join(domain)
-            .join(topic_id.to_string());
+        let domain_dir = cache_root()?.join(domain);
+        let dir = domain_dir.join(topic_id.to_string());
         std::fs::create_dir_all(&dir)
             .with_context(|| format!("Failed to create cache directory: {:?}", dir))?;
-        Ok(Self { dir })
+        Ok(Self { dir, domain_dir })
+    }
+
+    /// A cache kept in `dir`, for tests that must not touch the real one.
+    #[cfg(test)]
+    pub(crate) fn in_dir(dir: PathBuf) -> Self {
+        let domain_dir = dir.parent().unwrap_or(&dir).to_path_buf();
+        Self { dir, domain_dir }
     }
 
     /// Load a cached post by post ID, if it exists.
@@ -48,14 +282,188 @@ impl Cache {
     }
 
     /// Save a post to the cache (keyed by post_id).
+    ///
+    /// Clears any earlier missing marker, since the post evidently exists again.
     pub fn save(&self, post: &CachedPost) -> Result<()> {
         let path = self.post_path(post.post_id);
         let data = serde_json::to_string_pretty(post).context("Failed to serialize post")?;
         std::fs::write(&path, data).with_context(|| format!("Failed to write {:?}", path))?;
+        let missing_path = self.missing_path(post.post_id);
+        if missing_path.exists() {
+            std::fs::remove_file(&missing_path)
+                .with_context(|| format!("Failed to remove {:?}", missing_path))?;
+        }
         Ok(())
     }
 
+    /// Load the missing marker for a post, if it was recorded as deleted.
+    pub fn load_missing(&self, post_id: u64) -> Result<Option<MissingPost>> {
+        read_json(&self.missing_path(post_id))
+    }
+
+    /// Record that a post is missing (deleted), as of now.
+    pub fn save_missing(&self, post_id: u64, post_number: u64) -> Result<()> {
+        let marker = MissingPost {
+            post_number,
+            checked_at: chrono::Utc::now(),
+        };
+        write_json(&self.missing_path(post_id), &marker)
+    }
+
+    /// Load the cached site data for this domain, if it exists.
+    pub fn load_site(&self) -> Result<Option<CachedSite>> {
+        read_json(&self.domain_dir.join("site.json"))
+    }
+
+    /// Save the site data for this domain.
+    pub fn save_site(&self, site: &CachedSite) -> Result<()> {
+        write_json(&self.domain_dir.join("site.json"), site)
+    }
+
+    /// Load the cached topic ID -> title map for this domain.
+    pub fn load_topic_titles(&self) -> Result<HashMap<u64, String>> {
+        Ok(read_json(&self.domain_dir.join("topic-titles.json"))?.unwrap_or_default())
+    }
+
+    /// Save the topic ID -> title map for this domain.
+    pub fn save_topic_titles(&self, titles: &HashMap<u64, String>) -> Result<()> {
+        write_json(&self.domain_dir.join("topic-titles.json"), titles)
+    }
+
     fn post_path(&self, post_id: u64) -> PathBuf {
         self.dir.join(format!("{}.json", post_id))
     }
+
+    fn missing_path(&self, post_id: u64) -> PathBuf {
+        self.dir.join(format!("{}.missing.json", post_id))
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let value =
+        serde_json::from_str(&data).with_context(|| format!("Failed to parse {:?}", path))?;
+    Ok(Some(value))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let data = serde_json::to_string_pretty(value)
+        .with_context(|| format!("Failed to serialize {:?}", path))?;
+    std::fs::write(path, data).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_topics_in() {
+        let root = std::env::temp_dir().join(format!("disc-scrape-test-{}", std::process::id()));
+        let topic_dir = root.join("discuss.example.com").join("42");
+        std::fs::create_dir_all(&topic_dir).unwrap();
+        std::fs::create_dir_all(root.join("discuss.example.com").join("not-a-topic")).unwrap();
+        write_json(&root.join("discuss.example.com").join("site.json"), &1).unwrap();
+
+        let at = |s: &str| {
+            chrono::DateTime::parse_from_rfc3339(s)
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+        };
+        let post = CachedPost {
+            post_id: 100,
+            created_at: at("2026-01-01T00:00:00Z"),
+            fetched_at: at("2026-02-01T00:00:00Z"),
+            ..test_post(1, "alice", "Hi")
+        };
+        write_json(&topic_dir.join("100.json"), &post).unwrap();
+        let missing = MissingPost {
+            post_number: 2,
+            checked_at: at("2026-03-01T00:00:00Z"),
+        };
+        write_json(&topic_dir.join("101.missing.json"), &missing).unwrap();
+
+        let topics = list_topics_in(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].domain, "discuss.example.com");
+        assert_eq!(topics[0].topic_id, 42);
+        assert_eq!(topics[0].last_fetched, Some(at("2026-03-01T00:00:00Z")));
+        assert!(topics[0].bytes > 0);
+    }
+
+    #[test]
+    fn test_migrate_topic() {
+        let dir = std::env::temp_dir().join(format!("disc-scrape-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let post = |post_id: u64, fetched_at: &str| CachedPost {
+            post_id,
+            created_at: chrono::Utc::now(),
+            fetched_at: chrono::DateTime::parse_from_rfc3339(fetched_at)
+                .unwrap()
+                .with_timezone(&chrono::Utc),
+            ..test_post(post_id - 100, "alice", fetched_at)
+        };
+        write_json(&dir.join("1.json"), &post(101, "2026-01-01T00:00:00Z")).unwrap();
+        write_json(&dir.join("102.json"), &post(102, "2026-01-01T00:00:00Z")).unwrap();
+        write_json(&dir.join("old.json"), &post(102, "2025-01-01T00:00:00Z")).unwrap();
+        write_json(&dir.join("103.json"), &post(103, "2026-01-01T00:00:00Z")).unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        let marker = MissingPost {
+            post_number: 3,
+            checked_at: chrono::Utc::now(),
+        };
+        write_json(&dir.join("103.missing.json"), &marker).unwrap();
+
+        let dry = migrate_topic(&dir, true).unwrap();
+        assert!(dir.join("1.json").exists());
+        let migration = migrate_topic(&dir, false).unwrap();
+        let mut files: Vec<String> = read_dir(&dir)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        let kept: CachedPost = read_json(&dir.join("102.json")).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dry.renamed, migration.renamed);
+        assert_eq!(
+            migration.renamed,
+            vec![(dir.join("1.json"), dir.join("101.json"))]
+        );
+        assert_eq!(migration.removed.len(), 2);
+        assert_eq!(migration.unreadable, vec![dir.join("broken.json")]);
+        assert_eq!(files, ["101.json", "102.json", "103.json", "broken.json"]);
+        assert_eq!(kept.raw, "2026-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_migrate_topic_swapped() {
+        let dir = std::env::temp_dir().join(format!("disc-scrape-swap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let post = |post_id: u64| CachedPost {
+            post_id,
+            ..test_post(post_id - 100, "alice", &post_id.to_string())
+        };
+        write_json(&dir.join("101.json"), &post(102)).unwrap();
+        write_json(&dir.join("102.json"), &post(101)).unwrap();
+
+        let migration = migrate_topic(&dir, false).unwrap();
+        let first: CachedPost = read_json(&dir.join("101.json")).unwrap().unwrap();
+        let second: CachedPost = read_json(&dir.join("102.json")).unwrap().unwrap();
+        let files = read_dir(&dir).unwrap().len();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(migration.renamed.len(), 2);
+        assert!(migration.removed.is_empty());
+        assert_eq!(files, 2);
+        assert_eq!((first.post_id, first.raw.as_str()), (101, "101"));
+        assert_eq!((second.post_id, second.raw.as_str()), (102, "102"));
+    }
 }
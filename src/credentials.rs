@@ -0,0 +1,50 @@
+use anyhow::{bail, Context, Result};
+
+/// Keyring account under which the API key is stored when no username is given.
+const DEFAULT_ACCOUNT: &str = "api-key";
+
+fn entry(service: &str, username: Option<&str>) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(service, username.unwrap_or(DEFAULT_ACCOUNT))
+}
+
+/// Look up the API key stored under `service` in the OS keyring.
+///
+/// Returns `None` with a warning, rather than failing, when there is no keyring
+/// backend or no stored key, so the scrape can go ahead unauthenticated.
+pub fn load_api_key(service: &str, username: Option<&str>) -> Option<String> {
+    match entry(service, username).and_then(|e| e.get_password()) {
+        Ok(key) => Some(key),
+        Err(keyring::Error::NoEntry) => {
+            eprintln!(
+                "Warning: no API key stored for '{}' in the keyring (run `disc-scrape login {}`); continuing without one",
+                service, service
+            );
+            None
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: could not read API key from the keyring ({}); continuing without one",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Prompt for an API key and store it under `service` in the OS keyring.
+pub fn login(service: &str, username: Option<&str>) -> Result<()> {
+    let key = rpassword::prompt_password(format!("API key for {}: ", service))
+        .context("Failed to read API key")?;
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("No API key given");
+    }
+    entry(service, username)
+        .and_then(|e| e.set_password(key))
+        .context("Failed to store API key in the keyring")?;
+    eprintln!(
+        "Stored API key for '{}'; use it with --api-key-from-keyring {}",
+        service, service
+    );
+    Ok(())
+}
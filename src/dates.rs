@@ -0,0 +1,161 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+/// Parse a date filter bound: an ISO date (`2026-01-31`, midnight UTC), an
+/// RFC 3339 timestamp (`2026-01-31T12:00:00+02:00`), or a duration before `now`
+/// such as `12h`, `7d`, `2w`, `3mo` or `1y`.
+pub fn parse_bound(s: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+    let digits = s.chars().take_while(char::is_ascii_digit).count();
+    let (amount, unit) = s.split_at(digits);
+    // A number followed only by letters is meant as a duration
+    if digits > 0 && !unit.is_empty() && unit.chars().all(|c| c.is_ascii_alphabetic()) {
+        return parse_relative(amount, unit, now)
+            // clap shows only the outermost error, so keep the reason in one message
+            .map_err(|e| anyhow!("invalid duration `{}`: {}", s, e));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+            .and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .with_context(|| {
+            format!(
+                "invalid date `{}`: expected an ISO date like 2026-01-31, a timestamp like \
+                 2026-01-31T12:00:00Z, or a duration like 7d",
+                s
+            )
+        })
+}
+
+fn parse_relative(amount: &str, unit: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let n: u32 = amount.parse().context("number too large")?;
+    let time = match unit {
+        "h" => now.checked_sub_signed(chrono::Duration::hours(n.into())),
+        "d" => now.checked_sub_signed(chrono::Duration::days(n.into())),
+        "w" => now.checked_sub_signed(chrono::Duration::weeks(n.into())),
+        "mo" => now.checked_sub_months(Months::new(n)),
+        "y" => n
+            .checked_mul(12)
+            .and_then(|months| now.checked_sub_months(Months::new(months))),
+        _ => bail!("unknown unit `{}`; use h, d, w, mo or y", unit),
+    };
+    time.context("too far in the past")
+}
+
+/// The strftime pattern dates are written with by default, e.g.
+/// `2026-02-20 10:00 UTC`.
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M %Z";
+
+/// A time zone to show dates in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    /// A zone from the IANA database, such as `UTC` or `Europe/Paris`
+    Named(Tz),
+    /// The system's zone
+    Local,
+}
+
+/// Parse a time zone: `local`, or an IANA name such as `America/New_York`.
+pub fn parse_timezone(s: &str) -> Result<Timezone> {
+    if s.eq_ignore_ascii_case("local") {
+        return Ok(Timezone::Local);
+    }
+    s.parse().map(Timezone::Named).map_err(|_| {
+        anyhow!(
+            "unknown time zone `{}`; use an IANA name like Europe/Paris, or local",
+            s
+        )
+    })
+}
+
+/// Check a strftime pattern, which chrono would otherwise only reject when
+/// rendering.
+pub fn parse_date_format(s: &str) -> Result<String> {
+    if StrftimeItems::new(s).any(|item| item == Item::Error) {
+        bail!("invalid strftime pattern `{}`", s);
+    }
+    Ok(s.to_string())
+}
+
+/// How dates are written in documents: a strftime pattern, in a time zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateFormat {
+    pub timezone: Timezone,
+    pub pattern: String,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat {
+            timezone: Timezone::Named(Tz::UTC),
+            pattern: DEFAULT_DATE_FORMAT.to_string(),
+        }
+    }
+}
+
+impl DateFormat {
+    /// `time` in the configured zone and pattern.
+    pub fn format(&self, time: DateTime<Utc>) -> String {
+        self.format_as(time, &self.pattern)
+    }
+
+    /// `time` in the configured zone, but with `pattern`, for dates whose syntax
+    /// is fixed, such as Org timestamps.
+    pub fn format_as(&self, time: DateTime<Utc>, pattern: &str) -> String {
+        match self.timezone {
+            Timezone::Named(tz) => time.with_timezone(&tz).format(pattern).to_string(),
+            Timezone::Local => time.with_timezone(&Local).format(pattern).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bound() {
+        let now = DateTime::parse_from_rfc3339("2026-03-31T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let at = |s: &str| parse_bound(s, now).unwrap().to_rfc3339();
+
+        assert_eq!(at("7d"), "2026-03-24T10:00:00+00:00");
+        assert_eq!(at("2w"), "2026-03-17T10:00:00+00:00");
+        assert_eq!(at("12h"), "2026-03-30T22:00:00+00:00");
+        // Clamped to the end of February
+        assert_eq!(at("1mo"), "2026-02-28T10:00:00+00:00");
+        assert_eq!(at("1y"), "2025-03-31T10:00:00+00:00");
+        assert_eq!(at("2026-01-15"), "2026-01-15T00:00:00+00:00");
+        assert_eq!(at("2026-01-15T12:00:00+02:00"), "2026-01-15T10:00:00+00:00");
+
+        let err = |s: &str| parse_bound(s, now).unwrap_err().to_string();
+        assert!(err("3m").starts_with("invalid duration `3m`: unknown unit"));
+        assert!(err("2026-13-01").starts_with("invalid date `2026-13-01`"));
+        assert!(err("yesterday").starts_with("invalid date"));
+    }
+
+    #[test]
+    fn test_date_format() {
+        let time = DateTime::parse_from_rfc3339("2026-02-20T23:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(DateFormat::default().format(time), "2026-02-20 23:30 UTC");
+        let paris = DateFormat {
+            timezone: parse_timezone("Europe/Paris").unwrap(),
+            pattern: parse_date_format("%d %b %Y, %H:%M %Z").unwrap(),
+        };
+        assert_eq!(paris.format(time), "21 Feb 2026, 00:30 CET");
+        assert_eq!(paris.format_as(time, "%Y-%m-%d"), "2026-02-21");
+        assert_eq!(parse_timezone("LOCAL").unwrap(), Timezone::Local);
+        assert!(parse_timezone("Mars/Olympus").is_err());
+        assert!(parse_date_format("%Y-%Q").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::cache::CachedPost;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// How the posts of a scrape differ from a previously rendered document.
+#[derive(Debug, Default, PartialEq)]
+pub struct PostChanges {
+    pub new: Vec<u64>,
+    pub edited: Vec<u64>,
+    pub unchanged: Vec<u64>,
+    /// Post numbers present in the previous document but not in this scrape
+    pub removed: Vec<u64>,
+}
+
+impl PostChanges {
+    /// One-line summary, e.g. `3 new, 1 edited, 40 unchanged, 0 removed`.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} new, {} edited, {} unchanged, {} removed",
+            self.new.len(),
+            self.edited.len(),
+            self.unchanged.len(),
+            self.removed.len()
+        )
+    }
+}
+
+/// Extract post bodies from a document previously rendered by disc-scrape,
+/// keyed by post number.
+pub fn parse_rendered_posts(doc: &str) -> HashMap<u64, String> {
+    // With `--permalinks`, the `Post #N` label is a link
+    let header = Regex::new(r"(?m)^## \[?Post #(\d+)(?:\]\([^)\s]*\))? by @[^\n]*\n\n")
+        .expect("header regex is valid");
+    let matches: Vec<_> = header.captures_iter(doc).collect();
+
+    let mut posts = HashMap::new();
+    for (i, caps) in matches.iter().enumerate() {
+        let whole = caps.get(0).unwrap();
+        let end = matches
+            .get(i + 1)
+            .map(|next| next.get(0).unwrap().start())
+            .unwrap_or(doc.len());
+        let Ok(post_number) = caps[1].parse::<u64>() else {
+            continue;
+        };
+        posts.insert(
+            post_number,
+            strip_trailer(&doc[whole.end()..end]).to_string(),
+        );
+    }
+    posts
+}
+
+/// Remove the separator (and any disc-scrape markers) that follow a post body.
+fn strip_trailer(mut body: &str) -> &str {
+    body = body.trim_end();
+    if let Some(pos) = body.rfind('\n') {
+        if body[pos + 1..].starts_with("<!-- disc-scrape:post ") {
+            body = body[..pos].trim_end();
+        }
+    }
+    body = body.strip_suffix("---").unwrap_or(body).trim_end();
+    body = body
+        .strip_suffix("<!-- disc-scrape:end-post -->")
+        .unwrap_or(body)
+        .trim_end();
+    body
+}
+
+/// Classify `posts` against the bodies of a previous document.
+///
+/// `body` renders a post's body the same way it would appear in the output.
+pub fn compare(
+    previous: &HashMap<u64, String>,
+    posts: &[CachedPost],
+    body: impl Fn(&CachedPost) -> String,
+) -> PostChanges {
+    let mut changes = PostChanges::default();
+    for post in posts {
+        match previous.get(&post.post_number) {
+            None => changes.new.push(post.post_number),
+            Some(old) if old.trim_end() == body(post).trim_end() => {
+                changes.unchanged.push(post.post_number)
+            }
+            Some(_) => changes.edited.push(post.post_number),
+        }
+    }
+    let mut removed: Vec<u64> = previous
+        .keys()
+        .filter(|n| !posts.iter().any(|p| p.post_number == **n))
+        .copied()
+        .collect();
+    removed.sort_unstable();
+    changes.removed = removed;
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rendered_posts() {
+        let doc = "# Title\n\n- **Posts**: 2\n\n---\n\n\
+                   ## Post #1 by @alice (2026-02-20 10:00 UTC)\n\nHello\n\n---\n\nstill hello\n\n---\n\n\
+                   <!-- disc-scrape:post {\"post_number\":2} -->\n\
+                   ## Post #2 by @bob (2026-02-21 10:00 UTC) [context]\n\nWorld\n\
+                   <!-- disc-scrape:end-post -->\n\n---\n\n";
+        let posts = parse_rendered_posts(doc);
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[&1], "Hello\n\n---\n\nstill hello");
+        assert_eq!(posts[&2], "World");
+
+        let doc =
+            "## [Post #3](https://x.test/t/t/1/3) by @carol (2026-02-22 10:00 UTC)\n\nLinked\n";
+        assert_eq!(parse_rendered_posts(doc)[&3], "Linked");
+    }
+}
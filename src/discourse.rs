@@ -1,13 +1,262 @@
+use crate::http_cache::HttpCache;
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, LOCATION, USER_AGENT};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Default cap on requests per second to each forum, across all workers. It
+/// stays under Discourse's default per-IP limits below, so concurrent fetching
+/// is safe by default.
+pub const DEFAULT_RATE: f64 = 3.0;
+
+/// Default number of raw posts fetched at once; the rate limit, not the
+/// workers, sets the pace.
+pub const DEFAULT_POST_CONCURRENCY: usize = 4;
+
+/// Number of post IDs requested per `/t/{topic_id}/posts.json` batch.
+pub const BATCH_SIZE: usize = 20;
+
+/// Discourse's default per-IP limit over a 10 second window
+/// (`DISCOURSE_MAX_REQS_PER_IP_PER_10_SECONDS`).
+const MAX_REQS_PER_10_SECONDS: u64 = 50;
+
+/// Discourse's default per-IP limit over a one minute window
+/// (`DISCOURSE_MAX_REQS_PER_IP_PER_MINUTE`).
+const MAX_REQS_PER_MINUTE: u64 = 200;
+
+/// The shared HTTP client and the default headers it sends with every request.
+static CLIENT: OnceLock<(reqwest::blocking::Client, HeaderMap)> = OnceLock::new();
+
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+static HTTP_CACHE: OnceLock<HttpCache> = OnceLock::new();
+
+/// Keep HTTP responses in `dir` and serve requests from there while the server
+/// allows; see [`HttpCache`]. Must be called before the first request.
+pub fn set_http_cache(dir: &Path) -> Result<()> {
+    if HTTP_CACHE.set(HttpCache::new(dir)?).is_err() {
+        bail!("HTTP cache already set");
+    }
+    Ok(())
+}
+
+/// Cap requests to each host at `per_second`, across all threads.
+/// Must be called before the first request.
+pub fn set_rate_limit(per_second: f64) -> Result<()> {
+    if RATE_LIMITER.set(RateLimiter::new(per_second)).is_err() {
+        bail!("Rate limit already set");
+    }
+    Ok(())
+}
+
+/// Spaces out requests to each host so that at most `per_second` start in any
+/// second: a token bucket holding a single token, refilled every `1/per_second`.
+struct RateLimiter {
+    interval: Duration,
+    /// Earliest time the next request to each host may start
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            next_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Block until a request to `url`'s host may start.
+    fn wait(&self, url: &str) {
+        let host = url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slot.get(&host).copied().unwrap_or(now).max(now);
+            next_slot.insert(host, slot + self.interval);
+            slot
+        };
+        std::thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Discourse API credentials, sent as the `Api-Key` and `Api-Username` headers.
+pub struct ApiKey {
+    pub key: String,
+    /// Required for all-users keys; single-user keys may omit it
+    pub username: Option<String>,
+}
+
+/// A custom header given as `Key: Value`.
+pub type CustomHeader = (HeaderName, HeaderValue);
+
+/// Parse a `Key: Value` header specification.
+///
+/// The value is marked sensitive, since custom headers usually carry tokens,
+/// so it is redacted when requests are traced.
+pub fn parse_header(spec: &str) -> Result<CustomHeader> {
+    let (name, value) = spec
+        .split_once(':')
+        .with_context(|| format!("Expected `Key: Value`, got `{}`", spec))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("Invalid header name `{}`", name.trim()))?;
+    let mut value = HeaderValue::from_str(value.trim())
+        .with_context(|| format!("Invalid value for header `{}`", name))?;
+    value.set_sensitive(true);
+    Ok((name, value))
+}
+
+/// Set up the client shared by all requests. Must be called before the first request.
+///
+/// Every request identifies itself with a `disc-scrape` `User-Agent` and is
+/// authenticated with `api_key`, if given. `custom_headers` are added on top and
+/// replace any of these (or the `Accept` header of JSON requests) they name.
+pub fn init_client(api_key: Option<&ApiKey>, custom_headers: &[CustomHeader]) -> Result<()> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_static(concat!("disc-scrape/", env!("CARGO_PKG_VERSION"))),
+    );
+    if let Some(api_key) = api_key {
+        let mut key =
+            HeaderValue::from_str(&api_key.key).context("API key is not a valid header value")?;
+        key.set_sensitive(true);
+        headers.insert("Api-Key", key);
+        if let Some(username) = &api_key.username {
+            let username = HeaderValue::from_str(username)
+                .context("API username is not a valid header value")?;
+            headers.insert("Api-Username", username);
+        }
+    }
+    for (name, value) in custom_headers {
+        headers.insert(name.clone(), value.clone());
+    }
+    let client = reqwest::blocking::Client::builder()
+        .default_headers(headers.clone())
+        .build()
+        .context("Failed to build HTTP client")?;
+    if CLIENT.set((client, headers)).is_err() {
+        bail!("HTTP client already in use");
+    }
+    Ok(())
+}
+
+fn shared() -> &'static (reqwest::blocking::Client, HeaderMap) {
+    CLIENT.get_or_init(|| (reqwest::blocking::Client::new(), HeaderMap::new()))
+}
+
+/// The HTTP client shared by all requests.
+fn client() -> &'static reqwest::blocking::Client {
+    &shared().0
+}
+
+/// A GET request.
+fn get(url: &str) -> reqwest::blocking::RequestBuilder {
+    client().get(url)
+}
+
+/// Wait until the rate limit allows another request to `url`'s host.
+fn wait_for_rate_limit(url: &str) {
+    if let Some(limiter) = RATE_LIMITER.get() {
+        limiter.wait(url);
+    }
+}
+
+/// Send a request through the HTTP cache, if one is set, once the rate limit
+/// allows. Responses served from the cache don't count against the limit.
+fn send(request: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response> {
+    let request = request.build().context("Failed to build request")?;
+    match HTTP_CACHE.get() {
+        Some(cache) => cache.send(client(), request, &shared().1, wait_for_rate_limit),
+        None => {
+            wait_for_rate_limit(request.url().as_str());
+            Ok(client().execute(request)?)
+        }
+    }
+}
+
+/// A GET request for a JSON endpoint.
+fn get_json(url: &str) -> reqwest::blocking::RequestBuilder {
+    let request = get(url);
+    if shared().1.contains_key(ACCEPT) {
+        request
+    } else {
+        request.header(ACCEPT, "application/json")
+    }
+}
 
 /// Parsed topic metadata from Discourse JSON API
 #[derive(Debug, Deserialize)]
 pub struct Topic {
+    /// Canonical topic ID; differs from the requested one after a merge or redirect
+    pub id: u64,
     pub title: String,
+    /// URL slug of the topic title
+    #[serde(default)]
+    pub slug: String,
+    #[serde(default)]
+    pub category_id: Option<u64>,
+    /// Kind of topic: `regular` for discussions, or e.g. `banner` or `private_message`
+    #[serde(default = "regular_archetype")]
+    pub archetype: String,
+    /// The reply accepted as the solution, on forums using the solved plugin
+    #[serde(default)]
+    pub accepted_answer: Option<AcceptedAnswer>,
+    /// The topic's tags, on forums with tagging enabled
+    #[serde(default)]
+    pub tags: Vec<Tag>,
     pub post_stream: PostStream,
 }
 
+/// A topic tag: a bare name, or an object with one on newer Discourse versions.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Tag {
+    Name(String),
+    Detailed { name: String },
+}
+
+impl Tag {
+    pub fn name(&self) -> &str {
+        match self {
+            Tag::Name(name) | Tag::Detailed { name } => name,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptedAnswer {
+    pub post_number: u64,
+}
+
+fn regular_archetype() -> String {
+    "regular".to_string()
+}
+
+impl Topic {
+    /// A warning for topics that aren't ordinary discussions, whose output may be
+    /// limited or surprising.
+    pub fn archetype_warning(&self) -> Option<String> {
+        let what = match self.archetype.as_str() {
+            "regular" => return None,
+            "banner" => {
+                "a banner topic (pinned site-wide, usually with replies closed)".to_string()
+            }
+            "private_message" => "a private message, visible only to its participants".to_string(),
+            other => format!("a '{}' topic", other),
+        };
+        Some(format!(
+            "'{}' is {} rather than a regular discussion; the output may be limited",
+            self.title, what
+        ))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PostStream {
     /// Full list of post IDs in the topic
@@ -24,6 +273,98 @@ pub struct PostData {
     pub post_number: u64,
     pub username: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Post number this post replies to, if any
+    #[serde(default)]
+    pub reply_to_post_number: Option<u64>,
+    /// Raw Markdown, present when requested with `include_raw=1`
+    #[serde(default)]
+    pub raw: Option<String>,
+    /// Rendered HTML
+    #[serde(default)]
+    pub cooked: Option<String>,
+    /// Set on small-action posts Discourse adds for moderator actions, e.g.
+    /// `split_topic` when posts were moved to another topic
+    #[serde(default)]
+    pub action_code: Option<String>,
+    /// Counts of the actions taken on the post; likes have ID 2
+    #[serde(default)]
+    pub actions_summary: Vec<ActionSummary>,
+    /// Emoji reactions, from the discourse-reactions plugin when it's active
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    /// The post's polls and their results, which `/raw` leaves out
+    #[serde(default)]
+    pub polls: Vec<Poll>,
+    /// Whether the author is one of the forum's admins, moderators or staff
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub moderator: bool,
+    #[serde(default)]
+    pub staff: bool,
+}
+
+impl PostData {
+    /// How many times the post was liked.
+    pub fn like_count(&self) -> u64 {
+        const LIKE: u64 = 2;
+        self.actions_summary
+            .iter()
+            .find(|action| action.id == LIKE)
+            .map_or(0, |action| action.count)
+    }
+
+    /// `admin`, `moderator` or `staff` for posts by the forum's staff, most
+    /// specific first, or `None` for posts by other users.
+    pub fn role(&self) -> Option<&'static str> {
+        if self.admin {
+            Some("admin")
+        } else if self.moderator {
+            Some("moderator")
+        } else if self.staff {
+            Some("staff")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ActionSummary {
+    pub id: u64,
+    #[serde(default)]
+    pub count: u64,
+}
+
+/// A poll in a post, as the poll plugin reports it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Poll {
+    /// Matches the `name` attribute of the `[poll]` tag, `poll` by default
+    pub name: String,
+    /// `open` or `closed`
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub voters: u64,
+    #[serde(default)]
+    pub options: Vec<PollOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PollOption {
+    /// The option as rendered HTML
+    pub html: String,
+    /// Missing while the poll hides its results
+    #[serde(default)]
+    pub votes: Option<u64>,
+}
+
+/// One emoji's reactions to a post.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    /// The emoji's name, e.g. `heart`
+    pub id: String,
+    pub count: u64,
 }
 
 /// Parse a Discourse topic URL into (base_url, topic_id).
@@ -57,15 +398,23 @@ pub fn parse_topic_url(url_str: &str) -> Result<(String, u64)> {
     Ok((base_url, topic_id))
 }
 
+/// Canonical URL of a topic, e.g. `https://discuss.example.com/t/topic-slug/12345`.
+///
+/// Individual posts live at `{topic_url}/{post_number}`.
+pub fn topic_url(base_url: &str, slug: &str, topic_id: u64) -> String {
+    if slug.is_empty() {
+        format!("{}/t/{}", base_url, topic_id)
+    } else {
+        format!("{}/t/{}/{}", base_url, slug, topic_id)
+    }
+}
+
 /// Fetch topic metadata including the full post stream.
+///
+/// Inline posts carry their raw Markdown where the forum supports `include_raw`.
 pub fn fetch_topic(base_url: &str, topic_id: u64) -> Result<Topic> {
-    let url = format!("{}/t/{}.json", base_url, topic_id);
-    let client = reqwest::blocking::Client::new();
-    let resp = client
-        .get(&url)
-        .header("Accept", "application/json")
-        .send()
-        .context("HTTP request failed")?;
+    let url = format!("{}/t/{}.json?include_raw=1", base_url, topic_id);
+    let resp = send(get_json(&url)).context("HTTP request failed")?;
 
     if !resp.status().is_success() {
         bail!("Failed to fetch topic {}: HTTP {}", topic_id, resp.status());
@@ -75,33 +424,128 @@ pub fn fetch_topic(base_url: &str, topic_id: u64) -> Result<Topic> {
     Ok(topic)
 }
 
+/// Fetch topic metadata positioned at a specific post via `/t/{topic_id}/{post_number}.json`.
+///
+/// Discourse centres the inline `posts` on the requested post number, so the
+/// metadata for that post is available without batch-fetching the stream.
+pub fn fetch_topic_at_post(base_url: &str, topic_id: u64, post_number: u64) -> Result<Topic> {
+    let url = format!(
+        "{}/t/{}/{}.json?include_raw=1",
+        base_url, topic_id, post_number
+    );
+    let resp = send(get_json(&url)).context("HTTP request failed")?;
+
+    if !resp.status().is_success() {
+        bail!(
+            "Failed to fetch topic {} at post #{}: HTTP {}",
+            topic_id,
+            post_number,
+            resp.status()
+        );
+    }
+
+    let topic: Topic = resp.json().context("Failed to parse topic JSON")?;
+    Ok(topic)
+}
+
+/// Fetch the metadata for a single post by its post number.
+///
+/// Returns the topic (for its title) alongside the post's metadata.
+pub fn fetch_post_by_number(
+    base_url: &str,
+    topic_id: u64,
+    post_number: u64,
+) -> Result<(Topic, PostData)> {
+    let topic = fetch_topic_at_post(base_url, topic_id, post_number)?;
+    let post = topic
+        .post_stream
+        .posts
+        .iter()
+        .find(|p| p.post_number == post_number)
+        .cloned()
+        .with_context(|| format!("Post #{} not found in topic {}", post_number, topic_id))?;
+    Ok((topic, post))
+}
+
+#[derive(Debug, Deserialize)]
+struct Site {
+    categories: Vec<SiteCategory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SiteCategory {
+    id: u64,
+    name: String,
+    #[serde(default)]
+    parent_category_id: Option<u64>,
+}
+
+/// Fetch the site's categories via `/site.json`, as a map of category ID to name.
+///
+/// Subcategories are named `Parent / Child`.
+pub fn fetch_site_categories(base_url: &str) -> Result<HashMap<u64, String>> {
+    let url = format!("{}/site.json", base_url);
+    let resp = send(get_json(&url)).context("HTTP request failed")?;
+
+    if !resp.status().is_success() {
+        bail!("Failed to fetch site info: HTTP {}", resp.status());
+    }
+
+    let site: Site = resp.json().context("Failed to parse site JSON")?;
+    let names: HashMap<u64, &str> = site
+        .categories
+        .iter()
+        .map(|c| (c.id, c.name.as_str()))
+        .collect();
+    let categories = site
+        .categories
+        .iter()
+        .map(|c| {
+            let name = match c.parent_category_id.and_then(|p| names.get(&p)) {
+                Some(parent) => format!("{} / {}", parent, c.name),
+                None => c.name.clone(),
+            };
+            (c.id, name)
+        })
+        .collect();
+    Ok(categories)
+}
+
+/// Fetch the forum's `/robots.txt`, or `None` if it has none.
+pub fn fetch_robots_txt(base_url: &str) -> Result<Option<String>> {
+    let url = format!("{}/robots.txt", base_url);
+    let resp = send(get(&url)).context("HTTP request failed")?;
+
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !resp.status().is_success() {
+        bail!("Failed to fetch robots.txt: HTTP {}", resp.status());
+    }
+
+    let text = resp.text().context("Failed to read response body")?;
+    Ok(Some(text))
+}
+
 /// Batch-fetch post metadata for a set of post IDs.
 ///
-/// Uses `/t/{topic_id}/posts.json?post_ids[]=...` endpoint.
-/// Discourse typically allows ~20 IDs per request.
+/// Uses `/t/{topic_id}/posts.json?include_raw=1&post_ids[]=...` endpoint.
+/// Discourse typically allows ~20 IDs per request; `batch_delay` is slept between batches.
 pub fn fetch_posts_by_ids(
     base_url: &str,
     topic_id: u64,
     post_ids: &[u64],
+    batch_delay: Duration,
 ) -> Result<Vec<PostData>> {
-    let client = reqwest::blocking::Client::new();
-
     let mut all_posts = Vec::new();
 
-    // Batch in chunks of 20
-    for chunk in post_ids.chunks(20) {
-        let mut url = format!("{}/t/{}/posts.json?", base_url, topic_id);
-        for (i, id) in chunk.iter().enumerate() {
-            if i > 0 {
-                url.push('&');
-            }
-            url.push_str(&format!("post_ids[]={}", id));
+    for chunk in post_ids.chunks(BATCH_SIZE) {
+        let mut url = format!("{}/t/{}/posts.json?include_raw=1", base_url, topic_id);
+        for id in chunk {
+            url.push_str(&format!("&post_ids[]={}", id));
         }
 
-        let resp = client
-            .get(&url)
-            .header("Accept", "application/json")
-            .send()
+        let resp = send(get_json(&url))
             .with_context(|| "HTTP request failed for batch post fetch".to_string())?;
 
         if !resp.status().is_success() {
@@ -121,8 +565,8 @@ pub fn fetch_posts_by_ids(
         }
 
         // Small delay between batches
-        if post_ids.len() > 20 {
-            std::thread::sleep(std::time::Duration::from_millis(200));
+        if post_ids.len() > BATCH_SIZE {
+            std::thread::sleep(batch_delay);
         }
     }
 
@@ -132,21 +576,333 @@ pub fn fetch_posts_by_ids(
 /// Fetch the raw Markdown content for a post via /raw/{topic_id}/{post_number}.
 pub fn fetch_raw_post(base_url: &str, topic_id: u64, post_number: u64) -> Result<String> {
     let url = format!("{}/raw/{}/{}", base_url, topic_id, post_number);
-    let client = reqwest::blocking::Client::new();
-    let resp = client.get(&url).send().context("HTTP request failed")?;
+    let resp = send(get(&url)).context("HTTP request failed")?;
 
     if !resp.status().is_success() {
-        bail!(
-            "Failed to fetch raw post #{}: HTTP {}",
-            post_number,
-            resp.status()
-        );
+        return Err(HttpError {
+            what: format!("raw post #{}", post_number),
+            status: resp.status(),
+        }
+        .into());
+    }
+
+    let text = resp.text().context("Failed to read response body")?;
+    Ok(text)
+}
+
+/// Posts per page of `/raw/{topic_id}`, which Discourse renders like the print view.
+pub const RAW_TOPIC_PAGE_SIZE: usize = 1000;
+
+/// Separator Discourse puts after each post in `/raw/{topic_id}`.
+const RAW_TOPIC_SEPARATOR: &str = "\n\n-------------------------\n\n";
+
+/// Fetch the raw Markdown of a whole page of posts via `/raw/{topic_id}?page=N`,
+/// as `(post_number, raw)` pairs.
+pub fn fetch_topic_raw_page(
+    base_url: &str,
+    topic_id: u64,
+    page: usize,
+) -> Result<Vec<(u64, String)>> {
+    let url = format!("{}/raw/{}?page={}", base_url, topic_id, page);
+    let resp = send(get(&url)).context("HTTP request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError {
+            what: format!("raw topic page {}", page),
+            status: resp.status(),
+        }
+        .into());
+    }
+
+    let text = resp.text().context("Failed to read response body")?;
+    Ok(parse_topic_raw(&text))
+}
+
+/// Split a `/raw/{topic_id}` document into `(post_number, raw)` pairs.
+///
+/// Each post is rendered as `username | updated_at | #N`, a blank line, the raw
+/// body and a `-------------------------` separator. A separator followed by
+/// anything other than a post header belongs to the body.
+fn parse_topic_raw(text: &str) -> Vec<(u64, String)> {
+    let header = regex::Regex::new(r"\A[^\n|]+ \| [^\n|]+ \| #(\d+)\n\n")
+        .expect("raw topic header regex is valid");
+    let mut posts: Vec<(u64, String)> = Vec::new();
+    for piece in text.split(RAW_TOPIC_SEPARATOR) {
+        let post_number = header
+            .captures(piece)
+            .and_then(|caps| caps[1].parse::<u64>().ok());
+        match (post_number, posts.last_mut()) {
+            (Some(n), _) => {
+                let body_start = header.find(piece).map_or(0, |m| m.end());
+                posts.push((n, piece[body_start..].to_string()));
+            }
+            (None, Some((_, raw))) if !piece.is_empty() => {
+                raw.push_str(RAW_TOPIC_SEPARATOR);
+                raw.push_str(piece);
+            }
+            _ => {}
+        }
+    }
+    posts
+}
+
+/// Fetch the raw Markdown for a post like [`fetch_raw_post`], printing the request
+/// and the full response (status, headers and body) to stderr.
+///
+/// Sensitive headers such as `Api-Key` are redacted. Headers added while sending
+/// (such as `Host`) are not shown.
+pub fn trace_raw_post(base_url: &str, topic_id: u64, post_number: u64) -> Result<String> {
+    let url = format!("{}/raw/{}/{}", base_url, topic_id, post_number);
+    let client = client();
+    let request = get(&url).build().context("Failed to build request")?;
+
+    eprintln!("> {} {}", request.method(), request.url());
+    let defaults = shared()
+        .1
+        .iter()
+        .filter(|(name, _)| !request.headers().contains_key(*name));
+    for (name, value) in request.headers().iter().chain(defaults) {
+        let value = if value.is_sensitive() {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        eprintln!("> {}: {}", name, value);
     }
 
+    wait_for_rate_limit(&url);
+    let resp = client.execute(request).context("HTTP request failed")?;
+    let status = resp.status();
+    eprintln!("< {:?} {}", resp.version(), status);
+    for (name, value) in resp.headers() {
+        eprintln!("< {}: {}", name, value.to_str().unwrap_or("<binary>"));
+    }
     let text = resp.text().context("Failed to read response body")?;
+    eprintln!("<");
+    eprintln!("{}", text);
+
+    if !status.is_success() {
+        return Err(HttpError {
+            what: format!("raw post #{}", post_number),
+            status,
+        }
+        .into());
+    }
     Ok(text)
 }
 
+/// Fetch the raw Markdown content for a post from the `raw` field of `/posts/{post_id}.json`.
+pub fn fetch_post_json_raw(base_url: &str, post_id: u64) -> Result<String> {
+    let url = format!("{}/posts/{}.json", base_url, post_id);
+    let resp = send(get_json(&url)).context("HTTP request failed")?;
+
+    if !resp.status().is_success() {
+        return Err(HttpError {
+            what: format!("post id={}", post_id),
+            status: resp.status(),
+        }
+        .into());
+    }
+
+    let body: serde_json::Value = resp.json().context("Failed to parse post JSON")?;
+    let raw = body["raw"].as_str().context("No raw field in post JSON")?;
+    Ok(raw.to_string())
+}
+
+/// Redirects followed when downloading a file.
+const MAX_REDIRECTS: usize = 10;
+
+/// Download a file, such as an upload a post links to.
+///
+/// Only requests to `base_url`'s origin carry the API key and custom headers;
+/// files elsewhere, such as on a CDN an upload redirects to, are fetched
+/// anonymously.
+pub fn fetch_file(base_url: &str, url: &str) -> Result<Vec<u8>> {
+    let origin = url::Url::parse(base_url)
+        .context("Invalid base URL")?
+        .origin();
+    let mut url = url::Url::parse(url).context("Invalid URL")?;
+    for _ in 0..=MAX_REDIRECTS {
+        let (authenticated, anonymous) = file_clients();
+        let client = if url.origin() == origin {
+            authenticated
+        } else {
+            anonymous
+        };
+        wait_for_rate_limit(url.as_str());
+        let resp = client
+            .get(url.clone())
+            .send()
+            .context("HTTP request failed")?;
+        if resp.status().is_redirection() {
+            let location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .context("Redirect without a location")?;
+            url = url.join(location).context("Invalid redirect location")?;
+            continue;
+        }
+        if !resp.status().is_success() {
+            return Err(HttpError {
+                what: url.to_string(),
+                status: resp.status(),
+            }
+            .into());
+        }
+        return Ok(resp
+            .bytes()
+            .context("Failed to read response body")?
+            .to_vec());
+    }
+    bail!("Too many redirects fetching {}", url)
+}
+
+/// Clients for [`fetch_file`] that leave redirects to it: one sending the
+/// default headers, one sending only the `User-Agent`.
+fn file_clients() -> &'static (reqwest::blocking::Client, reqwest::blocking::Client) {
+    static FILE_CLIENTS: OnceLock<(reqwest::blocking::Client, reqwest::blocking::Client)> =
+        OnceLock::new();
+    FILE_CLIENTS.get_or_init(|| {
+        let headers = &shared().1;
+        let mut anonymous = HeaderMap::new();
+        if let Some(agent) = headers.get(USER_AGENT) {
+            anonymous.insert(USER_AGENT, agent.clone());
+        }
+        let build = |headers: HeaderMap| {
+            reqwest::blocking::Client::builder()
+                .default_headers(headers)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("file clients only differ from the shared one in redirects")
+        };
+        (build(headers.clone()), build(anonymous))
+    })
+}
+
+/// A request that completed with a non-success HTTP status.
+#[derive(Debug)]
+pub struct HttpError {
+    /// What was being fetched, e.g. `raw post #3`
+    pub what: String,
+    pub status: reqwest::StatusCode,
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to fetch {}: HTTP {}", self.what, self.status)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Whether an error was ultimately caused by an HTTP 404.
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<HttpError>()
+            .is_some_and(|http| http.status == reqwest::StatusCode::NOT_FOUND)
+    })
+}
+
+/// Number of leading `/raw/` failures with the same status after which the
+/// endpoint is assumed to be disabled for the whole forum.
+const RAW_PROBE_FAILURES: usize = 3;
+
+/// Fetches raw post content, switching to the `/posts/{id}.json` fallback for the
+/// rest of the run when the forum has `/raw/` disabled.
+///
+/// May be shared by threads fetching posts concurrently.
+pub struct RawFetcher<'a> {
+    base_url: &'a str,
+    topic_id: u64,
+    probe: Mutex<RawProbe>,
+}
+
+/// What has been learned about the `/raw/` endpoint so far.
+#[derive(Default)]
+struct RawProbe {
+    raw_worked: bool,
+    leading_failures: Vec<reqwest::StatusCode>,
+    raw_disabled: bool,
+}
+
+impl<'a> RawFetcher<'a> {
+    pub fn new(base_url: &'a str, topic_id: u64) -> Self {
+        Self {
+            base_url,
+            topic_id,
+            probe: Mutex::new(RawProbe::default()),
+        }
+    }
+
+    /// Never use `/raw/`; always fetch via `/posts/{id}.json`.
+    pub fn skip_raw_endpoint(&mut self) {
+        self.probe.get_mut().unwrap().raw_disabled = true;
+    }
+
+    /// Fetch the raw Markdown for a post.
+    ///
+    /// A `/raw/` 403 or 404 is retried via `/posts/{id}.json`. If the first few
+    /// `/raw/` requests all fail with the same status, `/raw/` is skipped from then on.
+    pub fn fetch(&self, post: &PostData) -> Result<String> {
+        if self.probe.lock().unwrap().raw_disabled {
+            return fetch_post_json_raw(self.base_url, post.id);
+        }
+
+        let err = match fetch_raw_post(self.base_url, self.topic_id, post.post_number) {
+            Ok(raw) => {
+                self.probe.lock().unwrap().raw_worked = true;
+                return Ok(raw);
+            }
+            Err(err) => err,
+        };
+        let status = match err.downcast_ref::<HttpError>() {
+            Some(http) if matches!(http.status.as_u16(), 403 | 404) => http.status,
+            _ => return Err(err),
+        };
+
+        let mut probe = self.probe.lock().unwrap();
+        if !probe.raw_worked && !probe.raw_disabled {
+            probe.leading_failures.push(status);
+            if probe.leading_failures.len() >= RAW_PROBE_FAILURES
+                && probe.leading_failures.iter().all(|s| *s == status)
+            {
+                probe.raw_disabled = true;
+                eprintln!(
+                    "Notice: /raw/ looks disabled on this forum (HTTP {}); using /posts/{{id}}.json instead",
+                    status
+                );
+            }
+        }
+        drop(probe);
+
+        fetch_post_json_raw(self.base_url, post.id).with_context(|| format!("{:#}", err))
+    }
+}
+
+/// Check whether `requests` requests, issued by `parallel` workers each waiting
+/// `delay` between requests, are likely to trip Discourse's default per-IP rate
+/// limits. Returns a description of the expected burst if so.
+///
+/// Request latency is ignored, so this errs on the side of warning.
+pub fn rate_limit_risk(requests: u64, delay: Duration, parallel: u64) -> Option<String> {
+    let per_second = parallel as f64 / delay.as_secs_f64().max(0.001);
+    let windows = [
+        (10.0, MAX_REQS_PER_10_SECONDS, "10 seconds"),
+        (60.0, MAX_REQS_PER_MINUTE, "minute"),
+    ];
+    windows.iter().find_map(|&(secs, limit, label)| {
+        let burst = requests.min((per_second * secs) as u64);
+        (burst > limit).then(|| {
+            format!(
+                "~{} requests per {} (Discourse allows {} per IP by default)",
+                burst, label, limit
+            )
+        })
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,4 +933,127 @@ mod tests {
     fn test_parse_topic_url_invalid() {
         assert!(parse_topic_url("https://example.com/not-discourse").is_err());
     }
+
+    #[test]
+    fn test_parse_topic_raw() {
+        let text = "alice | 2026-02-20 10:00:00 UTC | #1\n\nHello\n\n-------------------------\n\n\
+                    bob | 2026-02-21 10:00:00 UTC | #3\n\nAbove\n\n-------------------------\n\nBelow\
+                    \n\n-------------------------\n\n";
+        assert_eq!(
+            parse_topic_raw(text),
+            vec![
+                (1, "Hello".to_string()),
+                (3, "Above\n\n-------------------------\n\nBelow".to_string())
+            ]
+        );
+        assert!(parse_topic_raw("<html>Not found</html>").is_empty());
+    }
+
+    #[test]
+    fn test_rate_limiter_spaces_requests_per_host() {
+        let limiter = RateLimiter::new(50.0);
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    for _ in 0..2 {
+                        limiter.wait("https://a.example.com/t/1.json");
+                    }
+                });
+            }
+        });
+        // Six requests at 50/s: the last one starts 5 intervals (100ms) in
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let start = Instant::now();
+        limiter.wait("https://b.example.com/t/1.json");
+        assert!(start.elapsed() < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let (name, value) = parse_header("X-Bypass-Token:  abc: def ").unwrap();
+        assert_eq!(name, "x-bypass-token");
+        assert_eq!(value, "abc: def");
+        assert!(value.is_sensitive());
+        let (_, value) = parse_header("Authorization: Bearer secret").unwrap();
+        assert!(value.is_sensitive());
+        assert!(parse_header("X-Missing-Colon").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+        assert!(parse_header("X-Newline: a\nb").is_err());
+    }
+
+    #[test]
+    fn test_post_like_count() {
+        let post: PostData = serde_json::from_str(
+            r#"{"id": 1, "post_number": 1, "username": "a", "created_at": "2026-02-20T10:00:00Z",
+                "actions_summary": [{"id": 3, "can_act": true}, {"id": 2, "count": 4}],
+                "reactions": [{"id": "heart", "type": "emoji", "count": 4}]}"#,
+        )
+        .unwrap();
+        assert_eq!(post.like_count(), 4);
+        assert_eq!(post.reactions[0].id, "heart");
+
+        let post: PostData = serde_json::from_str(
+            r#"{"id": 1, "post_number": 1, "username": "a", "created_at": "2026-02-20T10:00:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(post.like_count(), 0);
+    }
+
+    #[test]
+    fn test_post_role() {
+        let post = |flags: &str| -> PostData {
+            serde_json::from_str(&format!(
+                r#"{{"id": 1, "post_number": 1, "username": "a",
+                    "created_at": "2026-02-20T10:00:00Z"{}}}"#,
+                flags
+            ))
+            .unwrap()
+        };
+        assert_eq!(post("").role(), None);
+        assert_eq!(
+            post(r#", "admin": true, "moderator": true, "staff": true"#).role(),
+            Some("admin")
+        );
+        assert_eq!(
+            post(r#", "admin": false, "moderator": true, "staff": true"#).role(),
+            Some("moderator")
+        );
+        assert_eq!(post(r#", "staff": true"#).role(), Some("staff"));
+    }
+
+    #[test]
+    fn test_archetype_warning() {
+        let topic = |json: &str| -> Topic { serde_json::from_str(json).unwrap() };
+        let regular = topic(r#"{"id":1,"title":"T","post_stream":{"stream":[]}}"#);
+        assert_eq!(regular.archetype, "regular");
+        assert!(regular.archetype_warning().is_none());
+
+        let banner =
+            topic(r#"{"id":1,"title":"T","archetype":"banner","post_stream":{"stream":[]}}"#);
+        assert!(banner.archetype_warning().unwrap().contains("banner topic"));
+    }
+
+    #[test]
+    fn test_topic_tags() {
+        let topic: Topic = serde_json::from_str(
+            r#"{"id":1,"title":"T","tags":["nix",{"id":2,"name":"flakes","slug":"flakes"}],
+                "post_stream":{"stream":[]}}"#,
+        )
+        .unwrap();
+        let names: Vec<&str> = topic.tags.iter().map(Tag::name).collect();
+        assert_eq!(names, ["nix", "flakes"]);
+    }
+
+    #[test]
+    fn test_rate_limit_risk() {
+        let delay = Duration::from_millis(200);
+        assert!(rate_limit_risk(150, delay, 1).is_none());
+        assert!(rate_limit_risk(500, delay, 1).is_some());
+        assert!(rate_limit_risk(60, delay, 2).is_some());
+        let default_rate = Duration::from_secs_f64(1.0 / DEFAULT_RATE);
+        assert!(rate_limit_risk(10_000, default_rate, 1).is_none());
+        assert!(rate_limit_risk(10_000, Duration::from_secs(1), 1).is_none());
+    }
 }
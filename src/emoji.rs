@@ -0,0 +1,115 @@
+use crate::markdown;
+use regex::{Captures, Regex};
+
+/// Discourse shortcodes that differ from the GitHub (gemoji) names.
+const DISCOURSE_ALIASES: &[(&str, &str)] = &[
+    ("slight_smile", "slightly_smiling_face"),
+    ("slight_frown", "slightly_frowning_face"),
+    ("hugging", "hugs"),
+    ("thinking_face", "thinking"),
+    ("nerd", "nerd_face"),
+    ("rolling_on_the_floor_laughing", "rofl"),
+    ("face_with_rolling_eyes", "roll_eyes"),
+    ("robot_face", "robot"),
+    ("thumbsup", "+1"),
+    ("thumbsdown", "-1"),
+];
+
+/// Replace Discourse emoji shortcodes such as `:smile:` or `:+1:t3:` with their
+/// Unicode equivalents. Unknown shortcodes and anything inside code are left as-is.
+pub fn render_shortcodes(raw: &str) -> String {
+    let re = Regex::new(r":([a-z0-9_+\-]+):(?:t([2-6]):)?").expect("emoji regex is valid");
+    markdown::map_prose(raw, |text| {
+        re.replace_all(text, |caps: &Captures| {
+            let name = &caps[1];
+            let Some(emoji) = lookup(name) else {
+                return caps[0].to_string();
+            };
+            let toned = caps
+                .get(2)
+                .and_then(|t| skin_tone(t.as_str()))
+                .and_then(|tone| emoji.with_skin_tone(tone));
+            toned.unwrap_or(emoji).as_str().to_string()
+        })
+        .into_owned()
+    })
+}
+
+/// Remove Discourse emoji shortcodes such as `:smile:` or `:+1:t3:`, with the
+/// space that separated each from the text. Unknown shortcodes and anything
+/// inside code are left as-is.
+pub fn strip_shortcodes(raw: &str) -> String {
+    let re = Regex::new(r"( ?):([a-z0-9_+\-]+):(?:t[2-6]:)?( ?)").expect("emoji regex is valid");
+    markdown::map_prose(raw, |text| {
+        re.replace_all(text, |caps: &Captures| {
+            if lookup(&caps[2]).is_none() {
+                return caps[0].to_string();
+            }
+            let start = caps.get(0).expect("a match has a whole").start();
+            // Keep one space between the words on either side
+            match (&caps[1], &caps[3]) {
+                (" ", " ") => " ".to_string(),
+                ("", " ") if start > 0 => " ".to_string(),
+                _ => String::new(),
+            }
+        })
+        .into_owned()
+    })
+}
+
+fn lookup(name: &str) -> Option<&'static emojis::Emoji> {
+    emojis::get_by_shortcode(name).or_else(|| {
+        DISCOURSE_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .and_then(|(_, gemoji)| emojis::get_by_shortcode(gemoji))
+    })
+}
+
+/// Map Discourse's `t2`..`t6` skin tone suffixes to Fitzpatrick modifiers.
+fn skin_tone(tone: &str) -> Option<emojis::SkinTone> {
+    match tone {
+        "2" => Some(emojis::SkinTone::Light),
+        "3" => Some(emojis::SkinTone::MediumLight),
+        "4" => Some(emojis::SkinTone::Medium),
+        "5" => Some(emojis::SkinTone::MediumDark),
+        "6" => Some(emojis::SkinTone::Dark),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_shortcodes() {
+        assert_eq!(
+            render_shortcodes("Thanks :smile: :slight_smile: :+1:t3: :not_an_emoji:"),
+            "Thanks 😄 🙂 👍🏼 :not_an_emoji:"
+        );
+    }
+
+    #[test]
+    fn test_strip_shortcodes() {
+        assert_eq!(
+            strip_shortcodes(":wave: Hi :smile: there:+1:t3:, :not_an_emoji: `:smile:` :tada:"),
+            "Hi there, :not_an_emoji: `:smile:`"
+        );
+    }
+
+    #[test]
+    fn test_discourse_aliases_resolve() {
+        for (alias, gemoji) in DISCOURSE_ALIASES {
+            assert!(emojis::get_by_shortcode(gemoji).is_some(), "{}", alias);
+        }
+    }
+
+    #[test]
+    fn test_render_shortcodes_skips_code() {
+        assert_eq!(
+            render_shortcodes("`:smile:` at 10:30:45\n```\n:smile:\n```\n"),
+            "`:smile:` at 10:30:45\n```\n:smile:\n```\n"
+        );
+    }
+}
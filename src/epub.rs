@@ -0,0 +1,375 @@
+use crate::cache::CachedPost;
+use crate::markdown;
+use crate::output::{header_suffix, permalink, post_body, RenderOptions};
+use std::io::Write;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Render the topic as an EPUB 3 book: a title page with the topic's details,
+/// then a section per post, with a table of contents linking them.
+///
+/// Post bodies are laid out from their Markdown as paragraphs, quotes, headings
+/// and code blocks, with inline markup kept as text.
+pub fn render(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> Vec<u8> {
+    // Without a fetch time, date the book by its newest post so it's reproducible
+    let modified = if options.reproducible {
+        posts.iter().map(|p| p.created_at).max().unwrap_or_default()
+    } else {
+        chrono::Utc::now()
+    };
+
+    let mut book = Book::default();
+    // Must come first and uncompressed, so readers can sniff the type
+    book.add_stored("mimetype", b"application/epub+zip");
+    book.add("META-INF/container.xml", CONTAINER_XML.as_bytes());
+    book.add(
+        "OEBPS/content.opf",
+        package(title, source_url, posts, modified).as_bytes(),
+    );
+    book.add("OEBPS/nav.xhtml", nav(title, posts).as_bytes());
+    book.add(
+        "OEBPS/title.xhtml",
+        title_page(title, source_url, posts.len(), options).as_bytes(),
+    );
+    for post in posts {
+        book.add(
+            &format!("OEBPS/{}", post_file(post)),
+            post_page(post, options).as_bytes(),
+        );
+    }
+    book.finish()
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn post_file(post: &CachedPost) -> String {
+    format!("post-{}.xhtml", post.post_number)
+}
+
+fn post_heading(post: &CachedPost) -> String {
+    format!("Post #{} by @{}", post.post_number, post.username)
+}
+
+/// The package document: metadata, the list of files and their reading order.
+fn package(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    modified: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let mut manifest = String::new();
+    manifest.push_str(
+        "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+    );
+    manifest.push_str(
+        "    <item id=\"title\" href=\"title.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+    );
+    let mut spine = String::from("    <itemref idref=\"title\"/>\n");
+    for post in posts {
+        manifest.push_str(&format!(
+            "    <item id=\"post-{n}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            post_file(post),
+            n = post.post_number
+        ));
+        spine.push_str(&format!(
+            "    <itemref idref=\"post-{}\"/>\n",
+            post.post_number
+        ));
+    }
+    let mut authors: Vec<&str> = Vec::new();
+    for post in posts {
+        if !authors.contains(&post.username.as_str()) {
+            authors.push(&post.username);
+        }
+    }
+    let creators: String = authors
+        .iter()
+        .map(|name| format!("    <dc:creator>{}</dc:creator>\n", xml_escape(name)))
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">{source}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:language>en</dc:language>
+    <dc:source>{source}</dc:source>
+{creators}    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+{manifest}  </manifest>
+  <spine>
+{spine}  </spine>
+</package>
+"#,
+        source = xml_escape(source_url),
+        title = xml_escape(title),
+        modified = modified.format("%Y-%m-%dT%H:%M:%SZ"),
+    )
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{}</title></head>
+<body>
+{}</body>
+</html>
+"#,
+        xml_escape(title),
+        body
+    )
+}
+
+/// The navigation document, which readers show as the table of contents.
+fn nav(title: &str, posts: &[CachedPost]) -> String {
+    let mut body = String::from("<nav epub:type=\"toc\">\n<h1>Contents</h1>\n<ol>\n");
+    body.push_str(&format!(
+        "<li><a href=\"title.xhtml\">{}</a></li>\n",
+        xml_escape(title)
+    ));
+    for post in posts {
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            post_file(post),
+            xml_escape(&post_heading(post))
+        ));
+    }
+    body.push_str("</ol>\n</nav>\n");
+    page(title, &body)
+}
+
+fn title_page(title: &str, source_url: &str, post_count: usize, options: &RenderOptions) -> String {
+    let mut body = format!("<h1>{}</h1>\n<ul>\n", xml_escape(title));
+    body.push_str(&format!(
+        "<li>Source: <a href=\"{0}\">{0}</a></li>\n",
+        xml_escape(source_url)
+    ));
+    if let Some(category) = &options.category {
+        body.push_str(&format!("<li>Category: {}</li>\n", xml_escape(category)));
+    }
+    if !options.reproducible {
+        let now = options.dates.format(chrono::Utc::now());
+        body.push_str(&format!("<li>Fetched: {}</li>\n", now));
+    }
+    body.push_str(&format!("<li>Posts: {}</li>\n</ul>\n", post_count));
+    page(title, &body)
+}
+
+fn post_page(post: &CachedPost, options: &RenderOptions) -> String {
+    let heading = post_heading(post);
+    let marker = if options.context_post_ids.contains(&post.post_id) {
+        " [context]"
+    } else {
+        ""
+    };
+    let mut body = format!(
+        "<section epub:type=\"chapter\">\n<h2>{}{}</h2>\n",
+        xml_escape(&heading),
+        marker
+    );
+    body.push_str(&format!(
+        "<p><a href=\"{}\">{}</a>{}</p>\n",
+        xml_escape(&permalink(options, post)),
+        options.dates.format(post.created_at),
+        xml_escape(&header_suffix(post, options))
+    ));
+    body.push_str(&blocks_to_xhtml(&post_body(post, options)));
+    body.push_str("</section>\n");
+    page(&heading, &body)
+}
+
+/// Lay out Markdown as XHTML blocks: fenced code as `<pre>`, `>` lines as
+/// quotes, `#` lines as headings and everything else as paragraphs, with line
+/// breaks kept. Inline markup is left as text.
+fn blocks_to_xhtml(text: &str) -> String {
+    let mut out = String::new();
+    let mut paragraph: Vec<String> = Vec::new();
+    let mut quote: Vec<String> = Vec::new();
+    let mut code: Option<String> = None;
+
+    let flush = |out: &mut String, lines: &mut Vec<String>, tag: &str| {
+        if !lines.is_empty() {
+            out.push_str(&format!("<{}>{}</{}>\n", tag, lines.join("<br/>\n"), tag));
+            lines.clear();
+        }
+    };
+
+    for (line, in_code) in markdown::classify_lines(text) {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if in_code {
+            flush(&mut out, &mut paragraph, "p");
+            flush(&mut out, &mut quote, "blockquote");
+            match &mut code {
+                None => code = Some(String::new()),
+                Some(block) if markdown::fence_marker(line).is_some() && is_closing(line) => {
+                    out.push_str(&format!("<pre><code>{}</code></pre>\n", block));
+                    code = None;
+                }
+                Some(block) => {
+                    block.push_str(&xml_escape(line));
+                    block.push('\n');
+                }
+            }
+            continue;
+        }
+        if let Some(block) = code.take() {
+            // A fence left open by the post
+            out.push_str(&format!("<pre><code>{}</code></pre>\n", block));
+        }
+
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix('>') {
+            flush(&mut out, &mut paragraph, "p");
+            quote.push(xml_escape(rest.trim_start_matches(['>', ' '])));
+        } else if trimmed.is_empty() {
+            flush(&mut out, &mut paragraph, "p");
+            flush(&mut out, &mut quote, "blockquote");
+        } else if let Some(heading) = trimmed
+            .strip_prefix('#')
+            .filter(|rest| rest.starts_with(['#', ' ']))
+        {
+            flush(&mut out, &mut paragraph, "p");
+            flush(&mut out, &mut quote, "blockquote");
+            let heading = heading.trim_start_matches('#').trim();
+            out.push_str(&format!("<h3>{}</h3>\n", xml_escape(heading)));
+        } else {
+            flush(&mut out, &mut quote, "blockquote");
+            paragraph.push(xml_escape(trimmed));
+        }
+    }
+    if let Some(block) = code {
+        out.push_str(&format!("<pre><code>{}</code></pre>\n", block));
+    }
+    flush(&mut out, &mut paragraph, "p");
+    flush(&mut out, &mut quote, "blockquote");
+    out
+}
+
+/// A closing fence carries no info string.
+fn is_closing(line: &str) -> bool {
+    line.trim().chars().all(|c| c == '`' || c == '~')
+}
+
+/// Escape text for XML content and attribute values.
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not allowed in XML 1.0 at all
+            c if c.is_control() && !matches!(c, '\n' | '\t' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The book's ZIP container. Entries are deflated, except those EPUB requires
+/// to be stored, and dated 1980-01-01 so the archive only depends on its
+/// contents.
+struct Book {
+    zip: ZipWriter<std::io::Cursor<Vec<u8>>>,
+}
+
+impl Default for Book {
+    fn default() -> Self {
+        Book {
+            zip: ZipWriter::new(std::io::Cursor::new(Vec::new())),
+        }
+    }
+}
+
+impl Book {
+    fn add(&mut self, name: &str, contents: &[u8]) {
+        self.add_with(name, contents, CompressionMethod::Deflated);
+    }
+
+    fn add_stored(&mut self, name: &str, contents: &[u8]) {
+        self.add_with(name, contents, CompressionMethod::Stored);
+    }
+
+    fn add_with(&mut self, name: &str, contents: &[u8], method: CompressionMethod) {
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .last_modified_time(zip::DateTime::default());
+        self.zip
+            .start_file(name, options)
+            .expect("ZIP entries can be added in memory");
+        self.zip
+            .write_all(contents)
+            .expect("ZIP entries can be written in memory");
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.zip
+            .finish()
+            .expect("ZIP archives can be finished in memory")
+            .into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::test_post;
+
+    #[test]
+    fn test_blocks_to_xhtml() {
+        let md = "# Fix\nTry <this>\nnow\n\n> said\n```\nif a < b {}\n```\nDone";
+        assert_eq!(
+            blocks_to_xhtml(md),
+            "<h3>Fix</h3>\n<p>Try &lt;this&gt;<br/>\nnow</p>\n<blockquote>said</blockquote>\n\
+             <pre><code>if a &lt; b {}\n</code></pre>\n<p>Done</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_render_layout() {
+        let post = || test_post(1, "alice", "Hi");
+        let options = RenderOptions {
+            reproducible: true,
+            ..Default::default()
+        };
+        let book = render("Title", "https://x.test/t/1", &[post()], &options);
+        assert_eq!(
+            book,
+            render("Title", "https://x.test/t/1", &[post()], &options)
+        );
+
+        // The first entry is the uncompressed mimetype, right after its header
+        assert_eq!(&book[..4], b"PK\x03\x04");
+        assert_eq!(&book[30..38], b"mimetype");
+        assert_eq!(&book[38..58], b"application/epub+zip");
+        // Every other entry is deflated
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(book)).unwrap();
+        assert_eq!(archive.len(), 6);
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).unwrap();
+            let expected = if entry.name() == "mimetype" {
+                CompressionMethod::Stored
+            } else {
+                CompressionMethod::Deflated
+            };
+            assert_eq!(entry.compression(), expected, "{}", entry.name());
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{
+    HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// An on-disk cache of HTTP responses, keyed by request URL and headers.
+///
+/// This sits below the post cache: it stores responses verbatim and follows the
+/// server's `Cache-Control`, `ETag` and `Last-Modified` headers, so it can be
+/// shared between users and CI runs hitting the same URLs.
+pub struct HttpCache {
+    dir: PathBuf,
+}
+
+/// A cached response.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    status: u16,
+    #[serde(default)]
+    content_type: Option<String>,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    /// Seconds after `stored_at` the response may be served without revalidating
+    #[serde(default)]
+    max_age: u64,
+    stored_at: chrono::DateTime<chrono::Utc>,
+    body: String,
+}
+
+/// The `Cache-Control` directives the cache acts on.
+#[derive(Debug, Default, PartialEq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut control = Self::default();
+        for value in headers.get_all(CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else { continue };
+            for directive in value.split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                match directive.split_once('=') {
+                    Some(("max-age", secs)) => {
+                        control.max_age = secs.trim_matches('"').parse().ok();
+                    }
+                    _ if directive == "no-store" => control.no_store = true,
+                    _ if directive == "no-cache" => control.no_cache = true,
+                    _ => {}
+                }
+            }
+        }
+        control
+    }
+
+    /// How long a response may be served without revalidating.
+    fn freshness(&self) -> u64 {
+        if self.no_cache {
+            0
+        } else {
+            self.max_age.unwrap_or(0)
+        }
+    }
+}
+
+impl Entry {
+    fn is_fresh(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        let age = now.signed_duration_since(self.stored_at).num_seconds();
+        age >= 0 && (age as u64) < self.max_age
+    }
+
+    fn header(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    }
+
+    fn to_response(&self) -> Result<Response> {
+        let mut response = http::Response::builder().status(self.status);
+        if let Some(content_type) = &self.content_type {
+            response = response.header(CONTENT_TYPE, content_type);
+        }
+        let response = response
+            .body(self.body.clone())
+            .context("Invalid cached response")?;
+        Ok(response.into())
+    }
+}
+
+impl HttpCache {
+    /// Use `dir` for the cache, creating it if needed.
+    pub fn new(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create HTTP cache dir {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Send `request`, serving it from the cache when the stored response is still
+    /// fresh and revalidating it otherwise. `before_send` is called before anything
+    /// goes over the network.
+    ///
+    /// Only successful responses without `Cache-Control: no-store` are stored.
+    pub fn send(
+        &self,
+        client: &Client,
+        mut request: Request,
+        default_headers: &HeaderMap,
+        before_send: impl FnOnce(&str),
+    ) -> Result<Response> {
+        let path = self
+            .dir
+            .join(format!("{}.json", key(&request, default_headers)));
+        let cached: Option<Entry> = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+
+        if let Some(entry) = &cached {
+            if entry.is_fresh(chrono::Utc::now()) {
+                return entry.to_response();
+            }
+            let headers = request.headers_mut();
+            if let Some(etag) = entry.etag.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_NONE_MATCH, etag);
+            }
+            if let Some(date) = entry.last_modified.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_MODIFIED_SINCE, date);
+            }
+        }
+
+        before_send(request.url().as_str());
+        let url = request.url().to_string();
+        let response = client.execute(request)?;
+        let control = CacheControl::parse(response.headers());
+
+        if let (StatusCode::NOT_MODIFIED, Some(mut entry)) = (response.status(), cached) {
+            entry.stored_at = chrono::Utc::now();
+            entry.max_age = control.freshness();
+            if let Some(etag) = Entry::header(response.headers(), ETAG) {
+                entry.etag = Some(etag);
+            }
+            self.store(&path, &entry)?;
+            return entry.to_response();
+        }
+        if !response.status().is_success() || control.no_store {
+            return Ok(response);
+        }
+
+        let headers = response.headers();
+        let mut entry = Entry {
+            url,
+            status: response.status().as_u16(),
+            content_type: Entry::header(headers, CONTENT_TYPE),
+            etag: Entry::header(headers, ETAG),
+            last_modified: Entry::header(headers, LAST_MODIFIED),
+            max_age: control.freshness(),
+            stored_at: chrono::Utc::now(),
+            body: String::new(),
+        };
+        entry.body = response.text().context("Failed to read response body")?;
+        self.store(&path, &entry)?;
+        entry.to_response()
+    }
+
+    /// Write an entry through a temporary file, so concurrent runs sharing the
+    /// cache never read a partial one.
+    fn store(&self, path: &Path, entry: &Entry) -> Result<()> {
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let json = serde_json::to_string(entry)?;
+        std::fs::write(&tmp, json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Cache key for a request: a hash of its method, URL and headers, including the
+/// client's default ones, so that e.g. authenticated and anonymous responses are
+/// kept apart.
+fn key(request: &Request, default_headers: &HeaderMap) -> String {
+    let mut headers: Vec<(&str, &HeaderValue)> = default_headers
+        .iter()
+        .filter(|(name, _)| !request.headers().contains_key(*name))
+        .chain(request.headers())
+        .map(|(name, value)| (name.as_str(), value))
+        .collect();
+    headers.sort_by(|a, b| {
+        a.0.cmp(b.0)
+            .then_with(|| a.1.as_bytes().cmp(b.1.as_bytes()))
+    });
+
+    let mut hasher = Sha256::new();
+    hasher.update(request.method().as_str());
+    hasher.update(b"\n");
+    hasher.update(request.url().as_str());
+    hasher.update(b"\n");
+    for (name, value) in headers {
+        hasher.update(name);
+        hasher.update(b": ");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_control() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, "public, max-age=60".parse().unwrap());
+        let control = CacheControl::parse(&headers);
+        assert_eq!(control.max_age, Some(60));
+        assert_eq!(control.freshness(), 60);
+
+        headers.insert(CACHE_CONTROL, "no-cache, max-age=60".parse().unwrap());
+        assert_eq!(CacheControl::parse(&headers).freshness(), 0);
+
+        headers.insert(CACHE_CONTROL, "No-Store".parse().unwrap());
+        assert!(CacheControl::parse(&headers).no_store);
+    }
+
+    #[test]
+    fn test_key_depends_on_headers() {
+        let client = Client::new();
+        let url = "https://discuss.example.com/t/1.json";
+        let request = client.get(url).build().unwrap();
+        let mut defaults = HeaderMap::new();
+        let anonymous = key(&request, &defaults);
+        assert_eq!(anonymous, key(&client.get(url).build().unwrap(), &defaults));
+
+        defaults.insert("Api-Key", "secret".parse().unwrap());
+        assert_ne!(anonymous, key(&request, &defaults));
+        assert_ne!(
+            anonymous,
+            key(
+                &client.get(format!("{}?page=2", url)).build().unwrap(),
+                &HeaderMap::new()
+            )
+        );
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let now = chrono::Utc::now();
+        let entry = Entry {
+            url: "https://discuss.example.com/t/1.json".to_string(),
+            status: 200,
+            content_type: Some("application/json".to_string()),
+            etag: Some("W/\"abc\"".to_string()),
+            last_modified: None,
+            max_age: 60,
+            stored_at: now,
+            body: r#"{"id":1}"#.to_string(),
+        };
+        assert!(entry.is_fresh(now + chrono::Duration::seconds(59)));
+        assert!(!entry.is_fresh(now + chrono::Duration::seconds(60)));
+
+        let response = entry.to_response().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().unwrap();
+        assert_eq!(body["id"], 1);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::markdown;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+
+/// Build a regex matching links to topics on the forum at `base_url`.
+///
+/// Matches Markdown links `[text](url)`, where `url` may be relative, and bare
+/// absolute URLs. The topic ID is captured as `md_id` or `bare_id` respectively,
+/// and the post number, if any, as `md_post` or `bare_post`.
+fn topic_link_regex(base_url: &str) -> Regex {
+    let base = regex::escape(base_url);
+    // The slug must contain a non-digit, so `/t/12345/42` reads as topic 12345, post 42
+    let path = |id: &str, post: &str| {
+        format!(
+            r"/t/(?:[^/\s()\[\]<>]*[^/\s()\[\]<>\d][^/\s()\[\]<>]*/)?(?P<{id}>\d+)(?:/(?P<{post}>\d+))?/?(?:[?#][^\s()\[\]<>]*)?"
+        )
+    };
+    Regex::new(&format!(
+        r"\[(?P<text>[^\]\n]*)\]\((?P<link>(?:{base})?{md})\)|(?P<bare>{base}{bare})",
+        md = path("md_id", "md_post"),
+        bare = path("bare_id", "bare_post"),
+    ))
+    .expect("topic link regex is valid")
+}
+
+/// Find the IDs of all forum topics linked from `raw`, in order of first appearance.
+///
+/// Links inside code are ignored.
+pub fn find_topic_ids(raw: &str, base_url: &str) -> Vec<u64> {
+    let re = topic_link_regex(base_url);
+    let mut ids = Vec::new();
+    markdown::map_prose(raw, |text| {
+        for caps in re.captures_iter(text) {
+            if let Some(id) = topic_id(&caps) {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        text.to_string()
+    });
+    ids
+}
+
+/// Rewrite internal topic links in `raw` to absolute URLs, using the target
+/// topic's title as link text where the link has none of its own.
+///
+/// A bare `https://forum/t/slug/123` becomes `[Target Title](https://forum/t/slug/123)`,
+/// and `[/t/slug/123](/t/slug/123)` gets the same treatment. Links with
+/// meaningful text keep it. Links inside code are left alone.
+pub fn rewrite_topic_links(raw: &str, base_url: &str, titles: &HashMap<u64, String>) -> String {
+    let re = topic_link_regex(base_url);
+    markdown::map_prose(raw, |text| {
+        re.replace_all(text, |caps: &Captures| {
+            let title = topic_id(caps).and_then(|id| titles.get(&id));
+            if let Some(bare) = caps.name("bare") {
+                return match title {
+                    Some(title) => format!("[{}]({})", escape_link_text(title), bare.as_str()),
+                    None => bare.as_str().to_string(),
+                };
+            }
+            let link = &caps["link"];
+            let absolute = if link.starts_with('/') {
+                format!("{}{}", base_url, link)
+            } else {
+                link.to_string()
+            };
+            let text = &caps["text"];
+            let has_own_text = !text.trim().is_empty() && text != link && text != absolute;
+            match title {
+                Some(title) if !has_own_text => {
+                    format!("[{}]({})", escape_link_text(title), absolute)
+                }
+                _ => format!("[{}]({})", text, absolute),
+            }
+        })
+        .into_owned()
+    })
+}
+
+/// Point links to posts of topic `topic_id` that are in the document, by their
+/// numbers in `post_numbers`, at the posts' `#post-N` anchors.
+///
+/// Link text stays, unless it's just the URL; bare URLs become `[post #N](#post-N)`.
+/// Links to the topic as a whole, to posts left out and inside code are left
+/// alone.
+pub fn anchor_post_links(
+    raw: &str,
+    base_url: &str,
+    topic_id: u64,
+    post_numbers: &HashSet<u64>,
+) -> String {
+    let re = topic_link_regex(base_url);
+    markdown::map_prose(raw, |text| {
+        re.replace_all(text, |caps: &Captures| {
+            let post_number = caps
+                .name("md_post")
+                .or_else(|| caps.name("bare_post"))
+                .and_then(|m| m.as_str().parse::<u64>().ok())
+                .filter(|n| self::topic_id(caps) == Some(topic_id) && post_numbers.contains(n));
+            let Some(n) = post_number else {
+                return caps[0].to_string();
+            };
+            let text = match caps.name("text") {
+                Some(text)
+                    if !text.as_str().trim().is_empty()
+                        && text.as_str() != &caps["link"]
+                        && text.as_str() != format!("{}{}", base_url, &caps["link"]) =>
+                {
+                    text.as_str().to_string()
+                }
+                _ => format!("post #{}", n),
+            };
+            format!("[{}](#post-{})", text, n)
+        })
+        .into_owned()
+    })
+}
+
+/// Like [`anchor_post_links`], for the links in a post's rendered HTML.
+pub fn anchor_post_hrefs(
+    cooked: &str,
+    base_url: &str,
+    topic_id: u64,
+    post_numbers: &HashSet<u64>,
+) -> String {
+    let href = Regex::new(r#"\bhref="([^"]*)""#).expect("href regex is valid");
+    let target = Regex::new(&format!(
+        r"^(?:{})?/t/(?:[^/]*[^/\d][^/]*/)?(\d+)/(\d+)/?(?:[?#].*)?$",
+        regex::escape(base_url)
+    ))
+    .expect("post URL regex is valid");
+    href.replace_all(cooked, |caps: &Captures| {
+        let post_number = target.captures(&caps[1]).and_then(|url| {
+            let n = url[2].parse::<u64>().ok()?;
+            (url[1].parse::<u64>().ok()? == topic_id && post_numbers.contains(&n)).then_some(n)
+        });
+        match post_number {
+            Some(n) => format!("href=\"#post-{}\"", n),
+            None => caps[0].to_string(),
+        }
+    })
+    .into_owned()
+}
+
+/// Matches an `@username` mention, with the character before it in `pre` and the
+/// username in `name`.
+pub fn mention_regex() -> Regex {
+    // Usernames are word characters, `.` and `-`, starting and ending with a word character
+    Regex::new(r"(?P<pre>^|[^\w@/.\[\]-])@(?P<name>\w(?:[\w.-]*\w)?)")
+        .expect("mention regex is valid")
+}
+
+/// Turn `@username` mentions in `raw` into links to the user's profile,
+/// `[@username]({base_url}/u/username)`.
+///
+/// Mentions inside code are left alone, as are email addresses, `@`s within URLs
+/// and mentions that are already link text.
+pub fn link_mentions(raw: &str, base_url: &str) -> String {
+    let re = mention_regex();
+    markdown::map_prose(raw, |text| {
+        re.replace_all(text, |caps: &Captures| {
+            format!(
+                "{}[@{name}]({}/u/{name})",
+                &caps["pre"],
+                base_url,
+                name = &caps["name"]
+            )
+        })
+        .into_owned()
+    })
+}
+
+fn topic_id(caps: &Captures) -> Option<u64> {
+    caps.name("md_id")
+        .or_else(|| caps.name("bare_id"))
+        .and_then(|m| m.as_str().parse().ok())
+}
+
+fn escape_link_text(text: &str) -> String {
+    text.replace('[', "\\[").replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://discuss.example.com";
+
+    #[test]
+    fn test_find_topic_ids() {
+        let raw = "See https://discuss.example.com/t/other/42 and [this](/t/12345/7).\n\
+                   `https://discuss.example.com/t/x/99` and https://elsewhere.com/t/x/5\n\
+                   Again https://discuss.example.com/t/42";
+        assert_eq!(find_topic_ids(raw, BASE), vec![42, 12345]);
+    }
+
+    #[test]
+    fn test_link_mentions() {
+        let raw = "Thanks @alice and @bob.smith! Mail me at me@example.com,@carol\n\
+                   `@code` [@dave](https://discuss.example.com/u/dave) https://x.com/@eve\n\
+                   ```\n@fenced\n```\n";
+        assert_eq!(
+            link_mentions(raw, BASE),
+            "Thanks [@alice](https://discuss.example.com/u/alice) and \
+             [@bob.smith](https://discuss.example.com/u/bob.smith)! \
+             Mail me at me@example.com,[@carol](https://discuss.example.com/u/carol)\n\
+             `@code` [@dave](https://discuss.example.com/u/dave) https://x.com/@eve\n\
+             ```\n@fenced\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_topic_links() {
+        let titles = HashMap::from([(42, "Other Topic".to_string())]);
+        let raw = "See https://discuss.example.com/t/other/42/3 or [/t/other/42](/t/other/42), \
+                   [my words](/t/other/42) and [old](/t/unknown/77).";
+        assert_eq!(
+            rewrite_topic_links(raw, BASE, &titles),
+            "See [Other Topic](https://discuss.example.com/t/other/42/3) or \
+             [Other Topic](https://discuss.example.com/t/other/42), \
+             [my words](https://discuss.example.com/t/other/42) and \
+             [old](https://discuss.example.com/t/unknown/77)."
+        );
+    }
+
+    #[test]
+    fn test_anchor_post_links() {
+        let posts = HashSet::from([1, 3]);
+        let raw = "As https://discuss.example.com/t/this/7/3?u=bob says, and [the question](/t/this/7/1), \
+                   not [/t/this/7/2](/t/this/7/2), [the topic](/t/this/7) or [elsewhere](/t/other/8/3).\n\
+                   [https://discuss.example.com/t/7/3](https://discuss.example.com/t/7/3) `/t/this/7/3`";
+        assert_eq!(
+            anchor_post_links(raw, BASE, 7, &posts),
+            "As [post #3](#post-3) says, and [the question](#post-1), \
+             not [/t/this/7/2](/t/this/7/2), [the topic](/t/this/7) or [elsewhere](/t/other/8/3).\n\
+             [post #3](#post-3) `/t/this/7/3`"
+        );
+
+        let cooked = "<a href=\"https://discuss.example.com/t/this/7/3?u=bob\">x</a> \
+                      <a href=\"/t/this/7/2\">y</a> <a href=\"/t/7/1\">z</a>";
+        assert_eq!(
+            anchor_post_hrefs(cooked, BASE, 7, &posts),
+            "<a href=\"#post-3\">x</a> <a href=\"/t/this/7/2\">y</a> <a href=\"#post-1\">z</a>"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::manifest::TopicReport;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Write `llms.txt`, an index of the scraped topics following the llms.txt
+/// convention, and `llms-full.txt`, every topic's document one after another,
+/// to `dir`, where the topics' documents were written.
+pub fn write(dir: &Path, reports: &[TopicReport]) -> Result<()> {
+    let index_path = dir.join("llms.txt");
+    crate::write_output(&index_path, index(reports).as_bytes())?;
+
+    let mut full = String::new();
+    for path in reports.iter().flat_map(|report| &report.output_paths) {
+        let document =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        if !full.is_empty() {
+            full.push('\n');
+        }
+        full.push_str(&document);
+    }
+    let full_path = dir.join("llms-full.txt");
+    crate::write_output(&full_path, full.as_bytes())
+}
+
+/// The `llms.txt` index: a title and summary, then a link to each topic's
+/// document with its size, source and opening line.
+///
+/// A single topic titles the index; several are named after their forum.
+pub fn index(reports: &[TopicReport]) -> String {
+    let mut forums: Vec<&str> = Vec::new();
+    for report in reports {
+        if !forums.contains(&host(&report.base_url)) {
+            forums.push(host(&report.base_url));
+        }
+    }
+    let (title, summary) = match reports {
+        [report] => (
+            report.title.clone(),
+            report
+                .preview
+                .clone()
+                .unwrap_or_else(|| format!("A discussion on {}", host(&report.base_url))),
+        ),
+        _ => (
+            match forums.as_slice() {
+                [forum] => format!("{} discussions", forum),
+                _ => "Forum discussions".to_string(),
+            },
+            format!(
+                "{} topics from {}, each as a Markdown document of its posts",
+                reports.len(),
+                forums.join(", ")
+            ),
+        ),
+    };
+
+    let mut out = format!("# {}\n\n> {}\n\n## Topics\n\n", title, summary);
+    for report in reports {
+        let Some(file) = report
+            .output_paths
+            .first()
+            .and_then(|path| Path::new(path).file_name())
+        else {
+            continue;
+        };
+        out.push_str(&format!(
+            "- [{}]({}): {} post{} from {}",
+            report.title.replace('[', "\\[").replace(']', "\\]"),
+            file.to_string_lossy(),
+            report.posts,
+            if report.posts == 1 { "" } else { "s" },
+            report.url
+        ));
+        if let Some(preview) = &report.preview {
+            out.push_str(&format!(" — {}", preview));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn host(base_url: &str) -> &str {
+    base_url
+        .split_once("://")
+        .map_or(base_url, |(_, host)| host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(topic_id: u64, title: &str, preview: Option<&str>) -> TopicReport {
+        TopicReport {
+            url: format!("https://forum.test/t/x/{}", topic_id),
+            base_url: "https://forum.test".to_string(),
+            topic_id,
+            title: title.to_string(),
+            preview: preview.map(str::to_string),
+            posts: 3,
+            cache_hits: 0,
+            cache_misses: 3,
+            failed_posts: Vec::new(),
+            output_paths: vec![format!("out/{}-x.md", topic_id)],
+            output_bytes: 0,
+            tokens: None,
+            redactions: None,
+            elapsed_seconds: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        assert_eq!(
+            index(&[report(1, "Setup [help]", Some("How do I…"))]),
+            "# Setup [help]\n\n> How do I…\n\n## Topics\n\n\
+             - [Setup \\[help\\]](1-x.md): 3 posts from https://forum.test/t/x/1 — How do I…\n"
+        );
+        assert_eq!(
+            index(&[report(1, "A", None), report(2, "B", None)]),
+            "# forum.test discussions\n\n\
+             > 2 topics from forum.test, each as a Markdown document of its posts\n\n## Topics\n\n\
+             - [A](1-x.md): 3 posts from https://forum.test/t/x/1\n\
+             - [B](2-x.md): 3 posts from https://forum.test/t/x/2\n"
+        );
+    }
+}
@@ -1,56 +1,1057 @@
+mod anonymize;
+mod batch;
 mod cache;
+mod credentials;
+mod dates;
+mod diff;
 mod discourse;
+mod emoji;
+mod epub;
+mod http_cache;
+mod links;
+mod llms;
+mod manifest;
+mod markdown;
+mod markup;
+mod org;
 mod output;
+mod pdf;
+mod plaintext;
+mod polls;
+mod redact;
+mod robots;
+mod sqlite;
+mod summary;
+mod template;
+mod tokens;
+mod uploads;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, IsTerminal, Write};
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[clap(
     author = "Sridhar Ratnakumar",
     version,
-    about = "Download Discourse thread posts as raw Markdown for LLM consumption"
+    about = "Download Discourse thread posts as raw Markdown for LLM consumption",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
-    /// Discourse thread URL (e.g. `https://discuss.example.com/t/topic-slug/12345`)
-    #[arg()]
-    url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Discourse thread URLs (e.g. `https://discuss.example.com/t/topic-slug/12345`)
+    #[arg(required = true, value_name = "URL")]
+    urls: Vec<String>,
+
+    /// Send requests to this base URL instead of the one in the topic URL
+    /// (e.g. an internal hostname for a proxied forum)
+    #[arg(long, value_name = "URL")]
+    base_url: Option<String>,
+
+    /// Number of topics to scrape in parallel when given several URLs
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    topic_concurrency: usize,
 
-    /// Output file (default: `TOPIC_TITLE.md`)
+    /// Number of raw posts to fetch in parallel within a topic; `--rate` paces them
+    #[arg(long, value_name = "N", default_value_t = discourse::DEFAULT_POST_CONCURRENCY)]
+    post_concurrency: usize,
+
+    /// Write each post to the document as soon as it and every post before it
+    /// are fetched, instead of all at once at the end
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "template",
+            "shard_size",
+            "max_tokens",
+            "split_posts",
+            "group_size",
+            "sections",
+            "digest",
+            "toc",
+            "participant_summary",
+            "participants",
+            "qa_layout",
+            "threaded",
+            "citations",
+            "frontmatter",
+            "pandoc",
+            "static_site",
+            "diff_against",
+            "follow_internal_links",
+            "tag_code_from_cooked",
+            "exclude_quoted_only",
+            "strip_quotes",
+            "collapse_quotes",
+            "dedupe_quotes",
+            "sort_by_number",
+            "top",
+            "anchor_post_links",
+            "resolve_link_titles",
+            "download_images",
+            "download_attachments",
+            "render_emoji",
+            "strip_emoji",
+            "render_mentions_as_links",
+            "normalize_markup",
+            "redact_pii",
+            "anonymize",
+            "stale_warning_days",
+            "compress_output",
+            "checksum_output",
+            "count_tokens",
+            "llms_txt",
+            "merge_json",
+        ]
+    )]
+    stream: bool,
+
+    /// Output file, or directory with `--shard-size` (default: `TOPIC_TITLE.md`, or
+    /// the format's extension, e.g. `.json`); `-` writes to standard output.
+    /// May contain `{title}`, `{title_slug}` and `{topic_id}` placeholders
     #[arg(short, long)]
     output: Option<String>,
 
+    /// Print a filename slug derived from each topic's title and exit
+    #[arg(long)]
+    print_slug: bool,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = output::OutputFormat::Markdown)]
+    format: output::OutputFormat,
+
+    /// Render the document with this Tera template instead of a built-in format
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "format",
+            "shard_size",
+            "group_size",
+            "sections",
+            "digest",
+            "toc",
+            "participant_summary",
+            "qa_layout",
+            "follow_internal_links",
+            "diff_against",
+            "frontmatter",
+            "pandoc",
+            "static_site",
+        ]
+    )]
+    template: Option<std::path::PathBuf>,
+
+    /// With `--format text`, replace code blocks with a `[code]` line
+    #[arg(long)]
+    collapse_code: bool,
+
+    /// Split output into files of N posts each; `--output` is then a directory
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    shard_size: Option<u64>,
+
+    /// Write each post to its own file in DIR, plus an index
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = [
+            "shard_size",
+            "output",
+            "qa_layout",
+            "frontmatter",
+            "pandoc",
+            "static_site",
+            "group_size",
+            "sections",
+            "digest",
+            "toc",
+            "participant_summary",
+            "compress_output",
+            "template",
+        ]
+    )]
+    split_posts: Option<String>,
+
+    /// Split output into files of at most N tokens each; `--output` is then a
+    /// directory
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = [
+            "shard_size",
+            "split_posts",
+            "qa_layout",
+            "frontmatter",
+            "pandoc",
+            "static_site",
+            "group_size",
+            "sections",
+            "digest",
+            "toc",
+            "participant_summary",
+            "compress_output",
+            "template",
+        ]
+    )]
+    max_tokens: Option<u64>,
+
+    /// Render posts in post-number order instead of the topic's stream order
+    #[arg(long)]
+    sort_by_number: bool,
+
+    /// Order of the posts
+    #[arg(long, value_enum, default_value_t = output::PostOrder::Chronological)]
+    sort: output::PostOrder,
+
+    /// Keep only the N most-liked posts
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    top: Option<u64>,
+
+    /// Wrap each post in HTML-comment markers carrying its metadata as JSON (for RAG chunking)
+    #[arg(long)]
+    post_separator_with_metadata: bool,
+
+    /// Collapse blank lines, trim trailing spaces and normalize line endings (code fences untouched)
+    #[arg(long)]
+    normalize_whitespace: bool,
+
+    /// Compare against a previously rendered document and report new/edited posts
+    #[arg(long, value_name = "PATH")]
+    diff_against: Option<String>,
+
+    /// With `--diff-against`, only render new and edited posts
+    #[arg(long, requires = "diff_against")]
+    changes_only: bool,
+
+    /// Close code fences left open by a post so they can't swallow later posts
+    #[arg(long)]
+    repair_markdown: bool,
+
+    /// List the participants and their post counts in the header
+    #[arg(long)]
+    participants: bool,
+
+    /// Order of the participant roster
+    #[arg(long, value_enum, default_value_t = output::ParticipantOrder::Count, requires = "participants")]
+    sort_participants: output::ParticipantOrder,
+
+    /// Render post #1 as the lead under the title and the other posts as answers
+    #[arg(long, conflicts_with_all = ["shard_size", "diff_against"])]
+    qa_layout: bool,
+
+    /// Link each post's header to the post on the forum
+    #[arg(long)]
+    permalinks: bool,
+
+    /// Heading level of the posts (2 for `##`, up to 6)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..=6), conflicts_with = "diff_against")]
+    heading_level: Option<u64>,
+
+    /// Text of each post's heading, e.g. "{username} on {date}"; see the README for the placeholders
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_post_header, conflicts_with_all = ["permalinks", "diff_against"])]
+    post_header: Option<String>,
+
+    /// Line between posts instead of `---`; empty for none
+    #[arg(long, value_name = "TEXT", conflicts_with = "diff_against")]
+    post_separator: Option<String>,
+
+    /// Order posts by the reply tree, nesting each reply under the post it answers
+    #[arg(long, conflicts_with_all = ["shard_size", "max_tokens", "split_posts", "group_size", "diff_against", "template"])]
+    threaded: bool,
+
+    /// Give the topic's details as YAML frontmatter instead of a bullet list
+    #[arg(long, conflicts_with = "shard_size")]
+    frontmatter: bool,
+
+    /// Shape the frontmatter for Pandoc: add the authors and date, and leave
+    /// the title heading to Pandoc
+    #[arg(long, conflicts_with = "shard_size")]
+    pandoc: bool,
+
+    /// Add the frontmatter Hugo and Jekyll expect: date, slug, tags, categories
+    /// and canonical URL
+    #[arg(long, conflicts_with_all = ["shard_size", "pandoc"])]
+    static_site: bool,
+
+    /// Group the posts under a `## Posts #N–#M` heading every N posts
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["shard_size", "diff_against"])]
+    group_size: Option<u64>,
+
+    /// Sections to render, in order (comma-separated; default: header,posts)
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "SECTIONS",
+        conflicts_with = "shard_size"
+    )]
+    sections: Vec<output::Section>,
+
+    /// Add a digest of each post's opening prose between the header and the posts
+    #[arg(long, conflicts_with_all = ["sections", "shard_size"])]
+    digest: bool,
+
+    /// Add a table of contents linking each post between the header and the posts
+    #[arg(long, conflicts_with_all = ["sections", "shard_size"])]
+    toc: bool,
+
+    /// Summarize the participants (post counts, activity, OP and staff) under the header
+    #[arg(long, conflicts_with_all = ["sections", "shard_size"])]
+    participant_summary: bool,
+
+    /// Show dates in this time zone: an IANA name like `Europe/Paris`, or `local`
+    #[arg(long, value_name = "ZONE", default_value = "UTC", value_parser = dates::parse_timezone)]
+    timezone: dates::Timezone,
+
+    /// Write dates with this strftime pattern
+    #[arg(long, value_name = "PATTERN", default_value = dates::DEFAULT_DATE_FORMAT, value_parser = dates::parse_date_format)]
+    date_format: String,
+
+    /// Warn, on stderr and in the header, when the newest post is older than DAYS days
+    #[arg(long, value_name = "DAYS")]
+    stale_warning_days: Option<u64>,
+
+    /// Print the full HTTP request and response for this post number to stderr
+    #[arg(long, value_name = "POST_NUMBER")]
+    trace_post: Option<u64>,
+
+    /// Leave run-dependent details (fetch time, post age warning) out of the output
+    #[arg(long, alias = "deterministic")]
+    reproducible: bool,
+
+    /// Print a SHA-256 of each output file (ignoring the fetch time) to stderr
+    #[arg(long)]
+    checksum_output: bool,
+
+    /// Print the document's token count, and each post's, to stderr
+    #[arg(long)]
+    count_tokens: bool,
+
+    /// Compress the output file with gzip (the default) or zstd, adding `.gz` or
+    /// `.zst` to its name; implied by an `--output` ending in either
+    #[arg(
+        long,
+        alias = "compress",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "gzip",
+        conflicts_with = "shard_size"
+    )]
+    compress_output: Option<output::Compression>,
+
+    /// With `--format json`, merge into the document already at the output path
+    /// instead of overwriting it: posts are matched by ID, so updated posts
+    /// replace their old entries and new ones are added in order
+    #[arg(long, conflicts_with_all = ["template", "compress_output"])]
+    merge_json: bool,
+
+    /// Write a JSON summary of the run (counts, timings, output paths) to this file
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<String>,
+
+    /// Write each topic as Markdown to DIR, with an `llms.txt` index and an
+    /// `llms-full.txt` of everything, for publishing as LLM context
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = [
+            "output",
+            "format",
+            "template",
+            "shard_size",
+            "split_posts",
+            "max_tokens",
+            "compress_output",
+        ]
+    )]
+    llms_txt: Option<String>,
+
     /// Cache threshold in days — posts older than this are not re-downloaded
     #[arg(short, long, default_value_t = 4)]
     cache_days: u64,
 
+    /// Days to remember deleted (HTTP 404) posts and not request them again; 0 disables
+    #[arg(long, value_name = "DAYS", default_value_t = 1)]
+    missing_cache_days: u64,
+
+    /// Always re-fetch posts created within the last DAYS days, even if cached
+    #[arg(long, value_name = "DAYS")]
+    force_refresh_newer: Option<u64>,
+
+    /// Only scrape the post with this post number
+    #[arg(long, value_name = "POST_NUMBER")]
+    single_post: Option<u64>,
+
+    /// Only fetch posts after the first N in the topic's stream (for delta scrapes)
+    #[arg(long, value_name = "N", conflicts_with = "single_post")]
+    known_count: Option<u64>,
+
+    /// Only fetch and render N posts of the topic: the first N, or with
+    /// --posts-newest-first the last N
+    #[arg(long, value_name = "N", conflicts_with = "single_post")]
+    max_posts: Option<u64>,
+
+    /// Spend --max-posts on the newest posts rather than the oldest
+    #[arg(long, requires = "max_posts")]
+    posts_newest_first: bool,
+
+    /// Only include posts made at or after WHEN: an ISO date or timestamp, or a
+    /// duration ago such as 7d, 2w or 3mo
+    #[arg(long, value_name = "WHEN", value_parser = parse_date_bound)]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only include posts made before WHEN (same forms as --since)
+    #[arg(long, value_name = "WHEN", value_parser = parse_date_bound)]
+    until: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only include posts by this user (can be repeated)
+    #[arg(short, long = "user", value_name = "USERNAME")]
+    users: Vec<String>,
+
+    /// Also include posts that filtered posts reply to, marked as `[context]`
+    #[arg(long)]
+    fetch_replies_context: bool,
+
+    /// Drop posts that are only a quote of another post with little added (e.g. "this")
+    #[arg(long)]
+    exclude_quoted_only: bool,
+
+    /// Remove quotes of other posts
+    #[arg(long, conflicts_with = "collapse_quotes")]
+    strip_quotes: bool,
+
+    /// Replace quotes of other posts with a "(quoting @user, post #N)" note
+    #[arg(long)]
+    collapse_quotes: bool,
+
+    /// Point links to posts in the document at the posts' anchors
+    #[arg(long, conflicts_with_all = ["shard_size", "split_posts", "max_tokens"])]
+    anchor_post_links: bool,
+
+    /// Mark each post with a footnote citing its permalink, and list the
+    /// footnotes at the end, so answers drawn from the document can cite posts
+    #[arg(long, conflicts_with = "template")]
+    citations: bool,
+
+    /// Replace quotes of text an earlier post already shows with a back-reference
+    #[arg(long, conflicts_with_all = ["strip_quotes", "collapse_quotes", "threaded"])]
+    dedupe_quotes: bool,
+
+    /// Exit with an error instead of writing an empty document when no posts match the filters
+    #[arg(long)]
+    exit_on_empty: bool,
+
+    /// Make internal topic links absolute, titled with the linked topic's title
+    #[arg(long)]
+    resolve_link_titles: bool,
+
+    /// Convert Discourse markup (oneboxes, `[details]`, `upload://` links, mentions) to plain Markdown
+    #[arg(long)]
+    normalize_markup: bool,
+
+    /// Replace email addresses, phone numbers and IP addresses in posts with placeholders
+    #[arg(long)]
+    redact_pii: bool,
+
+    /// Replace usernames with stable pseudonyms (`user1`, `user2`, …) throughout
+    #[arg(long, conflicts_with = "render_mentions_as_links")]
+    anonymize: bool,
+
+    /// Replace emoji shortcodes like `:smile:` with Unicode emoji
+    #[arg(long)]
+    render_emoji: bool,
+
+    /// Remove emoji shortcodes like `:smile:` instead
+    #[arg(long, conflicts_with = "render_emoji")]
+    strip_emoji: bool,
+
+    /// Download the images posts show into DIR and point the Markdown at them
+    #[arg(long, value_name = "DIR")]
+    download_images: Option<std::path::PathBuf>,
+
+    /// Download the files posts attach into DIR, with a manifest, and point the
+    /// Markdown at them
+    #[arg(long, value_name = "DIR")]
+    download_attachments: Option<std::path::PathBuf>,
+
+    /// Also scrape topics on the same forum that posts link to, appended as an appendix
+    #[arg(long)]
+    follow_internal_links: bool,
+
+    /// How many links deep to follow with `--follow-internal-links`
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "follow_internal_links"
+    )]
+    depth: u32,
+
+    /// Maximum number of linked topics to scrape with `--follow-internal-links`
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        requires = "follow_internal_links"
+    )]
+    max_linked_topics: usize,
+
+    /// Give untagged code fences the language Discourse assigned them in the cooked HTML
+    #[arg(long)]
+    tag_code_from_cooked: bool,
+
+    /// Turn `@username` mentions into links to the user's profile
+    #[arg(long)]
+    render_mentions_as_links: bool,
+
+    /// Fetch raw content a page of posts at a time via `/raw/{topic_id}?page=N`,
+    /// falling back to one request per post where that fails
+    #[arg(long)]
+    bulk_raw: bool,
+
+    /// Fetch the forum's robots.txt and refuse to request paths it disallows
+    #[arg(long)]
+    respect_robots: bool,
+
+    /// Re-fetch the forum's category list instead of using the cached copy
+    #[arg(long)]
+    refresh_site_cache: bool,
+
+    /// Discourse API key, for forums or categories that require login
+    #[arg(long, env = "DISCOURSE_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+
+    /// Username to make API requests as (required for all-users API keys)
+    #[arg(long, env = "DISCOURSE_API_USERNAME")]
+    api_username: Option<String>,
+
+    /// Read the API key from the OS keyring entry stored with `disc-scrape login SERVICE`
+    #[arg(long, value_name = "SERVICE")]
+    api_key_from_keyring: Option<String>,
+
+    /// Send this header with every request (can be repeated)
+    #[arg(long = "header", value_name = "KEY: VALUE", value_parser = discourse::parse_header)]
+    headers: Vec<discourse::CustomHeader>,
+
+    /// Milliseconds to wait between raw post fetches and other single requests,
+    /// on top of `--rate`
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    delay_ms: u64,
+
+    /// Milliseconds to wait between metadata batch requests, on top of `--rate`
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    batch_delay_ms: u64,
+
+    /// Send at most N requests per second to each forum, across all parallel workers
+    #[arg(long, value_name = "N", value_parser = parse_rate, default_value_t = discourse::DEFAULT_RATE)]
+    rate: f64,
+
+    /// Keep raw HTTP responses in DIR, following the server's caching headers
+    #[arg(long, value_name = "DIR")]
+    http_cache: Option<std::path::PathBuf>,
+
+    /// Don't ask for confirmation when the run may trip the forum's rate limits
+    #[arg(short, long, alias = "force")]
+    yes: bool,
+
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Only print warnings and errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Store a Discourse API key in the OS keyring for `--api-key-from-keyring`
+    Login {
+        /// Keyring service name to store the key under (e.g. the forum's hostname)
+        service: String,
+
+        /// Username the key makes API requests as
+        #[arg(long)]
+        api_username: Option<String>,
+    },
+
+    /// Remove cached topics that haven't been fetched for a while
+    CompactCache {
+        /// Remove topics whose most recent fetch is older than DAYS days
+        #[arg(long, value_name = "DAYS")]
+        older_than: u64,
+
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Maintain the post cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CacheCommand {
+    /// Rename cached posts to match their post IDs and drop duplicate copies
+    Migrate {
+        /// Only report what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    match &args.command {
+        Some(Command::Login {
+            service,
+            api_username,
+        }) => return credentials::login(service, api_username.as_deref()),
+        Some(Command::CompactCache {
+            older_than,
+            dry_run,
+        }) => return compact_cache(*older_than, *dry_run),
+        Some(Command::Cache {
+            command: CacheCommand::Migrate { dry_run },
+        }) => return migrate_cache(*dry_run),
+        None => {}
+    }
+
+    let api_key = match &args.api_key_from_keyring {
+        Some(service) => credentials::load_api_key(service, args.api_username.as_deref()),
+        None => args.api_key.clone(),
+    };
+    let api_key = api_key.map(|key| discourse::ApiKey {
+        key,
+        username: args.api_username.clone(),
+    });
+    discourse::init_client(api_key.as_ref(), &args.headers)?;
+    discourse::set_rate_limit(args.rate)?;
+    if let Some(dir) = &args.http_cache {
+        discourse::set_http_cache(dir)?;
+    }
+
+    // Many topics can go into one database
+    if args.urls.len() > 1
+        && args.format != output::OutputFormat::Sqlite
+        && args.output.as_deref().is_some_and(|o| !o.contains('{'))
+    {
+        bail!("--output needs a placeholder such as {{title_slug}} when given multiple URLs");
+    }
+    if args.urls.len() > 1
+        && args
+            .split_posts
+            .as_deref()
+            .is_some_and(|d| !d.contains('{'))
+    {
+        bail!("--split-posts needs a placeholder such as {{title_slug}} when given multiple URLs");
+    }
+
+    if args.qa_layout && args.format != output::OutputFormat::Markdown {
+        bail!("--qa-layout only works with --format markdown");
+    }
+    if args.stream
+        && (args.format != output::OutputFormat::Markdown || args.sort == output::PostOrder::Likes)
+    {
+        bail!("--stream only works with --format markdown, in stream order");
+    }
+    if args.merge_json && args.format != output::OutputFormat::Json {
+        bail!("--merge-json only works with --format json");
+    }
+    if args.compress_output.is_some() && args.format == output::OutputFormat::Sqlite {
+        bail!("--compress-output doesn't work with --format sqlite");
+    }
+    if args.output.as_deref() == Some(STDOUT_PATH) {
+        if args.format == output::OutputFormat::Sqlite
+            || args.shard_size.is_some()
+            || args.max_tokens.is_some()
+            || args.merge_json
+        {
+            bail!("--output - doesn't work with --format sqlite, --shard-size, --max-tokens or --merge-json");
+        }
+        if args.compress_output.is_some() && std::io::stdout().is_terminal() {
+            bail!("Refusing to write compressed output to a terminal; redirect or pipe it");
+        }
+    }
+    if args.collapse_code && args.format != output::OutputFormat::Text {
+        bail!("--collapse-code only works with --format text");
+    }
+    if args.sort == output::PostOrder::Likes && args.threaded {
+        bail!("--sort likes doesn't work with --threaded");
+    }
+    if args.anchor_post_links
+        && !matches!(
+            args.format,
+            output::OutputFormat::Markdown
+                | output::OutputFormat::Chat
+                | output::OutputFormat::Html
+                | output::OutputFormat::Org
+        )
+    {
+        bail!("--anchor-post-links only works with --format markdown, chat, html or org");
+    }
+    if args.citations
+        && !matches!(
+            args.format,
+            output::OutputFormat::Markdown | output::OutputFormat::Chat
+        )
+    {
+        bail!("--citations only works with --format markdown or chat");
+    }
+    let markdown_headings = [
+        (args.heading_level.is_some(), "--heading-level"),
+        (args.post_header.is_some(), "--post-header"),
+        (args.post_separator.is_some(), "--post-separator"),
+    ];
+    for (set, flag) in markdown_headings {
+        if set && args.format != output::OutputFormat::Markdown {
+            bail!("{} only works with --format markdown", flag);
+        }
+    }
+    if args.pandoc && args.format == output::OutputFormat::YamlBlocks {
+        // Pandoc would read each post's block as more metadata
+        bail!("--pandoc doesn't work with --format yaml-blocks");
+    }
+    if args.static_site && args.format != output::OutputFormat::Markdown {
+        bail!("--static-site only works with --format markdown");
+    }
+    if args.group_size.is_some() && args.format == output::OutputFormat::YamlBlocks {
+        bail!("--group-size doesn't work with --format yaml-blocks");
+    }
+    if args.group_size.is_some() && args.heading_level == Some(6) {
+        // Posts go a level below their group's heading, and Markdown stops at six
+        bail!("--group-size needs a --heading-level of 5 or less");
+    }
+    if args.count_tokens && args.format.is_binary() {
+        bail!("--count-tokens only works with the text formats");
+    }
+    if args.checksum_output && args.format.is_binary() {
+        bail!("--checksum-output only works with the text formats");
+    }
+    if args.diff_against.is_some() && args.format != output::OutputFormat::Markdown {
+        // The previous document is read back by its `## Post #N` headings
+        bail!("--diff-against only works with --format markdown");
+    }
+    if !args.format.is_markdown() {
+        let markdown_only = [
+            (args.shard_size.is_some(), "--shard-size"),
+            (args.split_posts.is_some(), "--split-posts"),
+            (args.max_tokens.is_some(), "--max-tokens"),
+            (args.group_size.is_some(), "--group-size"),
+            (
+                !args.sections.is_empty() || args.digest || args.toc || args.participant_summary,
+                "--sections/--digest/--toc/--participant-summary",
+            ),
+            (args.follow_internal_links, "--follow-internal-links"),
+            (args.frontmatter, "--frontmatter"),
+            (args.pandoc, "--pandoc"),
+            (args.permalinks, "--permalinks"),
+            (args.threaded, "--threaded"),
+        ];
+        if let Some((_, flag)) = markdown_only.iter().find(|(set, _)| *set) {
+            bail!("{} only works with the Markdown formats", flag);
+        }
+    }
+
+    if let Some(path) = &args.template {
+        // Fail on a broken template before fetching anything
+        template::Template::load(path)?;
+    }
+
+    if args.print_slug {
+        return print_slugs(&args);
+    }
+
+    if let Some(dir) = &args.llms_txt {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory {}", dir))?;
+    }
+
+    let started_at = chrono::Utc::now();
+    let start = Instant::now();
+    let results = batch::run(&args.urls, args.topic_concurrency, |url| {
+        scrape_topic(&args, url)
+    });
+
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+    for (url, result) in results {
+        match result {
+            Ok(report) => reports.push(report),
+            Err(e) => failures.push((url, e)),
+        }
+    }
+
+    if show_summary(&args) {
+        let color = std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+        summary::print(&reports, &failures, start.elapsed(), color);
+    }
+
+    if let Some(dir) = &args.llms_txt {
+        llms::write(std::path::Path::new(dir), &reports)?;
+        if !args.quiet && !show_summary(&args) {
+            eprintln!(
+                "Index written to {}",
+                std::path::Path::new(dir).join("llms.txt").display()
+            );
+        }
+    }
+
+    if let Some(path) = &args.manifest {
+        let manifest = manifest::Manifest {
+            started_at,
+            elapsed_seconds: start.elapsed().as_secs_f64(),
+            topics: reports,
+            failed_topics: failures
+                .iter()
+                .map(|(url, e)| manifest::FailedTopic {
+                    url: url.clone(),
+                    error: format!("{:#}", e),
+                })
+                .collect(),
+        };
+        manifest.write(path)?;
+    }
+
+    if args.urls.len() == 1 {
+        if let Some((_, e)) = failures.pop() {
+            return Err(e);
+        }
+        return Ok(());
+    }
+    for (url, e) in &failures {
+        eprintln!("Failed to scrape {}: {:#}", url, e);
+    }
+    if !failures.is_empty() {
+        bail!("{} of {} topics failed", failures.len(), args.urls.len());
+    }
+
+    Ok(())
+}
+
+/// Remove cached topics last fetched more than `older_than` days ago.
+fn compact_cache(older_than: u64, dry_run: bool) -> Result<()> {
+    let threshold = chrono::Utc::now() - chrono::Duration::days(older_than as i64);
+    let mut removed = 0;
+    let mut freed = 0;
+    for topic in cache::list_topics()? {
+        if topic.last_fetched.is_some_and(|t| t >= threshold) {
+            continue;
+        }
+        let last_fetched = topic
+            .last_fetched
+            .map_or("never".to_string(), |t| t.format("%Y-%m-%d").to_string());
+        println!(
+            "{} {}/{} (last fetched {}, {})",
+            if dry_run { "Would remove" } else { "Removing" },
+            topic.domain,
+            topic.topic_id,
+            last_fetched,
+            format_size(topic.bytes)
+        );
+        if !dry_run {
+            std::fs::remove_dir_all(&topic.dir)
+                .with_context(|| format!("Failed to remove {:?}", topic.dir))?;
+        }
+        removed += 1;
+        freed += topic.bytes;
+    }
+    println!(
+        "{} {} topic(s), {}",
+        if dry_run { "Would free" } else { "Freed" },
+        removed,
+        format_size(freed)
+    );
+    Ok(())
+}
+
+/// Make every cached topic directory consistent; see [`cache::migrate_topic`].
+fn migrate_cache(dry_run: bool) -> Result<()> {
+    let mut changes = 0;
+    for topic in cache::list_topics()? {
+        let migration = cache::migrate_topic(&topic.dir, dry_run)?;
+        let label = format!("{}/{}", topic.domain, topic.topic_id);
+        for (from, to) in &migration.renamed {
+            println!(
+                "{}: {} {:?} -> {:?}",
+                label,
+                if dry_run { "would rename" } else { "renamed" },
+                from.file_name().unwrap_or_default(),
+                to.file_name().unwrap_or_default()
+            );
+        }
+        for path in &migration.removed {
+            println!(
+                "{}: {} {:?}",
+                label,
+                if dry_run { "would remove" } else { "removed" },
+                path.file_name().unwrap_or_default()
+            );
+        }
+        for path in &migration.unreadable {
+            eprintln!("Warning: {}: cannot parse {:?}, leaving it", label, path);
+        }
+        changes += migration.renamed.len() + migration.removed.len();
+    }
+    println!(
+        "{} {} change(s)",
+        if dry_run { "Would make" } else { "Made" },
+        changes
+    );
+    Ok(())
+}
+
+/// Format a byte count for humans, e.g. `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+fn parse_rate(s: &str) -> Result<f64> {
+    let rate: f64 = s.parse().context("not a number")?;
+    if !(rate.is_finite() && rate > 0.0) {
+        bail!("must be greater than 0");
+    }
+    Ok(rate)
+}
+
+fn parse_post_header(s: &str) -> Result<String> {
+    let placeholder = regex::Regex::new(r"\{(\w+)\}").expect("placeholder regex is valid");
+    for caps in placeholder.captures_iter(s) {
+        if !output::POST_HEADER_FIELDS.contains(&&caps[1]) {
+            bail!(
+                "unknown placeholder `{{{}}}`; use {}",
+                &caps[1],
+                output::POST_HEADER_FIELDS
+                    .map(|f| format!("{{{}}}", f))
+                    .join(", ")
+            );
+        }
+    }
+    Ok(s.to_string())
+}
+
+fn parse_date_bound(s: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    dates::parse_bound(s, chrono::Utc::now())
+}
+
+/// The base URL requests are sent to: `--base-url` if given, else the topic URL's.
+fn api_base_url(args: &Args, base_url: &str) -> String {
+    match &args.base_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => base_url.to_string(),
+    }
+}
+
+/// Whether to end the run with a summary: only for interactive, non-quiet runs.
+fn show_summary(args: &Args) -> bool {
+    !args.quiet && std::io::stderr().is_terminal()
+}
+
+/// Print the filename slug of each topic's title, one per line, without fetching posts.
+fn print_slugs(args: &Args) -> Result<()> {
+    for url in &args.urls {
+        let (base_url, topic_id) =
+            discourse::parse_topic_url(url).context("Failed to parse Discourse thread URL")?;
+        let topic = discourse::fetch_topic(&api_base_url(args, &base_url), topic_id)
+            .context("Failed to fetch topic")?;
+        println!("{}", output::slugify(&topic.title));
+    }
+    Ok(())
+}
+
+/// Scrape a single topic and write its rendered output.
+fn scrape_topic(args: &Args, url: &str) -> Result<manifest::TopicReport> {
+    let start = Instant::now();
     let (base_url, topic_id) =
-        discourse::parse_topic_url(&args.url).context("Failed to parse Discourse thread URL")?;
+        discourse::parse_topic_url(url).context("Failed to parse Discourse thread URL")?;
+
+    let api_base = api_base_url(args, &base_url);
 
     if args.verbose {
         eprintln!("Base URL: {}", base_url);
+        if api_base != base_url {
+            eprintln!("API base URL: {}", api_base);
+        }
         eprintln!("Topic ID: {}", topic_id);
     }
 
+    let robots = if args.respect_robots {
+        let robots = match discourse::fetch_robots_txt(&api_base)? {
+            Some(text) => robots::Robots::parse(&text),
+            None => robots::Robots::default(),
+        };
+        for path in [
+            format!("/t/{}.json", topic_id),
+            format!("/t/{}/posts.json", topic_id),
+        ] {
+            if !robots.is_allowed(&path) {
+                bail!("{}/robots.txt disallows {}", api_base, path);
+            }
+        }
+        Some(robots)
+    } else {
+        None
+    };
+
     // Fetch topic metadata and post stream
     if args.verbose {
         eprintln!("Fetching topic metadata...");
     }
-    let topic = discourse::fetch_topic(&base_url, topic_id).context("Failed to fetch topic")?;
+    let (topic, mut all_post_ids) = match args.single_post {
+        Some(post_number) => {
+            let (topic, post) = discourse::fetch_post_by_number(&api_base, topic_id, post_number)
+                .context("Failed to fetch post")?;
+            (topic, vec![post.id])
+        }
+        None => {
+            let topic =
+                discourse::fetch_topic(&api_base, topic_id).context("Failed to fetch topic")?;
+            let ids = topic.post_stream.stream.clone();
+            (topic, ids)
+        }
+    };
 
     if args.verbose {
         eprintln!("Topic: {}", topic.title);
         eprintln!("Total posts: {}", topic.post_stream.stream.len());
     }
+    if let Some(warning) = topic.archetype_warning() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    limit_post_ids(args, &mut all_post_ids);
+
+    // Old or merged topic URLs redirect to the canonical topic; key everything on that
+    let topic_id = if topic.id != topic_id {
+        eprintln!(
+            "Warning: topic {} redirected to topic {} ('{}'); using the canonical ID",
+            topic_id, topic.id, topic.title
+        );
+        topic.id
+    } else {
+        topic_id
+    };
 
     // Set up cache
     let domain = url::Url::parse(&base_url)
@@ -58,33 +1059,56 @@ fn main() -> Result<()> {
         .unwrap_or_else(|_| "unknown".to_string());
     let cache = cache::Cache::new(&domain, topic_id)?;
 
-    let cache_threshold = chrono::Utc::now() - chrono::Duration::days(args.cache_days as i64);
-
     // Build a map of post_id -> PostData from inline posts in the topic response
     let mut post_data_by_id: HashMap<u64, discourse::PostData> = HashMap::new();
     for post in &topic.post_stream.posts {
         post_data_by_id.insert(post.id, post.clone());
     }
 
-    // Figure out which post IDs we still need to fetch
-    // (not in inline posts AND not cached or cache is stale)
-    let all_post_ids = &topic.post_stream.stream;
+    // First pass: load fresh cache entries, collect IDs that need metadata
+    let mut missing_posts: Vec<output::FailedPost> = Vec::new();
+    let (cached_by_id, known_missing) =
+        load_cached_posts(args, &cache, &all_post_ids, &mut missing_posts)?;
+    let ids_to_fetch: Vec<u64> = all_post_ids
+        .iter()
+        .copied()
+        .filter(|id| !known_missing.contains(id) && !cached_by_id.contains_key(id))
+        .filter(|id| !post_data_by_id.contains_key(id))
+        .collect();
 
-    // First pass: check cache for all posts, collect IDs that need fetching
-    let mut ids_to_fetch: Vec<u64> = Vec::new();
-    for &post_id in all_post_ids {
-        if post_data_by_id.contains_key(&post_id) {
-            // We have inline data — still need to check cache for raw content
-            continue;
+    if !known_missing.is_empty() {
+        if args.verbose {
+            eprintln!("Skipping {} posts known to be deleted", known_missing.len());
         }
-        // Check cache by post_id
-        if let Some(cached) = cache.load_by_id(post_id)? {
-            if cached.created_at < cache_threshold {
-                // Old enough, trust cache — no need to fetch
-                continue;
-            }
+        all_post_ids.retain(|id| !known_missing.contains(id));
+    }
+
+    // Warn before starting if the run is likely to trip the forum's rate limits
+    let uncached = (all_post_ids.len() - cached_by_id.len()) as u64;
+    let requests = uncached + ids_to_fetch.len().div_ceil(discourse::BATCH_SIZE) as u64;
+    let topics_in_parallel = if args.urls.len() > 1 {
+        args.topic_concurrency
+            .clamp(1, batch::MAX_TOPICS_PER_DOMAIN) as u64
+    } else {
+        1
+    };
+    let mut parallel = topics_in_parallel * args.post_concurrency.max(1) as u64;
+    let mut delay = Duration::from_millis(args.delay_ms.min(args.batch_delay_ms));
+    // The limiter is shared, so all workers together stay under the rate
+    let rate_delay = Duration::from_secs_f64(1.0 / args.rate);
+    if rate_delay > delay / parallel as u32 {
+        parallel = 1;
+        delay = rate_delay;
+    }
+    if let Some(risk) = discourse::rate_limit_risk(requests, delay, parallel) {
+        eprintln!(
+            "Warning: '{}' needs {} requests, about {}; the forum may rate-limit or ban your IP",
+            topic.title, requests, risk
+        );
+        let interactive = args.urls.len() == 1 && std::io::stdin().is_terminal();
+        if !args.yes && interactive && !confirm("Continue anyway?")? {
+            bail!("Aborted");
         }
-        ids_to_fetch.push(post_id);
     }
 
     // Batch-fetch metadata for posts we don't have inline
@@ -95,81 +1119,1756 @@ fn main() -> Result<()> {
                 ids_to_fetch.len()
             );
         }
-        let fetched = discourse::fetch_posts_by_ids(&base_url, topic_id, &ids_to_fetch)
-            .context("Failed to batch-fetch posts")?;
+        let fetched = discourse::fetch_posts_by_ids(
+            &api_base,
+            topic_id,
+            &ids_to_fetch,
+            Duration::from_millis(args.batch_delay_ms),
+        )
+        .context("Failed to batch-fetch posts")?;
         for post in fetched {
             post_data_by_id.insert(post.id, post);
         }
     }
 
-    // Now iterate through all posts in order, fetching raw content as needed
-    let mut posts: Vec<cache::CachedPost> = Vec::new();
-    let total = all_post_ids.len();
-
-    for (i, &post_id) in all_post_ids.iter().enumerate() {
-        // Check cache first (keyed by post_id)
-        if let Some(cached) = cache.load_by_id(post_id)? {
-            if cached.created_at < cache_threshold {
-                if args.verbose {
-                    eprintln!(
-                        "[{}/{}] Post #{} (id={}) cached, skipping",
-                        i + 1,
-                        total,
-                        cached.post_number,
-                        post_id
-                    );
-                }
-                posts.push(cached);
-                continue;
+    // Apply the user filter (and pull in reply context) before fetching raw content
+    let summaries = all_post_ids
+        .iter()
+        .map(|&post_id| {
+            if let Some(cached) = cached_by_id.get(&post_id) {
+                Ok(PostSummary {
+                    post_id,
+                    post_number: cached.post_number,
+                    username: cached.username.clone(),
+                    created_at: cached.created_at,
+                    reply_to_post_number: cached.reply_to_post_number,
+                })
+            } else {
+                let post_data = post_data_by_id
+                    .get(&post_id)
+                    .with_context(|| format!("No metadata for post id={}", post_id))?;
+                Ok(PostSummary {
+                    post_id,
+                    post_number: post_data.post_number,
+                    username: post_data.username.clone(),
+                    created_at: post_data.created_at,
+                    reply_to_post_number: post_data.reply_to_post_number,
+                })
             }
-        }
-
-        // Get post metadata
-        let post_data = post_data_by_id
-            .get(&post_id)
-            .with_context(|| format!("No metadata for post id={}", post_id))?;
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut post_authors: HashMap<u64, String> = summaries
+        .iter()
+        .map(|s| (s.post_number, s.username.clone()))
+        .collect();
+    let (selected_ids, context_ids) = select_posts(
+        &summaries,
+        &args.users,
+        (args.since, args.until),
+        args.fetch_replies_context,
+    );
 
-        // Fetch raw markdown via /raw/{topic_id}/{post_number}
-        if args.verbose {
-            eprintln!(
-                "[{}/{}] Fetching raw post #{} (id={})...",
-                i + 1,
-                total,
-                post_data.post_number,
-                post_id
-            );
-        }
-        let raw = discourse::fetch_raw_post(&base_url, topic_id, post_data.post_number)
-            .with_context(|| {
-                format!(
-                    "Failed to fetch raw content for post #{}",
-                    post_data.post_number
-                )
-            })?;
+    let filtered = !args.users.is_empty() || args.since.is_some() || args.until.is_some();
+    if args.verbose && filtered {
+        eprintln!(
+            "Selected {} of {} posts ({} as reply context)",
+            selected_ids.len(),
+            all_post_ids.len(),
+            context_ids.len()
+        );
+    }
+    if selected_ids.is_empty() && args.exit_on_empty {
+        bail!("No posts in '{}' matched the filters", topic.title);
+    }
 
-        let cached_post = cache::CachedPost {
-            post_number: post_data.post_number,
-            post_id: post_data.id,
-            username: post_data.username.clone(),
-            created_at: post_data.created_at,
-            raw,
-            fetched_at: chrono::Utc::now(),
-        };
+    if args.stream {
+        return stream_topic(
+            args,
+            url,
+            &api_base,
+            &topic,
+            &cache,
+            robots.as_ref(),
+            &selected_ids,
+            cached_by_id,
+            &mut post_data_by_id,
+            missing_posts,
+            output::RenderOptions {
+                format: args.format,
+                context_post_ids: context_ids,
+                category: match topic.category_id {
+                    Some(category_id) => resolve_category(&cache, &api_base, category_id, args),
+                    None => None,
+                },
+                topic_url: discourse::topic_url(&base_url, &topic.slug, topic_id),
+                metadata_separators: args.post_separator_with_metadata,
+                normalize_whitespace: args.normalize_whitespace,
+                repair_markdown: args.repair_markdown,
+                reproducible: args.reproducible,
+                slug: topic.slug.clone(),
+                permalinks: args.permalinks,
+                post_authors,
+                heading_level: args.heading_level.map(|n| n as usize),
+                post_header: args.post_header.clone(),
+                post_separator: args.post_separator.clone(),
+                dates: dates::DateFormat {
+                    timezone: args.timezone,
+                    pattern: args.date_format.clone(),
+                },
+                accepted_answer: topic.accepted_answer.as_ref().map(|a| a.post_number),
+                ..Default::default()
+            },
+            start,
+        );
+    }
 
-        cache.save(&cached_post)?;
-        posts.push(cached_post);
+    // Now iterate through the selected posts in order, fetching raw content as needed
+    let mut posts = Vec::new();
+    let (cache_hits, cache_misses) = fetch_selected_posts(
+        args,
+        &api_base,
+        &topic,
+        &cache,
+        robots.as_ref(),
+        &selected_ids,
+        cached_by_id,
+        &mut post_data_by_id,
+        &mut missing_posts,
+        |post| {
+            posts.push(post);
+            Ok(())
+        },
+    )?;
 
-        // Small delay to be respectful to the server
-        std::thread::sleep(std::time::Duration::from_millis(200));
+    if args.tag_code_from_cooked {
+        tag_code_from_cooked(args, &api_base, topic_id, &mut posts, &post_data_by_id);
     }
 
-    // Generate output
-    let rendered = output::render(&topic.title, &args.url, &posts);
+    if args.exclude_quoted_only {
+        let before = posts.len();
+        posts.retain(|post| !output::is_quote_only(&post.raw));
+        let dropped = before - posts.len();
+        if dropped > 0 && !args.quiet {
+            eprintln!("Dropped {} quote-only post(s)", dropped);
+        }
+    }
 
-    let output_path = args.output.unwrap_or_else(|| format!("{}.md", topic.title));
-    std::fs::write(&output_path, &rendered)
-        .with_context(|| format!("Failed to write output to {}", output_path))?;
-    eprintln!("Output written to {}", output_path);
+    if args.strip_quotes || args.collapse_quotes {
+        for post in &mut posts {
+            post.raw = if args.strip_quotes {
+                output::strip_quotes(&post.raw)
+            } else {
+                output::collapse_quotes(&post.raw, topic_id)
+            };
+        }
+    }
 
-    Ok(())
+    if args.sort_by_number {
+        output::sort_by_post_number(&mut posts);
+    }
+    if let Some(n) = args.top {
+        output::keep_most_liked(&mut posts, n as usize);
+    }
+    if args.sort == output::PostOrder::Likes {
+        output::sort_by_likes(&mut posts);
+    }
+    if args.anchor_post_links {
+        let numbers: HashSet<u64> = posts.iter().map(|post| post.post_number).collect();
+        for post in &mut posts {
+            post.raw = links::anchor_post_links(&post.raw, &base_url, topic_id, &numbers);
+            post.cooked = post
+                .cooked
+                .as_deref()
+                .map(|cooked| links::anchor_post_hrefs(cooked, &base_url, topic_id, &numbers));
+        }
+    }
+    if args.dedupe_quotes {
+        let deduped = output::dedupe_quotes(&mut posts, topic_id);
+        if !args.quiet && deduped > 0 {
+            eprintln!(
+                "Replaced {} repeated quote(s) with a back-reference",
+                deduped
+            );
+        }
+    }
+
+    for post in &mut posts {
+        post.raw = polls::render_results(&post.raw, &post.polls);
+    }
+
+    if args.resolve_link_titles {
+        let titles = resolve_link_titles(
+            &cache,
+            &base_url,
+            &api_base,
+            topic_id,
+            &topic.title,
+            &posts,
+            args,
+        );
+        for post in &mut posts {
+            post.raw = links::rewrite_topic_links(&post.raw, &base_url, &titles);
+        }
+    }
+
+    if let Some(dir) = &args.download_images {
+        let downloads = download_files(
+            args,
+            dir,
+            "image",
+            &base_url,
+            &api_base,
+            &posts,
+            uploads::image_references,
+        )?;
+        let locations = download_locations(dir, &downloads);
+        for post in &mut posts {
+            post.raw = uploads::rewrite_images(&post.raw, &locations);
+        }
+    }
+
+    if let Some(dir) = &args.download_attachments {
+        let downloads = download_files(
+            args,
+            dir,
+            "attachment",
+            &base_url,
+            &api_base,
+            &posts,
+            |raw| uploads::attachment_references(raw, &base_url),
+        )?;
+        let locations = download_locations(dir, &downloads);
+        for post in &mut posts {
+            post.raw = uploads::rewrite_links(&post.raw, &locations);
+        }
+        let entries: Vec<uploads::ManifestEntry> = downloads
+            .into_iter()
+            .map(|d| uploads::ManifestEntry {
+                file: d.name,
+                url: d.url,
+                topic_url: discourse::topic_url(&base_url, &topic.slug, topic_id),
+                post_number: d.post_number,
+            })
+            .collect();
+        uploads::update_manifest(dir, entries)?;
+    }
+
+    if args.render_emoji {
+        for post in &mut posts {
+            post.raw = emoji::render_shortcodes(&post.raw);
+        }
+    }
+
+    if args.strip_emoji {
+        for post in &mut posts {
+            post.raw = emoji::strip_shortcodes(&post.raw);
+        }
+    }
+
+    if args.render_mentions_as_links {
+        for post in &mut posts {
+            post.raw = links::link_mentions(&post.raw, &base_url);
+        }
+    }
+
+    if args.normalize_markup {
+        for post in &mut posts {
+            let titles = post
+                .cooked
+                .as_deref()
+                .map(markup::onebox_titles)
+                .unwrap_or_default();
+            post.raw = markup::normalize(&post.raw, &base_url, &titles);
+        }
+    }
+
+    let mut redactions = args.redact_pii.then(redact::Redactions::default);
+    if let Some(redactions) = &mut redactions {
+        for post in &mut posts {
+            redactions.add(redact_post(post));
+        }
+    }
+
+    let mut pseudonyms = args.anonymize.then(anonymize::Pseudonyms::default);
+    if let Some(pseudonyms) = &mut pseudonyms {
+        for post in &mut posts {
+            pseudonyms.anonymize_post(post);
+        }
+        let mut numbers: Vec<u64> = post_authors.keys().copied().collect();
+        numbers.sort_unstable();
+        for number in numbers {
+            let author = post_authors.get_mut(&number).expect("numbers are keys");
+            *author = pseudonyms.get(author);
+        }
+    }
+
+    let mut stale_warning = None;
+    let newest = posts.iter().map(|p| p.created_at).max();
+    if let (Some(days), Some(newest)) = (args.stale_warning_days, newest) {
+        let age = (chrono::Utc::now() - newest).num_days();
+        if age > days as i64 {
+            let warning = format!("Most recent post is {} old", output::describe_age(age));
+            eprintln!(
+                "Warning: {} ({}, {})",
+                warning,
+                newest.format("%Y-%m-%d"),
+                topic.title
+            );
+            stale_warning = Some(warning);
+        }
+    }
+
+    // Generate output
+    let category = match topic.category_id {
+        Some(category_id) => resolve_category(&cache, &api_base, category_id, args),
+        None => None,
+    };
+
+    missing_posts.sort_unstable_by_key(|f| f.post_number);
+    let render_options = output::RenderOptions {
+        format: args.format,
+        context_post_ids: context_ids,
+        category,
+        topic_url: discourse::topic_url(&base_url, &topic.slug, topic_id),
+        metadata_separators: args.post_separator_with_metadata,
+        normalize_whitespace: args.normalize_whitespace,
+        repair_markdown: args.repair_markdown,
+        // The age in the warning changes from day to day
+        stale_warning: stale_warning.filter(|_| !args.reproducible),
+        reproducible: args.reproducible,
+        participants: if args.participants
+            || args.sections.contains(&output::Section::Roster)
+            || args.participant_summary
+            || args.template.is_some()
+            || args.frontmatter
+            || args.pandoc
+            || args.static_site
+        {
+            output::participant_roster(&posts, args.sort_participants)
+        } else {
+            Vec::new()
+        },
+        sections: if args.toc || args.digest || args.participant_summary {
+            [
+                Some(output::Section::Header),
+                args.participant_summary.then_some(output::Section::Roster),
+                args.toc.then_some(output::Section::Toc),
+                args.digest.then_some(output::Section::Digest),
+                Some(output::Section::Posts),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        } else {
+            args.sections.clone()
+        },
+        qa_layout: args.qa_layout,
+        failed_posts: missing_posts.clone(),
+        group_size: args.group_size.map(|n| n as usize),
+        collapse_code: args.collapse_code,
+        frontmatter: args.frontmatter || args.pandoc || args.static_site,
+        pandoc: args.pandoc,
+        static_site: args.static_site,
+        slug: topic.slug.clone(),
+        tags: topic
+            .tags
+            .iter()
+            .map(|tag| tag.name().to_string())
+            .collect(),
+        permalinks: args.permalinks,
+        post_authors,
+        threaded: args.threaded,
+        post_anchors: args.anchor_post_links,
+        citations: args.citations,
+        heading_level: args.heading_level.map(|n| n as usize),
+        post_header: args.post_header.clone(),
+        post_separator: args.post_separator.clone(),
+        dates: dates::DateFormat {
+            timezone: args.timezone,
+            pattern: args.date_format.clone(),
+        },
+        accepted_answer: topic.accepted_answer.as_ref().map(|a| a.post_number),
+    };
+
+    if let Some(previous_path) = &args.diff_against {
+        let previous = std::fs::read_to_string(previous_path)
+            .with_context(|| format!("Failed to read {}", previous_path))?;
+        let changes = diff::compare(&diff::parse_rendered_posts(&previous), &posts, |post| {
+            output::post_body(post, &render_options)
+        });
+        eprintln!("Changes since {}: {}", previous_path, changes.summary());
+        for (label, numbers) in [("New", &changes.new), ("Edited", &changes.edited)] {
+            if !numbers.is_empty() {
+                let list: Vec<String> = numbers.iter().map(|n| format!("#{}", n)).collect();
+                eprintln!("  {}: {}", label, list.join(", "));
+            }
+        }
+        if args.changes_only {
+            posts.retain(|post| {
+                changes.new.contains(&post.post_number)
+                    || changes.edited.contains(&post.post_number)
+            });
+        }
+    }
+
+    let mut linked = if args.follow_internal_links {
+        follow_internal_links(
+            args,
+            &domain,
+            &base_url,
+            &api_base,
+            robots.as_ref(),
+            topic_id,
+            &posts,
+        )
+    } else {
+        Vec::new()
+    };
+    for post in linked.iter_mut().flat_map(|topic| &mut topic.posts) {
+        if let Some(pseudonyms) = &mut pseudonyms {
+            pseudonyms.anonymize_post(post);
+        }
+        if let Some(redactions) = &mut redactions {
+            redactions.add(redact_post(post));
+        }
+    }
+    if let Some(redactions) = redactions.filter(|_| !args.quiet) {
+        eprintln!("Redacted {}", redactions);
+    }
+    let appendix = (!linked.is_empty()).then(|| output::render_appendix(&linked, &render_options));
+
+    let mut output_paths = Vec::new();
+    let mut output_bytes = 0;
+    let chunks = if let Some(shard_size) = args.shard_size {
+        Some(posts.chunks(shard_size as usize).collect())
+    } else if let Some(max_tokens) = args.max_tokens {
+        let (chunks, oversized) = output::token_chunks(
+            &topic.title,
+            url,
+            &posts,
+            max_tokens as usize,
+            &render_options,
+        );
+        if !oversized.is_empty() {
+            let list: Vec<String> = oversized.iter().map(|n| format!("#{}", n)).collect();
+            eprintln!(
+                "Warning: posts {} don't fit in {} tokens on their own; each got a part anyway",
+                list.join(", "),
+                max_tokens
+            );
+        }
+        Some(chunks)
+    } else {
+        None
+    };
+    let split = if let Some(chunks) = chunks {
+        let output_dir = match &args.output {
+            Some(template) => output::expand_output_path(template, &topic.title, topic_id),
+            None => topic.title.clone(),
+        };
+        let shards = output::render_shards(&topic.title, url, &posts, &chunks, &render_options);
+        Some((output_dir, shards, "parts"))
+    } else {
+        args.split_posts.as_ref().map(|dir| {
+            let output_dir = output::expand_output_path(dir, &topic.title, topic_id);
+            let files = output::render_post_files(&topic.title, url, &posts, &render_options);
+            (output_dir, files, "posts")
+        })
+    };
+    let mut token_count = None;
+    if let Some((output_dir, mut shards, unit)) = split {
+        std::fs::create_dir_all(&output_dir)
+            .with_context(|| format!("Failed to create output directory {}", output_dir))?;
+        // Everything but the index
+        let count = shards.len() - 1;
+        if let Some(appendix) = appendix {
+            shards.push(("appendix.md".to_string(), appendix));
+        }
+        for (file_name, content) in &shards {
+            let path = std::path::Path::new(&output_dir).join(file_name);
+            write_output(&path, content.as_bytes())?;
+            if args.checksum_output {
+                eprintln!("{}  {}", output::checksum(content), path.display());
+            }
+            output_paths.push(path.to_string_lossy().into_owned());
+            output_bytes += content.len() as u64;
+        }
+        if args.count_tokens {
+            token_count = Some(
+                shards
+                    .iter()
+                    .map(|(_, content)| tokens::count(content))
+                    .sum(),
+            );
+        }
+        if !args.quiet && !show_summary(args) {
+            eprintln!("Output written to {} ({} {})", output_dir, count, unit);
+        }
+    } else if args.format == output::OutputFormat::Sqlite {
+        let output_path = match &args.output {
+            Some(template) => output::expand_output_path(template, &topic.title, topic_id),
+            None => format!("{}.{}", topic.title, args.format.extension()),
+        };
+        sqlite::export(
+            std::path::Path::new(&output_path),
+            topic_id,
+            &topic.title,
+            url,
+            &posts,
+        )?;
+        if !args.quiet && !show_summary(args) {
+            eprintln!("Output written to {}", output_path);
+        }
+        output_bytes += std::fs::metadata(&output_path).map_or(0, |m| m.len());
+        output_paths.push(output_path);
+    } else {
+        let template = args
+            .template
+            .as_deref()
+            .map(template::Template::load)
+            .transpose()?;
+        let extension = match &template {
+            Some(template) => template.extension(),
+            None => args.format.extension(),
+        };
+        let mut output_path = match (&args.output, &args.llms_txt) {
+            (Some(template), _) => output::expand_output_path(template, &topic.title, topic_id),
+            (None, Some(dir)) => std::path::Path::new(dir)
+                .join(output::expand_output_path(
+                    "{topic_id}-{title_slug}.md",
+                    &topic.title,
+                    topic_id,
+                ))
+                .to_string_lossy()
+                .into_owned(),
+            (None, None) => format!("{}.{}", topic.title, extension),
+        };
+        let to_stdout = output_path == STDOUT_PATH;
+        let compression = args
+            .compress_output
+            .or_else(|| output::Compression::for_path(&output_path));
+        if let (Some(compression), false) = (compression, to_stdout) {
+            let extension = format!(".{}", compression.extension());
+            if !output_path.ends_with(&extension) {
+                output_path.push_str(&extension);
+            }
+        }
+        let (rendered, checksum) = if let Some(template) = &template {
+            let rendered = template.render(&topic.title, url, &posts, &render_options)?;
+            let checksum = args.checksum_output.then(|| output::checksum(&rendered));
+            (rendered.into_bytes(), checksum)
+        } else if args.format == output::OutputFormat::Epub {
+            (
+                epub::render(&topic.title, url, &posts, &render_options),
+                None,
+            )
+        } else if args.format == output::OutputFormat::Pdf {
+            (
+                pdf::render(&topic.title, url, &posts, &render_options),
+                None,
+            )
+        } else {
+            let mut rendered = output::render(&topic.title, url, &posts, &render_options);
+            if let Some(appendix) = appendix {
+                rendered.push_str(&appendix);
+            }
+            if args.merge_json {
+                match std::fs::read_to_string(&output_path) {
+                    Ok(existing) => {
+                        rendered = output::merge_json(&existing, &rendered)
+                            .with_context(|| format!("Failed to merge into {}", output_path))?;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to read {}", output_path));
+                    }
+                }
+            }
+            let checksum = args.checksum_output.then(|| output::checksum(&rendered));
+            (rendered.into_bytes(), checksum)
+        };
+
+        if args.count_tokens {
+            token_count = Some(tokens::count(&String::from_utf8_lossy(&rendered)));
+        }
+        if to_stdout {
+            output_bytes += write_stdout(&rendered, compression)?;
+        } else {
+            let rendered = match compression {
+                Some(compression) => compress(Vec::new(), &rendered, compression)?,
+                None => rendered,
+            };
+            write_output(std::path::Path::new(&output_path), &rendered)?;
+            if !args.quiet && !show_summary(args) {
+                eprintln!("Output written to {}", output_path);
+            }
+            output_bytes += rendered.len() as u64;
+        }
+        if let Some(checksum) = checksum {
+            eprintln!("{}  {}", checksum, output_path);
+        }
+        output_paths.push(output_path);
+    }
+    if let Some(total) = token_count {
+        eprintln!("Tokens: {} ({})", total, tokens::ENCODING);
+        for post in &posts {
+            let body = output::post_body(post, &render_options);
+            eprintln!(
+                "  Post #{} by @{}: {}",
+                post.post_number,
+                post.username,
+                tokens::count(&body)
+            );
+        }
+    }
+
+    Ok(manifest::TopicReport {
+        url: url.to_string(),
+        base_url,
+        topic_id,
+        title: topic.title,
+        preview: posts
+            .first()
+            .and_then(|post| output::excerpt(&post.raw, PREVIEW_CHARS)),
+        posts: posts.len(),
+        cache_hits,
+        cache_misses,
+        failed_posts: missing_posts.iter().map(|f| f.post_number).collect(),
+        output_paths,
+        output_bytes,
+        tokens: token_count,
+        redactions,
+        elapsed_seconds: start.elapsed().as_secs_f64(),
+    })
+}
+
+/// Write the selected posts of a topic to its Markdown document as they're
+/// fetched (`--stream`): the header first, then each post as soon as every
+/// earlier one is in, so the document can be followed while a large topic is
+/// scraped and posts aren't held in memory. Deleted posts are noted at the end.
+#[allow(clippy::too_many_arguments)]
+fn stream_topic(
+    args: &Args,
+    url: &str,
+    api_base: &str,
+    topic: &discourse::Topic,
+    cache: &cache::Cache,
+    robots: Option<&robots::Robots>,
+    selected_ids: &[u64],
+    cached_by_id: HashMap<u64, cache::CachedPost>,
+    post_data_by_id: &mut HashMap<u64, discourse::PostData>,
+    mut missing_posts: Vec<output::FailedPost>,
+    render_options: output::RenderOptions,
+    start: Instant,
+) -> Result<manifest::TopicReport> {
+    let (base_url, _) = discourse::parse_topic_url(url)?;
+    let output_path = match &args.output {
+        Some(template) => output::expand_output_path(template, &topic.title, topic.id),
+        None => format!("{}.{}", topic.title, args.format.extension()),
+    };
+    let write_error = || format!("Failed to write output to {}", output_path);
+    let mut out: Box<dyn Write> = if output_path == STDOUT_PATH {
+        Box::new(std::io::stdout().lock())
+    } else {
+        let file = std::fs::File::create(&output_path).with_context(write_error)?;
+        Box::new(std::io::BufWriter::new(file))
+    };
+    let mut write = |text: &str| -> Result<()> {
+        out.write_all(text.as_bytes()).with_context(write_error)?;
+        // Flush every post, so the document can be followed as it grows
+        out.flush().with_context(write_error)
+    };
+
+    let header = output::render_stream_header(&topic.title, url, &render_options);
+    write(&header)?;
+    let mut output_bytes = header.len() as u64;
+    let mut streamed = 0;
+    let mut preview = None;
+    let (cache_hits, cache_misses) = fetch_selected_posts(
+        args,
+        api_base,
+        topic,
+        cache,
+        robots,
+        selected_ids,
+        cached_by_id,
+        post_data_by_id,
+        &mut missing_posts,
+        |mut post| {
+            post.raw = polls::render_results(&post.raw, &post.polls);
+            if streamed == 0 {
+                preview = output::excerpt(&post.raw, PREVIEW_CHARS);
+            }
+            let rendered = output::render_stream_post(&post, &render_options);
+            write(&rendered)?;
+            output_bytes += rendered.len() as u64;
+            streamed += 1;
+            Ok(())
+        },
+    )?;
+    missing_posts.sort_unstable_by_key(|f| f.post_number);
+    for failed in &missing_posts {
+        let annotation = output::failure_annotation(failed);
+        write(&annotation)?;
+        output_bytes += annotation.len() as u64;
+    }
+    if !args.quiet && !show_summary(args) && output_path != STDOUT_PATH {
+        eprintln!("Output written to {}", output_path);
+    }
+
+    Ok(manifest::TopicReport {
+        url: url.to_string(),
+        base_url,
+        topic_id: topic.id,
+        title: topic.title.clone(),
+        preview,
+        posts: streamed,
+        cache_hits,
+        cache_misses,
+        failed_posts: missing_posts.iter().map(|f| f.post_number).collect(),
+        output_paths: vec![output_path],
+        output_bytes,
+        tokens: None,
+        redactions: None,
+        elapsed_seconds: start.elapsed().as_secs_f64(),
+    })
+}
+
+/// Redact personal data from a post's Markdown and HTML, counting what was
+/// redacted from the Markdown.
+fn redact_post(post: &mut cache::CachedPost) -> redact::Redactions {
+    let (raw, counts) = redact::redact(&post.raw);
+    post.raw = raw;
+    if let Some(cooked) = &post.cooked {
+        post.cooked = Some(redact::redact(cooked).0);
+    }
+    counts
+}
+
+/// Write `bytes` to `out` compressed as a `.gz` or `.zst` stream, and return `out`.
+fn compress<W: Write>(out: W, bytes: &[u8], compression: output::Compression) -> Result<W> {
+    match compression {
+        output::Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+            encoder.write_all(bytes).context("Failed to compress")?;
+            encoder.finish().context("Failed to compress")
+        }
+        output::Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(out, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .context("Failed to compress")?;
+            encoder.write_all(bytes).context("Failed to compress")?;
+            encoder.finish().context("Failed to compress")
+        }
+    }
+}
+
+/// The `--output` that writes the document to standard output.
+const STDOUT_PATH: &str = "-";
+
+/// A writer that counts the bytes passing through it.
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write a document to standard output, through the encoder for `compression`
+/// if given. Returns how many bytes were written.
+fn write_stdout(bytes: &[u8], compression: Option<output::Compression>) -> Result<u64> {
+    let error = || "Failed to write output to standard output";
+    let mut out = CountingWriter {
+        inner: std::io::stdout().lock(),
+        bytes: 0,
+    };
+    match compression {
+        Some(compression) => out = compress(out, bytes, compression)?,
+        None => out.write_all(bytes).with_context(error)?,
+    }
+    out.flush().with_context(error)?;
+    Ok(out.bytes)
+}
+
+/// Write an output file through a temporary file beside it, so an interrupted
+/// run never leaves a truncated document (or archive) in its place.
+pub(crate) fn write_output(path: &std::path::Path, bytes: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let error = || format!("Failed to write output to {}", path.display());
+    std::fs::write(&tmp, bytes).with_context(error)?;
+    std::fs::rename(&tmp, path).with_context(error)
+}
+
+/// Length of the first-post preview recorded in the manifest.
+const PREVIEW_CHARS: usize = 160;
+
+/// How long the cached `/site.json` category mapping is trusted.
+const SITE_CACHE_DAYS: i64 = 30;
+
+/// The time before which cached posts are trusted rather than re-fetched.
+fn cache_threshold(args: &Args) -> chrono::DateTime<chrono::Utc> {
+    let mut threshold = chrono::Utc::now() - chrono::Duration::days(args.cache_days as i64);
+    if let Some(days) = args.force_refresh_newer {
+        // Posts from the last `days` days are re-fetched even if cache-days would trust them
+        threshold = threshold.min(chrono::Utc::now() - chrono::Duration::days(days as i64));
+    }
+    threshold
+}
+
+/// Load the fresh cache entries for `post_ids`, keyed by post ID. Posts recorded
+/// as deleted within `--missing-cache-days` are added to `missing_posts` and
+/// returned too, so they aren't requested again.
+fn load_cached_posts(
+    args: &Args,
+    cache: &cache::Cache,
+    post_ids: &[u64],
+    missing_posts: &mut Vec<output::FailedPost>,
+) -> Result<(HashMap<u64, cache::CachedPost>, HashSet<u64>)> {
+    let cache_threshold = cache_threshold(args);
+    let missing_threshold =
+        chrono::Utc::now() - chrono::Duration::days(args.missing_cache_days as i64);
+    let mut cached_by_id: HashMap<u64, cache::CachedPost> = HashMap::new();
+    let mut known_missing: HashSet<u64> = HashSet::new();
+    for &post_id in post_ids {
+        if args.missing_cache_days > 0 {
+            if let Some(missing) = cache.load_missing(post_id)? {
+                if missing.checked_at > missing_threshold {
+                    known_missing.insert(post_id);
+                    missing_posts.push(output::FailedPost {
+                        post_number: missing.post_number,
+                        reason: format!(
+                            "HTTP 404 (as of {})",
+                            missing.checked_at.format("%Y-%m-%d")
+                        ),
+                    });
+                    continue;
+                }
+            }
+        }
+        if let Some(cached) = cache.load_by_id(post_id)? {
+            let traced = args.trace_post == Some(cached.post_number);
+            if cached.created_at < cache_threshold && !traced {
+                // Old enough, trust cache — no need to fetch
+                cached_by_id.insert(post_id, cached);
+                continue;
+            }
+        }
+    }
+    Ok((cached_by_id, known_missing))
+}
+
+/// Assemble the `selected_ids` posts of `topic` and hand them to `emit` in
+/// order, each as soon as every earlier one is in: from `cached_by_id`, from
+/// the content inline in their metadata, or by fetching it on
+/// `--post-concurrency` workers (or from whole `/raw/` pages with `--bulk-raw`)
+/// and caching the result. Deleted posts are added to `missing_posts`. Returns
+/// how many of the emitted posts came from the cache, and how many did not.
+#[allow(clippy::too_many_arguments)]
+fn fetch_selected_posts(
+    args: &Args,
+    api_base: &str,
+    topic: &discourse::Topic,
+    cache: &cache::Cache,
+    robots: Option<&robots::Robots>,
+    selected_ids: &[u64],
+    mut cached_by_id: HashMap<u64, cache::CachedPost>,
+    post_data_by_id: &mut HashMap<u64, discourse::PostData>,
+    missing_posts: &mut Vec<output::FailedPost>,
+    mut emit: impl FnMut(cache::CachedPost) -> Result<()>,
+) -> Result<(usize, usize)> {
+    let topic_id = topic.id;
+    let total = selected_ids.len();
+    let mut cache_hits = 0;
+    let mut cache_misses = 0;
+    let mut raw_fetcher = discourse::RawFetcher::new(api_base, topic_id);
+    if let Some(robots) = robots {
+        if !robots.is_allowed(&format!("/raw/{}/1", topic_id)) {
+            if !robots.is_allowed("/posts/1.json") {
+                bail!("{}/robots.txt disallows both /raw/ and /posts/", api_base);
+            }
+            eprintln!("Notice: robots.txt disallows /raw/; using /posts/{{id}}.json instead");
+            raw_fetcher.skip_raw_endpoint();
+        }
+    }
+
+    if args.bulk_raw {
+        let needed: HashMap<u64, u64> = selected_ids
+            .iter()
+            .filter(|id| !cached_by_id.contains_key(id))
+            .filter_map(|id| post_data_by_id.get(id))
+            .filter(|p| p.raw.is_none() && args.trace_post != Some(p.post_number))
+            .map(|p| (p.post_number, p.id))
+            .collect();
+        let raw_allowed = robots.is_none_or(|r| r.is_allowed(&format!("/raw/{}", topic_id)));
+        if !needed.is_empty() && raw_allowed {
+            let filled = fill_raw_from_topic_pages(
+                args,
+                api_base,
+                topic_id,
+                &topic.post_stream.stream,
+                &needed,
+                post_data_by_id,
+            );
+            if args.verbose {
+                eprintln!(
+                    "Got raw content for {} of {} posts from /raw/{}",
+                    filled,
+                    needed.len(),
+                    topic_id
+                );
+            }
+        }
+    }
+
+    // Posts whose raw content has to be requested: not cached and not inline
+    let fetch_ids: HashSet<u64> = selected_ids
+        .iter()
+        .copied()
+        .filter(|id| !cached_by_id.contains_key(id))
+        .filter(|id| {
+            post_data_by_id
+                .get(id)
+                .is_some_and(|p| p.raw.is_none() || args.trace_post == Some(p.post_number))
+        })
+        .collect();
+
+    // Fetch raw content on worker threads via /raw/{topic_id}/{post_number} (or its
+    // fallback); cache and collect the posts here, in stream order
+    let fetch = |i: usize, post_id: &u64| {
+        let post_data = post_data_by_id.get(post_id)?;
+        if !fetch_ids.contains(post_id) {
+            return None;
+        }
+        if args.verbose {
+            eprintln!(
+                "[{}/{}] Fetching raw post #{} (id={})...",
+                i + 1,
+                total,
+                post_data.post_number,
+                post_id
+            );
+        }
+        let result = if args.trace_post == Some(post_data.post_number) {
+            discourse::trace_raw_post(api_base, topic_id, post_data.post_number)
+        } else {
+            raw_fetcher.fetch(post_data)
+        };
+        // Any extra delay on top of the shared rate limit
+        std::thread::sleep(Duration::from_millis(args.delay_ms));
+        Some(result)
+    };
+    let collect = |i: usize, fetched: Option<Result<String>>| -> Result<()> {
+        let post_id = selected_ids[i];
+        // Serve from cache if it was fresh enough
+        if let Some(cached) = cached_by_id.remove(&post_id) {
+            if args.verbose {
+                eprintln!(
+                    "[{}/{}] Post #{} (id={}) cached, skipping",
+                    i + 1,
+                    total,
+                    cached.post_number,
+                    post_id
+                );
+            }
+            cache_hits += 1;
+            return emit(cached);
+        }
+
+        let post_data = post_data_by_id
+            .get(&post_id)
+            .with_context(|| format!("No metadata for post id={}", post_id))?;
+
+        let raw = match (fetched, &post_data.raw) {
+            (None, Some(raw)) => {
+                if args.verbose {
+                    eprintln!(
+                        "[{}/{}] Post #{} (id={}) content inline",
+                        i + 1,
+                        total,
+                        post_data.post_number,
+                        post_id
+                    );
+                }
+                raw.clone()
+            }
+            (Some(Ok(raw)), _) => raw,
+            (Some(Err(e)), _) if discourse::is_not_found(&e) => {
+                eprintln!(
+                    "Warning: post #{} (id={}) is gone (HTTP 404), skipping",
+                    post_data.post_number, post_id
+                );
+                if args.missing_cache_days > 0 {
+                    cache.save_missing(post_id, post_data.post_number)?;
+                }
+                missing_posts.push(output::FailedPost {
+                    post_number: post_data.post_number,
+                    reason: "HTTP 404".to_string(),
+                });
+                return Ok(());
+            }
+            (Some(Err(e)), _) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Failed to fetch raw content for post #{}",
+                        post_data.post_number
+                    )
+                })
+            }
+            (None, None) => unreachable!("posts without inline content are fetched"),
+        };
+
+        let cached_post = cache::CachedPost {
+            post_number: post_data.post_number,
+            post_id: post_data.id,
+            username: post_data.username.clone(),
+            created_at: post_data.created_at,
+            reply_to_post_number: post_data.reply_to_post_number,
+            raw,
+            fetched_at: chrono::Utc::now(),
+            action_code: post_data.action_code.clone(),
+            cooked: post_data.cooked.clone(),
+            like_count: post_data.like_count(),
+            reactions: post_data.reactions.clone(),
+            polls: post_data.polls.clone(),
+            role: post_data.role().map(str::to_string),
+        };
+
+        cache.save(&cached_post)?;
+        cache_misses += 1;
+        emit(cached_post)
+    };
+    batch::run_ordered(selected_ids, args.post_concurrency, fetch, collect)?;
+    Ok((cache_hits, cache_misses))
+}
+
+/// Fill in the raw content of the `needed` posts (post number -> post ID) from
+/// `/raw/{topic_id}` pages, which carry many posts each. Only the pages covering
+/// those posts' positions in `stream` are requested.
+///
+/// Returns how many posts were filled in. Stops at the first page that fails or
+/// holds no posts, leaving the rest to be fetched one by one.
+fn fill_raw_from_topic_pages(
+    args: &Args,
+    api_base: &str,
+    topic_id: u64,
+    stream: &[u64],
+    needed: &HashMap<u64, u64>,
+    post_data_by_id: &mut HashMap<u64, discourse::PostData>,
+) -> usize {
+    let pages: std::collections::BTreeSet<usize> = needed
+        .values()
+        .filter_map(|id| stream.iter().position(|s| s == id))
+        .map(|position| position / discourse::RAW_TOPIC_PAGE_SIZE + 1)
+        .collect();
+
+    let mut filled = 0;
+    for (i, page) in pages.into_iter().enumerate() {
+        if i > 0 {
+            std::thread::sleep(Duration::from_millis(args.delay_ms));
+        }
+        if args.verbose {
+            eprintln!(
+                "Fetching raw content page {} of topic {}...",
+                page, topic_id
+            );
+        }
+        let result = discourse::fetch_topic_raw_page(api_base, topic_id, page);
+        let posts = match result {
+            Ok(posts) if !posts.is_empty() => posts,
+            Ok(_) => {
+                eprintln!(
+                    "Notice: /raw/{} returned no posts; fetching posts one by one",
+                    topic_id
+                );
+                break;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Notice: /raw/{} is unavailable ({:#}); fetching posts one by one",
+                    topic_id, e
+                );
+                break;
+            }
+        };
+        for (post_number, raw) in posts {
+            let post_data = needed
+                .get(&post_number)
+                .and_then(|id| post_data_by_id.get_mut(id));
+            if let Some(post_data) = post_data {
+                post_data.raw = Some(raw);
+                filled += 1;
+            }
+        }
+    }
+    filled
+}
+
+/// Tag untagged code fences with the languages Discourse gave the post's code
+/// blocks in its cooked HTML. Posts served from the cache have their cooked HTML
+/// fetched when they have untagged fences.
+fn tag_code_from_cooked(
+    args: &Args,
+    api_base: &str,
+    topic_id: u64,
+    posts: &mut [cache::CachedPost],
+    post_data_by_id: &HashMap<u64, discourse::PostData>,
+) {
+    let untagged: Vec<&cache::CachedPost> = posts
+        .iter()
+        .filter(|p| markdown::has_untagged_fence(&p.raw))
+        .collect();
+    let ids_to_fetch: Vec<u64> = untagged
+        .iter()
+        .map(|p| p.post_id)
+        .filter(|id| post_data_by_id.get(id).is_none_or(|d| d.cooked.is_none()))
+        .collect();
+
+    let mut cooked: HashMap<u64, String> = untagged
+        .iter()
+        .filter_map(|p| {
+            post_data_by_id
+                .get(&p.post_id)?
+                .cooked
+                .clone()
+                .map(|c| (p.post_id, c))
+        })
+        .collect();
+    if !ids_to_fetch.is_empty() {
+        if args.verbose {
+            eprintln!("Fetching cooked HTML for {} posts...", ids_to_fetch.len());
+        }
+        let delay = Duration::from_millis(args.batch_delay_ms);
+        match discourse::fetch_posts_by_ids(api_base, topic_id, &ids_to_fetch, delay) {
+            Ok(fetched) => cooked.extend(
+                fetched
+                    .into_iter()
+                    .filter_map(|p| p.cooked.map(|c| (p.id, c))),
+            ),
+            Err(e) => eprintln!("Warning: could not fetch cooked HTML: {:#}", e),
+        }
+    }
+
+    for post in posts {
+        if let Some(html) = cooked.get(&post.post_id) {
+            let languages = markdown::cooked_code_languages(html);
+            post.raw = markdown::tag_fences(&post.raw, &languages);
+        }
+    }
+}
+
+/// Scrape the topics linked from `posts`, breadth-first up to `--depth` links
+/// away and at most `--max-linked-topics` of them. Each topic is scraped once,
+/// so link cycles end there. Topics that fail are skipped with a warning.
+fn follow_internal_links(
+    args: &Args,
+    domain: &str,
+    base_url: &str,
+    api_base: &str,
+    robots: Option<&robots::Robots>,
+    topic_id: u64,
+    posts: &[cache::CachedPost],
+) -> Vec<output::LinkedTopic> {
+    let mut seen = HashSet::from([topic_id]);
+    let mut ids: Vec<u64> = posts
+        .iter()
+        .flat_map(|p| links::find_topic_ids(&p.raw, base_url))
+        .collect();
+    let mut linked = Vec::new();
+
+    for depth in 1..=args.depth {
+        let mut next = Vec::new();
+        for id in ids {
+            if !seen.insert(id) {
+                continue;
+            }
+            if linked.len() >= args.max_linked_topics {
+                eprintln!(
+                    "Warning: reached --max-linked-topics ({}); not following further links",
+                    args.max_linked_topics
+                );
+                return linked;
+            }
+            std::thread::sleep(Duration::from_millis(args.delay_ms));
+            if args.verbose {
+                eprintln!("Following link to topic {} (depth {})...", id, depth);
+            }
+            match fetch_linked_topic(args, domain, api_base, robots, id) {
+                Ok((topic, topic_posts)) => {
+                    // A redirect may land on a topic we already have
+                    if topic.id != id && !seen.insert(topic.id) {
+                        continue;
+                    }
+                    next.extend(
+                        topic_posts
+                            .iter()
+                            .flat_map(|p| links::find_topic_ids(&p.raw, base_url)),
+                    );
+                    linked.push(output::LinkedTopic {
+                        url: discourse::topic_url(base_url, &topic.slug, topic.id),
+                        title: topic.title,
+                        posts: topic_posts,
+                    });
+                }
+                Err(e) => eprintln!("Warning: skipping linked topic {}: {:#}", id, e),
+            }
+        }
+        ids = next;
+    }
+    linked
+}
+
+/// Fetch every post of a linked topic the way the main topic's are fetched:
+/// using and filling the cache, and honouring `--respect-robots`,
+/// `--post-concurrency` and `--bulk-raw`. Deleted posts are skipped.
+fn fetch_linked_topic(
+    args: &Args,
+    domain: &str,
+    api_base: &str,
+    robots: Option<&robots::Robots>,
+    topic_id: u64,
+) -> Result<(discourse::Topic, Vec<cache::CachedPost>)> {
+    let path = format!("/t/{}.json", topic_id);
+    if robots.is_some_and(|robots| !robots.is_allowed(&path)) {
+        bail!("{}/robots.txt disallows {}", api_base, path);
+    }
+    let topic = discourse::fetch_topic(api_base, topic_id)?;
+    let cache = cache::Cache::new(domain, topic.id)?;
+
+    let mut missing_posts = Vec::new();
+    let (cached_by_id, known_missing) =
+        load_cached_posts(args, &cache, &topic.post_stream.stream, &mut missing_posts)?;
+    let mut post_data_by_id: HashMap<u64, discourse::PostData> = topic
+        .post_stream
+        .posts
+        .iter()
+        .map(|p| (p.id, p.clone()))
+        .collect();
+    let ids_to_fetch: Vec<u64> = topic
+        .post_stream
+        .stream
+        .iter()
+        .copied()
+        .filter(|id| !known_missing.contains(id) && !cached_by_id.contains_key(id))
+        .filter(|id| !post_data_by_id.contains_key(id))
+        .collect();
+    if !ids_to_fetch.is_empty() {
+        let fetched = discourse::fetch_posts_by_ids(
+            api_base,
+            topic.id,
+            &ids_to_fetch,
+            Duration::from_millis(args.batch_delay_ms),
+        )?;
+        post_data_by_id.extend(fetched.into_iter().map(|p| (p.id, p)));
+    }
+
+    // Posts missing from the metadata too have nothing to show
+    let post_ids: Vec<u64> = topic
+        .post_stream
+        .stream
+        .iter()
+        .copied()
+        .filter(|id| cached_by_id.contains_key(id) || post_data_by_id.contains_key(id))
+        .collect();
+    let mut posts = Vec::new();
+    fetch_selected_posts(
+        args,
+        api_base,
+        &topic,
+        &cache,
+        robots,
+        &post_ids,
+        cached_by_id,
+        &mut post_data_by_id,
+        &mut missing_posts,
+        |post| {
+            posts.push(post);
+            Ok(())
+        },
+    )?;
+    Ok((topic, posts))
+}
+
+/// Resolve a category ID to its name, using the per-domain site cache.
+///
+/// Category names are cosmetic, so failures are reported and otherwise ignored.
+fn resolve_category(
+    cache: &cache::Cache,
+    base_url: &str,
+    category_id: u64,
+    args: &Args,
+) -> Option<String> {
+    let fresh_after = chrono::Utc::now() - chrono::Duration::days(SITE_CACHE_DAYS);
+    let cached = if args.refresh_site_cache {
+        None
+    } else {
+        cache
+            .load_site()
+            .unwrap_or_else(|e| {
+                eprintln!("Warning: ignoring unreadable site cache: {:#}", e);
+                None
+            })
+            .filter(|site| site.fetched_at > fresh_after)
+    };
+    // A category created since the cache was filled warrants a refresh
+    let site = match cached.filter(|site| site.categories.contains_key(&category_id)) {
+        Some(site) => site,
+        None => {
+            if args.verbose {
+                eprintln!("Fetching site categories...");
+            }
+            let categories = match discourse::fetch_site_categories(base_url) {
+                Ok(categories) => categories,
+                Err(e) => {
+                    eprintln!("Warning: could not resolve category name: {:#}", e);
+                    return None;
+                }
+            };
+            let site = cache::CachedSite {
+                categories,
+                fetched_at: chrono::Utc::now(),
+            };
+            if let Err(e) = cache.save_site(&site) {
+                eprintln!("Warning: {:#}", e);
+            }
+            site
+        }
+    };
+    site.categories.get(&category_id).cloned()
+}
+
+/// Look up the titles of all topics linked from `posts`, using the per-domain title cache.
+///
+/// Links are matched against `base_url`; titles are fetched from `api_base`.
+/// Topics that can't be fetched (private, deleted) are skipped.
+fn resolve_link_titles(
+    cache: &cache::Cache,
+    base_url: &str,
+    api_base: &str,
+    topic_id: u64,
+    title: &str,
+    posts: &[cache::CachedPost],
+    args: &Args,
+) -> HashMap<u64, String> {
+    let mut titles = cache.load_topic_titles().unwrap_or_else(|e| {
+        eprintln!("Warning: ignoring unreadable topic title cache: {:#}", e);
+        HashMap::new()
+    });
+    titles.insert(topic_id, title.to_string());
+
+    let mut missing: Vec<u64> = Vec::new();
+    for post in posts {
+        for id in links::find_topic_ids(&post.raw, base_url) {
+            if !titles.contains_key(&id) && !missing.contains(&id) {
+                missing.push(id);
+            }
+        }
+    }
+
+    for (i, &id) in missing.iter().enumerate() {
+        if i > 0 {
+            std::thread::sleep(Duration::from_millis(args.delay_ms));
+        }
+        if args.verbose {
+            eprintln!("Looking up title of linked topic {}...", id);
+        }
+        match discourse::fetch_topic(api_base, id) {
+            Ok(linked) => {
+                titles.insert(id, linked.title);
+            }
+            Err(e) => {
+                if args.verbose {
+                    eprintln!("Could not look up topic {}: {:#}", id, e);
+                }
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        if let Err(e) = cache.save_topic_titles(&titles) {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+    titles
+}
+
+/// A file a post references, downloaded by [`download_files`].
+struct Download {
+    /// The reference as written in the post
+    reference: String,
+    url: String,
+    /// The file's name in the download directory
+    name: String,
+    post_number: u64,
+}
+
+/// Download the files that `find` picks out of each post into `dir`, returning
+/// them by reference. Files already in `dir` aren't fetched again; ones that
+/// fail are reported and left out, so their references stay as they are.
+fn download_files(
+    args: &Args,
+    dir: &std::path::Path,
+    what: &str,
+    base_url: &str,
+    api_base: &str,
+    posts: &[cache::CachedPost],
+    find: impl Fn(&str) -> Vec<String>,
+) -> Result<Vec<Download>> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {} directory {}", what, dir.display()))?;
+    let mut downloads: Vec<Download> = Vec::new();
+    let mut fetched = 0;
+    for post in posts {
+        for reference in find(&post.raw) {
+            if downloads.iter().any(|d| d.reference == reference) {
+                continue;
+            }
+            let Some(url) = uploads::resolve(&reference, base_url) else {
+                continue;
+            };
+            let name = uploads::local_name(&url);
+            let path = dir.join(&name);
+            if !path.exists() {
+                if fetched > 0 {
+                    std::thread::sleep(Duration::from_millis(args.delay_ms));
+                }
+                // Fetch the forum's own uploads from where the API is reached
+                let fetch_url = match url.strip_prefix(base_url) {
+                    Some(rest) => format!("{}{}", api_base, rest),
+                    None => url.clone(),
+                };
+                if args.verbose {
+                    eprintln!("Downloading {} {}...", what, url);
+                }
+                fetched += 1;
+                let bytes = match discourse::fetch_file(api_base, &fetch_url) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        eprintln!(
+                            "Warning: could not download {} in post #{}: {:#}",
+                            what, post.post_number, e
+                        );
+                        continue;
+                    }
+                };
+                std::fs::write(&path, bytes)
+                    .with_context(|| format!("Failed to write {} {}", what, path.display()))?;
+            }
+            downloads.push(Download {
+                reference,
+                url,
+                name,
+                post_number: post.post_number,
+            });
+        }
+    }
+    if !args.quiet && fetched > 0 {
+        eprintln!("Downloaded {} {}(s) to {}", fetched, what, dir.display());
+    }
+    Ok(downloads)
+}
+
+/// Where the document links to a downloaded file.
+fn download_locations(dir: &std::path::Path, downloads: &[Download]) -> HashMap<String, String> {
+    downloads
+        .iter()
+        .map(|d| {
+            let location = format!("{}/{}", dir.display(), d.name).replace(' ', "%20");
+            (d.reference.clone(), location)
+        })
+        .collect()
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Just enough about a post to decide whether it gets rendered.
+struct PostSummary {
+    post_id: u64,
+    post_number: u64,
+    username: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    reply_to_post_number: Option<u64>,
+}
+
+/// Drop the posts `--known-count` says the caller already has, then cap the
+/// rest at `--max-posts`, keeping the oldest or, with `--posts-newest-first`,
+/// the newest.
+fn limit_post_ids(args: &Args, post_ids: &mut Vec<u64>) {
+    // Delta scrape: the caller already has the first N posts of the stream
+    if let Some(known) = args.known_count {
+        let known = (known as usize).min(post_ids.len());
+        post_ids.drain(..known);
+        if args.verbose {
+            eprintln!("Skipping {} known posts, {} new", known, post_ids.len());
+        }
+    }
+
+    // Cap the posts before anything is fetched, so the budget goes where the user wants it
+    if let Some(max) = args.max_posts {
+        let skipped = post_ids.len().saturating_sub(max as usize);
+        if args.posts_newest_first {
+            post_ids.drain(..skipped);
+        } else {
+            post_ids.truncate(max as usize);
+        }
+        if args.verbose && skipped > 0 {
+            let which = if args.posts_newest_first {
+                "oldest"
+            } else {
+                "newest"
+            };
+            eprintln!("Skipping the {} {} posts (--max-posts)", skipped, which);
+        }
+    }
+}
+
+/// Select the posts to render, in stream order.
+///
+/// With no filters every post is selected. Otherwise only posts by the given
+/// users (if any) created within `since..until` (where given) are kept, and
+/// with `replies_context` the posts they reply to are added back, even if the
+/// filters would drop them. Returns the selected post IDs and the subset
+/// included only as context.
+fn select_posts(
+    summaries: &[PostSummary],
+    users: &[String],
+    (since, until): (
+        Option<chrono::DateTime<chrono::Utc>>,
+        Option<chrono::DateTime<chrono::Utc>>,
+    ),
+    replies_context: bool,
+) -> (Vec<u64>, HashSet<u64>) {
+    if users.is_empty() && since.is_none() && until.is_none() {
+        return (
+            summaries.iter().map(|s| s.post_id).collect(),
+            HashSet::new(),
+        );
+    }
+
+    let matches = |s: &PostSummary| {
+        (users.is_empty() || users.iter().any(|u| u.eq_ignore_ascii_case(&s.username)))
+            && since.is_none_or(|since| s.created_at >= since)
+            && until.is_none_or(|until| s.created_at < until)
+    };
+
+    let mut context_numbers: HashSet<u64> = HashSet::new();
+    if replies_context {
+        for s in summaries.iter().filter(|s| matches(s)) {
+            if let Some(n) = s.reply_to_post_number {
+                context_numbers.insert(n);
+            }
+        }
+    }
+
+    let mut selected = Vec::new();
+    let mut context_ids = HashSet::new();
+    for s in summaries {
+        if matches(s) {
+            selected.push(s.post_id);
+        } else if context_numbers.contains(&s.post_number) {
+            selected.push(s.post_id);
+            context_ids.insert(s.post_id);
+        }
+    }
+    (selected, context_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(post_number: u64, username: &str, reply_to: Option<u64>) -> PostSummary {
+        PostSummary {
+            post_id: 1000 + post_number,
+            post_number,
+            username: username.to_string(),
+            created_at: chrono::DateTime::parse_from_rfc3339("2026-02-20T10:00:00Z")
+                .unwrap()
+                .with_timezone(&chrono::Utc),
+            reply_to_post_number: reply_to,
+        }
+    }
+
+    fn args(flags: &[&str]) -> Args {
+        let mut argv = vec!["disc-scrape"];
+        argv.extend_from_slice(flags);
+        argv.push("https://discuss.example.com/t/topic/7");
+        Args::parse_from(argv)
+    }
+
+    #[test]
+    fn test_select_posts_without_users() {
+        let summaries = [summary(1, "alice", None), summary(2, "bob", Some(1))];
+        let (selected, context) = select_posts(&summaries, &[], (None, None), true);
+        assert_eq!(selected, [1001, 1002]);
+        assert!(context.is_empty());
+    }
+
+    #[test]
+    fn test_select_posts_reply_context() {
+        let summaries = [
+            summary(1, "alice", None),
+            summary(2, "carol", None),
+            summary(3, "Bob", Some(1)),
+            summary(4, "carol", Some(3)),
+        ];
+        let users = ["bob".to_string()];
+        let (selected, context) = select_posts(&summaries, &users, (None, None), false);
+        assert_eq!(selected, [1003]);
+        assert!(context.is_empty());
+
+        // The post bob replied to comes back, in stream order, as context only
+        let (selected, context) = select_posts(&summaries, &users, (None, None), true);
+        assert_eq!(selected, [1001, 1003]);
+        assert_eq!(context, HashSet::from([1001]));
+    }
+
+    #[test]
+    fn test_select_posts_date_range() {
+        let at = |day: u32| {
+            chrono::NaiveDate::from_ymd_opt(2026, 2, day)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+        };
+        let dated = |post_number, username, reply_to, day| PostSummary {
+            created_at: at(day),
+            ..summary(post_number, username, reply_to)
+        };
+        let summaries = [
+            dated(1, "alice", None, 1),
+            dated(2, "bob", Some(1), 10),
+            dated(3, "carol", Some(2), 20),
+        ];
+        let range = (Some(at(5)), Some(at(15)));
+        let (selected, context) = select_posts(&summaries, &[], range, false);
+        assert_eq!(selected, [1002]);
+        assert!(context.is_empty());
+
+        // Reply context reaches outside the range
+        let (selected, context) = select_posts(&summaries, &[], range, true);
+        assert_eq!(selected, [1001, 1002]);
+        assert_eq!(context, HashSet::from([1001]));
+
+        let users = ["carol".to_string()];
+        let (selected, _) = select_posts(&summaries, &users, range, true);
+        assert!(selected.is_empty());
+    }
+
+    #[test]
+    fn test_limit_post_ids() {
+        let ids = || (1..=10).collect::<Vec<u64>>();
+        let limited = |flags: &[&str]| {
+            let mut post_ids = ids();
+            limit_post_ids(&args(flags), &mut post_ids);
+            post_ids
+        };
+        assert_eq!(limited(&[]), ids());
+        assert_eq!(limited(&["--known-count", "7"]), [8, 9, 10]);
+        assert!(limited(&["--known-count", "20"]).is_empty());
+        assert_eq!(limited(&["--max-posts", "3"]), [1, 2, 3]);
+        assert_eq!(
+            limited(&["--max-posts", "3", "--posts-newest-first"]),
+            [8, 9, 10]
+        );
+        // Known posts are skipped before the cap applies
+        assert_eq!(limited(&["--known-count", "5", "--max-posts", "2"]), [6, 7]);
+    }
+
+    #[test]
+    fn test_fetch_selected_posts_warm_cache_with_top() {
+        let dir = std::env::temp_dir().join(format!("disc-scrape-top-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = cache::Cache::in_dir(dir.clone());
+        let created_at = chrono::DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        for n in 1..=3 {
+            let post = cache::CachedPost {
+                created_at,
+                like_count: n,
+                ..cache::test_post(n, "alice", "Hi")
+            };
+            cache.save(&post).unwrap();
+        }
+        let topic: discourse::Topic = serde_json::from_value(serde_json::json!({
+            "id": 7,
+            "title": "Topic",
+            "post_stream": { "stream": [1001, 1002, 1003] },
+        }))
+        .unwrap();
+
+        let args = args(&["--top", "1"]);
+        let ids = topic.post_stream.stream.clone();
+        let mut missing_posts = Vec::new();
+        let (cached_by_id, _) = load_cached_posts(&args, &cache, &ids, &mut missing_posts).unwrap();
+        let mut posts = Vec::new();
+        let counts = fetch_selected_posts(
+            &args,
+            "https://discuss.example.com",
+            &topic,
+            &cache,
+            None,
+            &ids,
+            cached_by_id,
+            &mut HashMap::new(),
+            &mut missing_posts,
+            |post| {
+                posts.push(post);
+                Ok(())
+            },
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(counts.unwrap(), (3, 0));
+        output::keep_most_liked(&mut posts, 1);
+        assert_eq!(posts.len(), 1);
+        assert!(missing_posts.is_empty());
+    }
+
+    #[test]
+    fn test_compress_counting() {
+        let document = "# Title\n\nBody\n".repeat(50);
+        for compression in [output::Compression::Gzip, output::Compression::Zstd] {
+            let out = CountingWriter {
+                inner: Vec::new(),
+                bytes: 0,
+            };
+            let out = compress(out, document.as_bytes(), compression).unwrap();
+            assert_eq!(out.bytes, out.inner.len() as u64);
+            assert!(out.inner.len() < document.len());
+            let decoded = match compression {
+                output::Compression::Gzip => {
+                    let mut decoded = String::new();
+                    std::io::Read::read_to_string(
+                        &mut flate2::read::GzDecoder::new(&out.inner[..]),
+                        &mut decoded,
+                    )
+                    .unwrap();
+                    decoded
+                }
+                output::Compression::Zstd => {
+                    String::from_utf8(zstd::decode_all(&out.inner[..]).unwrap()).unwrap()
+                }
+            };
+            assert_eq!(decoded, document);
+        }
+    }
 }
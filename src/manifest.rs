@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// What happened while scraping one topic.
+#[derive(Debug, Serialize)]
+pub struct TopicReport {
+    pub url: String,
+    pub base_url: String,
+    pub topic_id: u64,
+    pub title: String,
+    /// Plain-text preview of the first rendered post
+    pub preview: Option<String>,
+    /// Number of posts rendered
+    pub posts: usize,
+    /// Posts served from the cache
+    pub cache_hits: usize,
+    /// Posts whose raw content was downloaded
+    pub cache_misses: usize,
+    /// Post numbers that could not be fetched
+    pub failed_posts: Vec<u64>,
+    pub output_paths: Vec<String>,
+    pub output_bytes: u64,
+    /// Tokens in the output, with `--count-tokens`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>,
+    /// What `--redact-pii` redacted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redactions: Option<crate::redact::Redactions>,
+    pub elapsed_seconds: f64,
+}
+
+/// A topic that could not be scraped at all.
+#[derive(Debug, Serialize)]
+pub struct FailedTopic {
+    pub url: String,
+    pub error: String,
+}
+
+/// Machine-readable summary of a run, written by `--manifest`.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub elapsed_seconds: f64,
+    pub topics: Vec<TopicReport>,
+    pub failed_topics: Vec<FailedTopic>,
+}
+
+impl Manifest {
+    /// Write the manifest as pretty-printed JSON.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let data = serde_json::to_string_pretty(self).context("Failed to serialize manifest")?;
+        crate::write_output(std::path::Path::new(path), data.as_bytes())
+    }
+}
@@ -0,0 +1,223 @@
+/// A code fence's character (`` ` `` or `~`) and length.
+pub type Fence = (char, usize);
+
+/// Split a Markdown document into lines (keeping their line endings), each paired
+/// with whether it belongs to a fenced code block, fences included.
+pub fn classify_lines(text: &str) -> Vec<(&str, bool)> {
+    scan_fences(text).0
+}
+
+/// If `text` ends inside a fenced code block, return the opening fence's
+/// character and length.
+pub fn unclosed_fence(text: &str) -> Option<Fence> {
+    scan_fences(text).1
+}
+
+fn scan_fences(text: &str) -> (Vec<(&str, bool)>, Option<Fence>) {
+    let mut lines = Vec::new();
+    let mut fence: Option<Fence> = None;
+
+    for line in text.split_inclusive('\n') {
+        match (fence, fence_marker(line)) {
+            (None, Some(opening)) => fence = Some(opening),
+            (Some((ch, len)), Some((close_ch, close_len)))
+                if close_ch == ch && close_len >= len && is_closing_fence(line) =>
+            {
+                lines.push((line, true));
+                fence = None;
+                continue;
+            }
+            _ => {}
+        }
+        lines.push((line, fence.is_some()));
+    }
+
+    (lines, fence)
+}
+
+/// Apply `f` to the prose of a Markdown document, leaving code untouched.
+///
+/// `f` is called once per run of text outside fenced code blocks and inline code
+/// spans (never across a line break); everything else is copied verbatim.
+pub fn map_prose(text: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for (line, in_code) in classify_lines(text) {
+        if in_code {
+            out.push_str(line);
+            continue;
+        }
+        // Odd-numbered segments between backticks are inline code
+        for (i, segment) in line.split('`').enumerate() {
+            if i > 0 {
+                out.push('`');
+            }
+            if i % 2 == 0 {
+                out.push_str(&f(segment));
+            } else {
+                out.push_str(segment);
+            }
+        }
+    }
+
+    out
+}
+
+/// The opening line of a fenced code block.
+struct FenceOpening {
+    line: usize,
+    fence: Fence,
+    /// Text after the fence marker, e.g. the language
+    info: String,
+}
+
+fn fence_openings(text: &str) -> Vec<FenceOpening> {
+    let mut openings = Vec::new();
+    let mut fence: Option<Fence> = None;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        match (fence, fence_marker(line)) {
+            (None, Some(opening)) => {
+                fence = Some(opening);
+                let info = line.trim_start()[opening.1..].trim().to_string();
+                openings.push(FenceOpening {
+                    line: i,
+                    fence: opening,
+                    info,
+                });
+            }
+            (Some((ch, len)), Some((close_ch, close_len)))
+                if close_ch == ch && close_len >= len && is_closing_fence(line) =>
+            {
+                fence = None;
+            }
+            _ => {}
+        }
+    }
+    openings
+}
+
+/// Whether `text` has a fenced code block without a language.
+pub fn has_untagged_fence(text: &str) -> bool {
+    fence_openings(text).iter().any(|o| o.info.is_empty())
+}
+
+/// Give untagged fenced code blocks a language: the `n`th block in `text` gets
+/// `languages[n]`, if that is `Some`. Blocks that already have one keep it.
+///
+/// Does nothing unless `languages` has exactly one entry per block, since the
+/// blocks could not be matched up reliably otherwise.
+pub fn tag_fences(text: &str, languages: &[Option<String>]) -> String {
+    let openings = fence_openings(text);
+    if openings.len() != languages.len() {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut openings = openings.iter().zip(languages).peekable();
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        let Some((opening, language)) = openings.next_if(|(o, _)| o.line == i) else {
+            out.push_str(line);
+            continue;
+        };
+        match language {
+            Some(language) if opening.info.is_empty() => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let (ch, len) = opening.fence;
+                out.push_str(indent);
+                out.push_str(&ch.to_string().repeat(len));
+                out.push_str(language);
+                out.push_str(&line[line.trim_end().len()..]);
+            }
+            _ => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// Languages Discourse assigned to the code blocks of a post's cooked HTML, in
+/// order: `Some` for a `lang-xxx` class, `None` where it left detection to the
+/// browser (`lang-auto`) or has no highlighting.
+pub fn cooked_code_languages(cooked: &str) -> Vec<Option<String>> {
+    let re = regex::Regex::new(r#"<pre[^>]*>\s*<code(?:\s+class="([^"]*)")?"#)
+        .expect("code block regex is valid");
+    re.captures_iter(cooked)
+        .map(|caps| {
+            let classes = caps.get(1).map_or("", |m| m.as_str());
+            classes
+                .split_whitespace()
+                .filter_map(|class| class.strip_prefix("lang-"))
+                .find(|lang| !matches!(*lang, "auto" | "plaintext" | "nohighlight" | "text"))
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// The text of a snippet of HTML: its tags dropped, emoji images given as their
+/// `:shortcode:`, and the common entities decoded.
+pub fn html_text(html: &str) -> String {
+    let emoji =
+        regex::Regex::new(r#"<img[^>]*\btitle="(:[^"]+:)"[^>]*>"#).expect("emoji regex is valid");
+    let tag = regex::Regex::new(r"<[^>]*>").expect("tag regex is valid");
+    let text = emoji.replace_all(html, "$1");
+    tag.replace_all(&text, "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// If `line` opens or closes a code fence, return its fence character and length.
+pub fn fence_marker(line: &str) -> Option<Fence> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let ch = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|c| *c == ch).count();
+    (len >= 3).then_some((ch, len))
+}
+
+/// A closing fence carries no info string.
+fn is_closing_fence(line: &str) -> bool {
+    line.trim().chars().all(|c| c == '`' || c == '~')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_prose_skips_code() {
+        let text = "a `a` a\n```a\na\n```\na\n";
+        let out = map_prose(text, |s| s.replace('a', "b"));
+        assert_eq!(out, "b `a` b\n```a\na\n```\nb\n");
+    }
+
+    #[test]
+    fn test_tag_fences_from_cooked() {
+        let raw = "Try:\n```\nfn main() {}\n```\n  ~~~python\nx = 1\n  ~~~\n```\nplain\n```";
+        let cooked = "<p>Try:</p>\n<pre><code class=\"lang-rust\">fn main() {}\n</code></pre>\n\
+                      <pre data-code-wrap=\"py\"><code class=\"lang-python\">x = 1\n</code></pre>\n\
+                      <pre><code class=\"lang-auto\">plain\n</code></pre>";
+        let languages = cooked_code_languages(cooked);
+        assert_eq!(
+            languages,
+            [Some("rust".to_string()), Some("python".to_string()), None]
+        );
+        assert!(has_untagged_fence(raw));
+        assert_eq!(
+            tag_fences(raw, &languages),
+            "Try:\n```rust\nfn main() {}\n```\n  ~~~python\nx = 1\n  ~~~\n```\nplain\n```"
+        );
+        assert_eq!(tag_fences(raw, &languages[..1]), raw);
+    }
+
+    #[test]
+    fn test_map_prose_nested_fence_lengths() {
+        let text = "````\n```\na\n```\n````\na";
+        let out = map_prose(text, |s| s.replace('a', "b"));
+        assert_eq!(out, "````\n```\na\n```\n````\nb");
+    }
+}
@@ -0,0 +1,138 @@
+use crate::links;
+use crate::markdown;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+/// Convert Discourse-specific markup in a post to plain Markdown:
+///
+/// - A URL alone on a line, which Discourse shows as a onebox preview, becomes a
+///   link titled from `onebox_titles` (see [`onebox_titles`]), or an autolink.
+/// - `[details="Summary"]` blocks become `<details>` sections, collapsed as on
+///   the forum.
+/// - `upload://` short URLs become `{base_url}/uploads/short-url/…` links.
+/// - `@mentions` are escaped, so renderers that link them don't.
+///
+/// Code is left alone.
+pub fn normalize(raw: &str, base_url: &str, onebox_titles: &HashMap<String, String>) -> String {
+    let details_open =
+        Regex::new(r#"^\[details(?:=("?)([^\]"]*)"?)?\]$"#).expect("details regex is valid");
+    let mut out = String::with_capacity(raw.len());
+    let lines = markdown::classify_lines(raw);
+    let blank = |i: Option<usize>| {
+        i.and_then(|i| lines.get(i))
+            .is_none_or(|(line, _)| line.trim().is_empty())
+    };
+
+    for (i, (line, code)) in lines.iter().enumerate() {
+        if *code {
+            out.push_str(line);
+            continue;
+        }
+        let ending = &line[line.trim_end_matches(['\n', '\r']).len()..];
+        let content = line.trim();
+        let standalone = blank(i.checked_sub(1)) && blank(Some(i + 1));
+        if standalone && is_url(content) {
+            match onebox_titles.get(content) {
+                Some(title) => out.push_str(&format!(
+                    "[{}]({})",
+                    title.replace('[', "\\[").replace(']', "\\]"),
+                    content
+                )),
+                None => out.push_str(&format!("<{}>", content)),
+            }
+            out.push_str(ending);
+        } else if let Some(caps) = details_open.captures(content) {
+            let summary = caps.get(2).map_or("", |s| s.as_str()).trim();
+            let summary = if summary.is_empty() {
+                "Details"
+            } else {
+                summary
+            };
+            out.push_str(&format!("<details>\n<summary>{}</summary>\n\n", summary));
+        } else if content.eq_ignore_ascii_case("[/details]") {
+            out.push_str("\n</details>");
+            out.push_str(ending);
+        } else {
+            out.push_str(line);
+        }
+    }
+
+    let upload = Regex::new(r"upload://([A-Za-z0-9._-]+)").expect("upload regex is valid");
+    let mention = links::mention_regex();
+    markdown::map_prose(&out, |text| {
+        let text = upload.replace_all(text, |caps: &Captures| {
+            format!("{}/uploads/short-url/{}", base_url, &caps[1])
+        });
+        mention
+            .replace_all(&text, |caps: &Captures| {
+                format!("{}\\@{}", &caps["pre"], &caps["name"])
+            })
+            .into_owned()
+    })
+}
+
+fn is_url(text: &str) -> bool {
+    (text.starts_with("https://") || text.starts_with("http://"))
+        && !text.contains(char::is_whitespace)
+}
+
+/// The titles of the oneboxes in a post's cooked HTML, by the URL they preview.
+pub fn onebox_titles(cooked: &str) -> HashMap<String, String> {
+    let onebox = Regex::new(r#"(?s)<aside\b[^>]*\bdata-onebox-src="([^"]+)"[^>]*>(.*?)</aside>"#)
+        .expect("onebox regex is valid");
+    let title = Regex::new(r"(?s)<h3>\s*<a\b[^>]*>(.*?)</a>").expect("title regex is valid");
+    onebox
+        .captures_iter(cooked)
+        .filter_map(|caps| {
+            let heading = title.captures(&caps[2])?;
+            let text = markdown::html_text(&heading[1]);
+            (!text.is_empty()).then(|| (markdown::html_text(&caps[1]), text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let raw = "See\n\nhttps://example.com/post\n\nhttps://other.test/x\n\
+                   inline https://example.com/post stays\n\n\
+                   [details=\"Logs\"]\n![trace|690x40](upload://aBc.png)\n[/details]\n\
+                   [details]\nmore\n[/details]\n\n\
+                   Thanks @alice, mail a@b.test\n```\n@code upload://x.png\n```\n";
+        let titles = HashMap::from([(
+            "https://example.com/post".to_string(),
+            "A [great] post".to_string(),
+        )]);
+        assert_eq!(
+            normalize(raw, "https://forum.test", &titles),
+            "See\n\n[A \\[great\\] post](https://example.com/post)\n\n\
+             https://other.test/x\ninline https://example.com/post stays\n\n\
+             <details>\n<summary>Logs</summary>\n\n\
+             ![trace|690x40](https://forum.test/uploads/short-url/aBc.png)\n\n</details>\n\
+             <details>\n<summary>Details</summary>\n\nmore\n\n</details>\n\n\
+             Thanks \\@alice, mail a@b.test\n```\n@code upload://x.png\n```\n"
+        );
+        assert_eq!(
+            normalize("https://x.test", "https://forum.test", &HashMap::new()),
+            "<https://x.test>"
+        );
+    }
+
+    #[test]
+    fn test_onebox_titles() {
+        let cooked = "<aside class=\"onebox allowlistedgeneric\" data-onebox-src=\"https://example.com/a?x=1&amp;y=2\">\n\
+                      <header class=\"source\"><a href=\"https://example.com\">example.com</a></header>\n\
+                      <article><h3><a href=\"https://example.com/a\">Fish &amp; Chips</a></h3></article>\n\
+                      </aside><aside class=\"quote\">not a onebox</aside>";
+        assert_eq!(
+            onebox_titles(cooked),
+            HashMap::from([(
+                "https://example.com/a?x=1&y=2".to_string(),
+                "Fish & Chips".to_string()
+            )])
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::markdown;
+use regex::Regex;
+
+/// Convert a post's Markdown to Org markup.
+///
+/// Code fences become `src` blocks and quotes `quote` blocks; headings (which Org
+/// would read as comments or keywords) become bold lines, `*`/`+` bullets become
+/// `-` so they can't be taken for Org headings, and inline emphasis, code, links
+/// and images are rewritten to their Org forms.
+pub fn from_markdown(text: &str) -> String {
+    let inline = Inline::new();
+    let mut out = String::with_capacity(text.len());
+    let mut in_src = false;
+    let mut in_quote = false;
+
+    for (line, in_code) in markdown::classify_lines(text) {
+        let content = line.trim_end_matches(['\n', '\r']);
+        if in_code {
+            if in_quote {
+                out.push_str("#+end_quote\n");
+                in_quote = false;
+            }
+            if !in_src {
+                let trimmed = content.trim_start();
+                let info = trimmed.trim_start_matches(['`', '~']).trim();
+                let language = info.split_whitespace().next().unwrap_or("");
+                out.push_str("#+begin_src");
+                if !language.is_empty() {
+                    out.push(' ');
+                    out.push_str(language);
+                }
+                out.push('\n');
+                in_src = true;
+            } else if markdown::fence_marker(content).is_some()
+                && content.trim().chars().all(|c| c == '`' || c == '~')
+            {
+                out.push_str("#+end_src\n");
+                in_src = false;
+            } else {
+                // Org reads these as structure even inside blocks unless comma-escaped
+                if content.starts_with('*') || content.starts_with("#+") {
+                    out.push(',');
+                }
+                out.push_str(content);
+                out.push('\n');
+            }
+            continue;
+        }
+        if in_src {
+            // A fence left open by the post
+            out.push_str("#+end_src\n");
+            in_src = false;
+        }
+
+        let quoted = content.trim_start().strip_prefix('>');
+        match (quoted, in_quote) {
+            (Some(_), false) => {
+                out.push_str("#+begin_quote\n");
+                in_quote = true;
+            }
+            (None, true) => {
+                out.push_str("#+end_quote\n");
+                in_quote = false;
+            }
+            _ => {}
+        }
+        let content = match quoted {
+            // Nested quotes are flattened into one block
+            Some(rest) => rest.trim_start_matches(['>', ' ']),
+            None => content,
+        };
+        out.push_str(&convert_line(content, &inline));
+        out.push('\n');
+    }
+    if in_src {
+        out.push_str("#+end_src\n");
+    }
+    if in_quote {
+        out.push_str("#+end_quote\n");
+    }
+    if !text.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+/// Convert a line of Markdown prose: block markers first, then inline markup.
+fn convert_line(line: &str, inline: &Inline) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let rest = line.trim_start();
+
+    let marks: String = rest.chars().filter(|c| *c != ' ').collect();
+    let is_rule = |mark: char| marks.len() >= 3 && marks.chars().all(|c| c == mark);
+    if is_rule('-') || is_rule('*') || is_rule('_') {
+        return "-----".to_string();
+    }
+    if let Some(caps) = inline.heading.captures(rest) {
+        return format!("*{}*", inline.convert(caps[1].trim()));
+    }
+    if let Some(item) = rest.strip_prefix("* ").or_else(|| rest.strip_prefix("+ ")) {
+        return format!("{}- {}", indent, inline.convert(item));
+    }
+    let converted = inline.convert(rest);
+    // `#+` starts a keyword and `# ` a comment; a leading space keeps them text
+    if indent.is_empty() && (converted.starts_with("#+") || converted.starts_with("# ")) {
+        return format!(" {}", converted);
+    }
+    format!("{}{}", indent, converted)
+}
+
+struct Inline {
+    heading: Regex,
+    image: Regex,
+    link: Regex,
+    bold: Regex,
+    italic_star: Regex,
+    italic_underscore: Regex,
+}
+
+/// Stands in for bold markers while italics are converted.
+const BOLD: char = '\u{1}';
+
+impl Inline {
+    fn new() -> Self {
+        let re = |pattern: &str| Regex::new(pattern).expect("Org conversion regex is valid");
+        Self {
+            heading: re(r"^#{1,6}\s+(.*?)\s*#*$"),
+            image: re(r"!\[([^\]]*)\]\(([^)\s]+)(?:\s+[^)]*)?\)"),
+            link: re(r"\[([^\]]+)\]\(([^)\s]+)(?:\s+[^)]*)?\)"),
+            bold: re(r"\*\*([^*]+)\*\*|__([^_]+)__"),
+            italic_star: re(r"\*([^*\s][^*]*)\*"),
+            italic_underscore: re(r"(^|[^\w])_([^_\s][^_]*)_($|[^\w])"),
+        }
+    }
+
+    /// Convert inline markup, leaving code spans' contents alone.
+    fn convert(&self, line: &str) -> String {
+        let mut out = String::new();
+        // Odd-numbered segments between backticks are inline code
+        let segments: Vec<&str> = line.split('`').collect();
+        for (i, segment) in segments.iter().enumerate() {
+            let unclosed = i % 2 == 1 && i == segments.len() - 1;
+            if i % 2 == 0 || unclosed {
+                if unclosed {
+                    out.push('`');
+                }
+                out.push_str(&self.convert_prose(segment));
+            } else {
+                let marker = if segment.contains('~') { '=' } else { '~' };
+                out.push(marker);
+                out.push_str(segment);
+                out.push(marker);
+            }
+        }
+        out
+    }
+
+    fn convert_prose(&self, text: &str) -> String {
+        let text = self.image.replace_all(text, "[[$2]]");
+        let text = self.link.replace_all(&text, "[[$2][$1]]");
+        let text = self.bold.replace_all(&text, |caps: &regex::Captures| {
+            let inner = caps
+                .get(1)
+                .or_else(|| caps.get(2))
+                .map_or("", |m| m.as_str());
+            format!("{}{}{}", BOLD, inner, BOLD)
+        });
+        let text = self.italic_star.replace_all(&text, "/$1/");
+        let text = self.italic_underscore.replace_all(&text, "$1/$2/$3");
+        text.replace(BOLD, "*")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_markdown() {
+        let md = "## Setup\n\
+                  Use **this** and *that*, see [docs](https://x.test/d) or `a*b`.\n\
+                  * one\n  + two\n\
+                  > quoted\n> > nested\n\
+                  ```rust\n* not a heading\n#+not a keyword\n```\n\
+                  #+title in prose\n---\nsnake_case_name stays";
+        assert_eq!(
+            from_markdown(md),
+            "*Setup*\n\
+             Use *this* and /that/, see [[https://x.test/d][docs]] or ~a*b~.\n\
+             - one\n  - two\n\
+             #+begin_quote\nquoted\nnested\n#+end_quote\n\
+             #+begin_src rust\n,* not a heading\n,#+not a keyword\n#+end_src\n \
+             #+title in prose\n-----\nsnake_case_name stays"
+        );
+    }
+
+    #[test]
+    fn test_from_markdown_closes_open_blocks() {
+        assert_eq!(
+            from_markdown("```\ncode\n"),
+            "#+begin_src\ncode\n#+end_src\n"
+        );
+        assert_eq!(
+            from_markdown("> quote\n"),
+            "#+begin_quote\nquote\n#+end_quote\n"
+        );
+    }
+}
@@ -1,30 +1,3187 @@
 use crate::cache::CachedPost;
+use crate::dates::DateFormat;
+use crate::discourse::Reaction;
+use crate::markdown;
+use crate::org;
+use crate::plaintext;
+use crate::tokens;
+use regex::Regex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Overall shape of the rendered document.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// A `## Post #N by @user (date)` section per post
+    #[default]
+    Markdown,
+    /// A compact `@user (date): body` transcript
+    Chat,
+    /// Each post as a `---`-delimited YAML metadata block followed by its raw body
+    YamlBlocks,
+    /// A single JSON document with the topic's details and an array of posts
+    Json,
+    /// One JSON object per post, per line
+    Jsonl,
+    /// A standalone, styled HTML page with an anchor per post
+    Html,
+    /// An Org document with a heading and properties drawer per post
+    Org,
+    /// An EPUB e-book with a section per post
+    Epub,
+    /// A printable PDF with a title page and numbered pages
+    Pdf,
+    /// Plain text, with post bodies' Markdown syntax stripped
+    Text,
+    /// A SQLite database of topics and posts, updated in place on re-scrapes
+    Sqlite,
+    /// One row of metadata per post, without bodies
+    Csv,
+}
+
+impl OutputFormat {
+    /// File extension for documents in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Html => "html",
+            OutputFormat::Org => "org",
+            OutputFormat::Epub => "epub",
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Text => "txt",
+            OutputFormat::Sqlite => "db",
+            OutputFormat::Csv => "csv",
+            _ => "md",
+        }
+    }
+
+    /// Whether documents in this format are Markdown, which the section, grouping
+    /// and diffing options work on.
+    pub fn is_markdown(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Markdown | OutputFormat::Chat | OutputFormat::YamlBlocks
+        )
+    }
+
+    /// Whether documents in this format are binary rather than text.
+    pub fn is_binary(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Epub | OutputFormat::Pdf | OutputFormat::Sqlite
+        )
+    }
+}
+
+/// Compression of the output file, for `--compress-output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// `.gz`, readable everywhere
+    Gzip,
+    /// `.zst`, smaller and faster to read back
+    Zstd,
+}
+
+impl Compression {
+    /// File extension added to compressed files.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// The compression a path's extension asks for, if any.
+    pub fn for_path(path: &str) -> Option<Compression> {
+        [Compression::Gzip, Compression::Zstd]
+            .into_iter()
+            .find(|compression| path.ends_with(&format!(".{}", compression.extension())))
+    }
+}
+
+/// Order of the participant roster.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ParticipantOrder {
+    /// Most posts first, ties by name
+    #[default]
+    Count,
+    /// By username
+    Name,
+}
+
+/// Order of the posts in the document, for `--sort`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PostOrder {
+    /// The topic's order, oldest first
+    #[default]
+    Chronological,
+    /// Most liked first, ties in the topic's order
+    Likes,
+}
+
+/// A part of the rendered document, for `--sections`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Section {
+    /// Title, source and other topic details
+    Header,
+    /// Table of contents: one line per post
+    Toc,
+    /// Each post's opening prose, for skimming the thread
+    Digest,
+    /// Participants and their post counts
+    Roster,
+    /// The posts themselves
+    Posts,
+    /// Post, participant and word counts and the date range
+    Stats,
+}
+
+/// Sections rendered when none are configured.
+const DEFAULT_SECTIONS: [Section; 2] = [Section::Header, Section::Posts];
+
+/// Options controlling how posts are rendered.
+#[derive(Debug, Default)]
+pub struct RenderOptions {
+    pub format: OutputFormat,
+    /// Post IDs included only as reply context; their headers are marked `[context]`
+    pub context_post_ids: HashSet<u64>,
+    /// Name of the topic's category, shown in the header
+    pub category: Option<String>,
+    /// Canonical topic URL, used to build per-post permalinks
+    pub topic_url: String,
+    /// Wrap each post in `<!-- disc-scrape:post {...} -->` / `<!-- disc-scrape:end-post -->`
+    /// markers carrying its metadata as JSON, so the document can be split per post
+    pub metadata_separators: bool,
+    /// Tidy whitespace in post bodies; see [`normalize_whitespace`]
+    pub normalize_whitespace: bool,
+    /// Close code fences left open by a post; see [`repair_markdown`]
+    pub repair_markdown: bool,
+    /// Warning about the age of the topic's most recent post, shown in the header
+    pub stale_warning: Option<String>,
+    /// Leave out the fetch time, so an unchanged thread renders byte-identically
+    pub reproducible: bool,
+    /// Participants and their post counts, for the roster section or, without one,
+    /// a header line when non-empty; see [`participant_roster`]
+    pub participants: Vec<(String, usize)>,
+    /// Sections to render, in order; empty means the header, then the posts
+    pub sections: Vec<Section>,
+    /// Render post #1 as the document's lead, under the title, and the other posts
+    /// as answers (Markdown only)
+    pub qa_layout: bool,
+    /// Posts that could not be fetched, by post number; each is noted where it
+    /// would have been, see [`failure_annotation`]
+    pub failed_posts: Vec<FailedPost>,
+    /// Group the posts under a `## Posts #N–#M` heading every this many posts,
+    /// demoting the post headings a level
+    pub group_size: Option<usize>,
+    /// Replace code blocks with a `[code]` line in plain-text output
+    pub collapse_code: bool,
+    /// Give the topic details as YAML frontmatter rather than bullets under the
+    /// title; see [`push_frontmatter`]
+    pub frontmatter: bool,
+    /// Shape the frontmatter for Pandoc, which takes its title, authors and date
+    /// from there, leaving out the `#` title heading it would repeat
+    pub pandoc: bool,
+    /// Add the fields Hugo and Jekyll expect to the frontmatter: the first
+    /// post's date, the topic's slug, tags and category, and its canonical URL
+    pub static_site: bool,
+    /// URL slug of the topic title, for `--static-site`
+    pub slug: String,
+    /// The topic's tags, for `--static-site`
+    pub tags: Vec<String>,
+    /// Link each post's header to its permalink (Markdown and chat)
+    pub permalinks: bool,
+    /// Authors of the topic's posts by post number, to name who a reply answers
+    /// even when their post isn't rendered; see [`reply_note`]
+    pub post_authors: HashMap<u64, String>,
+    /// Order posts by the reply tree and indent replies under what they answer
+    pub threaded: bool,
+    /// How post and fetch dates are written
+    pub dates: DateFormat,
+    /// Heading level of the posts in Markdown; `##` when unset
+    pub heading_level: Option<usize>,
+    /// What follows the `#`s of a Markdown post heading, with `{post_number}`,
+    /// `{username}`, `{date}`, `{permalink}` and `{notes}` filled in; see
+    /// [`POST_HEADER_FIELDS`]
+    pub post_header: Option<String>,
+    /// The line between Markdown posts; `---` when unset, and none when empty
+    pub post_separator: Option<String>,
+    /// Give each post a `post-N` anchor even without a table of contents, for
+    /// links pointed at posts in the document
+    pub post_anchors: bool,
+    /// Follow each post with a `[^N]` footnote marker and end the document with
+    /// the footnotes, citing each post's permalink; see [`push_citations`]
+    pub citations: bool,
+    /// Post number of the reply accepted as the topic's solution, marked in its
+    /// header; see [`ACCEPTED_SOLUTION`]
+    pub accepted_answer: Option<u64>,
+}
+
+/// A post that could not be fetched.
+#[derive(Debug, Clone)]
+pub struct FailedPost {
+    pub post_number: u64,
+    /// Why, e.g. `HTTP 404`
+    pub reason: String,
+}
+
+/// A machine-parseable marker for a post that could not be fetched, e.g.
+/// `<!-- disc-scrape: post 42 failed: HTTP 404 -->`.
+pub fn failure_annotation(failed: &FailedPost) -> String {
+    format!(
+        "<!-- disc-scrape: post {} failed: {} -->\n\n",
+        failed.post_number, failed.reason
+    )
+}
+
+impl RenderOptions {
+    /// Whether `post` is the topic's accepted solution.
+    pub fn is_accepted_answer(&self, post: &CachedPost) -> bool {
+        self.accepted_answer == Some(post.post_number)
+    }
+
+    fn sections(&self) -> &[Section] {
+        if self.sections.is_empty() {
+            &DEFAULT_SECTIONS
+        } else {
+            &self.sections
+        }
+    }
+
+    /// Whether posts get a `post-N` anchor for the table of contents or links
+    /// between posts to point at. YAML blocks must start with their `---`, so they
+    /// go without.
+    fn anchors(&self) -> bool {
+        (self.post_anchors || self.sections().contains(&Section::Toc))
+            && self.format != OutputFormat::YamlBlocks
+    }
+}
 
 /// Render all posts into an LLM-friendly Markdown document.
-pub fn render(title: &str, source_url: &str, posts: &[CachedPost]) -> String {
-    let now = chrono::Utc::now().format("%Y-%m-%d %H:%M UTC");
+pub fn render(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> String {
+    match options.format {
+        OutputFormat::Json => return render_json(title, source_url, posts, options),
+        OutputFormat::Jsonl => return render_jsonl(title, source_url, posts, options),
+        OutputFormat::Html => return render_html(title, source_url, posts, options),
+        OutputFormat::Org => return render_org(title, source_url, posts, options),
+        OutputFormat::Text => return render_text(title, source_url, posts, options),
+        OutputFormat::Csv => return render_csv(posts),
+        OutputFormat::Epub => unreachable!("EPUB books are binary; see epub::render"),
+        OutputFormat::Pdf => unreachable!("PDF documents are binary; see pdf::render"),
+        OutputFormat::Sqlite => unreachable!("databases are written by sqlite::export"),
+        _ => {}
+    }
+
     let mut out = String::new();
+    if options.frontmatter {
+        push_frontmatter(&mut out, title, source_url, posts, options);
+    }
 
-    // Header
-    out.push_str(&format!("# {}\n\n", title));
-    out.push_str(&format!("- **Source**: {}\n", source_url));
-    out.push_str(&format!("- **Fetched**: {}\n", now));
-    out.push_str(&format!("- **Posts**: {}\n", posts.len()));
-    out.push_str("\n---\n\n");
+    // The question leads the header, so it needs one to go in
+    let question = posts.first().filter(|post| {
+        options.qa_layout
+            && options.format == OutputFormat::Markdown
+            && post.post_number == 1
+            && options.sections().contains(&Section::Header)
+    });
+
+    for section in options.sections() {
+        match section {
+            Section::Header => {
+                if options.pandoc || options.static_site {
+                    // Pandoc and site themes show the title from the metadata
+                    match question {
+                        Some(question) => push_question(&mut out, question, options),
+                        None => continue,
+                    }
+                    out.pop();
+                    out.push_str(header_rule(options));
+                    continue;
+                }
+                out.push_str(&format!("# {}\n\n", title));
+                if let Some(question) = question {
+                    push_question(&mut out, question, options);
+                }
+                if options.frontmatter {
+                    // The rule below brings its own blank line
+                    out.pop();
+                } else {
+                    push_details(&mut out, source_url, Some(posts.len()), options);
+                }
+            }
+            Section::Toc => push_toc(&mut out, posts, options),
+            Section::Digest => push_digest(&mut out, posts),
+            Section::Roster => push_roster(&mut out, posts, options),
+            Section::Posts => {
+                let answers = if question.is_some() {
+                    &posts[1..]
+                } else {
+                    posts
+                };
+                let mut failed = options.failed_posts.iter().peekable();
+                push_posts(&mut out, answers, &mut failed, question.is_some(), options);
+                for failed in failed {
+                    out.push_str(&failure_annotation(failed));
+                }
+                continue;
+            }
+            Section::Stats => push_stats(&mut out, posts, options),
+        }
+        out.push_str(header_rule(options));
+    }
+    if options.citations {
+        push_citations(&mut out, posts, options);
+    }
+
+    out
+}
+
+/// The footnote marker citing a post, e.g. `[^3]`.
+fn citation_marker(post: &CachedPost) -> String {
+    format!("[^{}]", post.post_number)
+}
+
+/// The footnotes the posts' citation markers refer to, each naming the post
+/// and giving its permalink.
+fn push_citations(out: &mut String, posts: &[CachedPost], options: &RenderOptions) {
+    for post in posts {
+        out.push_str(&format!(
+            "{}: Post #{} by @{} ({}), {}\n",
+            citation_marker(post),
+            post.post_number,
+            post.username,
+            options.dates.format(post.created_at),
+            permalink(options, post)
+        ));
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    title: &'a str,
+    source_url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fetched_at: Option<chrono::DateTime<chrono::Utc>>,
+    posts: Vec<JsonPost<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonPost<'a> {
+    post_number: u64,
+    post_id: u64,
+    username: &'a str,
+    created_at: chrono::DateTime<chrono::Utc>,
+    permalink: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_post_number: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'a str>,
+    like_count: u64,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    reactions: &'a [Reaction],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    accepted_answer: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    context: bool,
+    raw: String,
+}
+
+impl<'a> JsonPost<'a> {
+    fn new(post: &'a CachedPost, options: &RenderOptions) -> Self {
+        JsonPost {
+            post_number: post.post_number,
+            post_id: post.post_id,
+            username: &post.username,
+            created_at: post.created_at,
+            permalink: permalink(options, post),
+            reply_to_post_number: post.reply_to_post_number,
+            role: post.role.as_deref(),
+            like_count: post.like_count,
+            reactions: &post.reactions,
+            accepted_answer: options.is_accepted_answer(post),
+            context: options.context_post_ids.contains(&post.post_id),
+            raw: post_body(post, options),
+        }
+    }
+}
+
+/// A post on its own JSONL line, carrying the topic it belongs to.
+#[derive(Serialize)]
+struct JsonlPost<'a> {
+    title: &'a str,
+    source_url: &'a str,
+    #[serde(flatten)]
+    post: JsonPost<'a>,
+}
+
+/// Render the topic as a pretty-printed JSON document, for pipelines that would
+/// rather not parse Markdown headers.
+fn render_json(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> String {
+    let document = JsonDocument {
+        title,
+        source_url,
+        category: options.category.as_deref(),
+        fetched_at: (!options.reproducible).then(chrono::Utc::now),
+        posts: posts
+            .iter()
+            .map(|post| JsonPost::new(post, options))
+            .collect(),
+    };
+    let mut out = serde_json::to_string_pretty(&document).expect("documents always serialize");
+    out.push('\n');
+    out
+}
+
+/// Merge a freshly rendered JSON document into one written by an earlier run:
+/// posts fetched now replace their old entries by `post_id`, posts only in the
+/// old document are kept, and the result is ordered by post number. The topic's
+/// details come from the fresh document.
+///
+/// Fails if the old document's `source_url` is for a different topic.
+pub fn merge_json(existing: &str, rendered: &str) -> anyhow::Result<String> {
+    use anyhow::Context;
+
+    let existing: serde_json::Value =
+        serde_json::from_str(existing).context("not a JSON document")?;
+    let mut document: serde_json::Value =
+        serde_json::from_str(rendered).expect("rendered documents always parse");
+    let old_source = existing["source_url"].as_str().unwrap_or_default();
+    let new_source = document["source_url"].as_str().unwrap_or_default();
+    // URLs with and without a post number or slug still name the same topic
+    let topic = |url: &str| crate::discourse::parse_topic_url(url).ok();
+    if old_source != new_source
+        && (topic(old_source).is_none() || topic(old_source) != topic(new_source))
+    {
+        anyhow::bail!(
+            "it archives a different topic ({}, not {})",
+            if old_source.is_empty() {
+                "no source_url"
+            } else {
+                old_source
+            },
+            new_source
+        );
+    }
+    let old_posts = existing
+        .get("posts")
+        .and_then(|posts| posts.as_array())
+        .context("no `posts` array")?;
+    let posts = document["posts"]
+        .as_array_mut()
+        .expect("rendered documents have posts");
+    let fetched: HashSet<u64> = posts
+        .iter()
+        .filter_map(|post| post["post_id"].as_u64())
+        .collect();
+    posts.extend(
+        old_posts
+            .iter()
+            .filter(|post| {
+                !post["post_id"]
+                    .as_u64()
+                    .is_some_and(|id| fetched.contains(&id))
+            })
+            .cloned(),
+    );
+    posts.sort_by_key(|post| post["post_number"].as_u64().unwrap_or(u64::MAX));
+    let mut out = serde_json::to_string_pretty(&document).expect("documents always serialize");
+    out.push('\n');
+    Ok(out)
+}
+
+/// Render each post as a standalone JSON object on its own line, for streaming
+/// into embedding pipelines or filtering with `jq`.
+fn render_jsonl(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> String {
+    let mut out = String::new();
+    for post in posts {
+        let line = JsonlPost {
+            title,
+            source_url,
+            post: JsonPost::new(post, options),
+        };
+        out.push_str(&serde_json::to_string(&line).expect("posts always serialize"));
+        out.push('\n');
+    }
+    out
+}
+
+/// Stylesheet embedded in HTML exports.
+const HTML_STYLE: &str = "\
+body { max-width: 48rem; margin: 2rem auto; padding: 0 1rem; font: 16px/1.6 system-ui, sans-serif; color: #222; }
+header ul { list-style: none; padding: 0; color: #555; }
+article { border-top: 1px solid #ddd; padding-top: 0.5rem; }
+article.context { opacity: 0.7; }
+article h2 { font-size: 1rem; color: #555; }
+article h2 a { color: inherit; text-decoration: none; }
+article h2 a:hover { text-decoration: underline; }
+article h2 .accepted { color: #1a7f37; }
+article h2 .role { font-weight: bold; }
+pre, code { background: #f5f5f5; border-radius: 3px; }
+pre { padding: 0.5rem; overflow-x: auto; white-space: pre-wrap; }
+aside.quote, blockquote { border-left: 3px solid #ccc; margin: 0 0 1rem; padding: 0 0.75rem; color: #555; }
+img { max-width: 100%; height: auto; }
+";
+
+/// Start of the line carrying the time of the scrape in an HTML document.
+const FETCHED_HTML_PREFIX: &str = "<li class=\"fetched\">";
+
+/// Render the topic as a standalone HTML page for archiving.
+///
+/// Post bodies are rendered from the Markdown the other formats write, so the
+/// options that rewrite posts (downloaded images, stripped quotes, normalized
+/// markup and so on) apply here too.
+fn render_html(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> String {
+    let title = html_escape(title);
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n", title));
+    // Posts link uploads and emoji relative to the forum
+    if let Ok(topic_url) = url::Url::parse(&options.topic_url) {
+        out.push_str(&format!(
+            "<base href=\"{}/\">\n",
+            html_escape(&topic_url.origin().ascii_serialization())
+        ));
+    }
+    out.push_str(&format!(
+        "<style>\n{}</style>\n</head>\n<body>\n",
+        HTML_STYLE
+    ));
+
+    out.push_str(&format!("<header>\n<h1>{}</h1>\n<ul>\n", title));
+    let source_url = html_escape(source_url);
+    out.push_str(&format!(
+        "<li>Source: <a href=\"{}\">{}</a></li>\n",
+        source_url, source_url
+    ));
+    if let Some(category) = &options.category {
+        out.push_str(&format!("<li>Category: {}</li>\n", html_escape(category)));
+    }
+    if !options.reproducible {
+        let now = options.dates.format(chrono::Utc::now());
+        out.push_str(&format!("{}Fetched: {}</li>\n", FETCHED_HTML_PREFIX, now));
+    }
+    out.push_str(&format!(
+        "<li>Posts: {}</li>\n</ul>\n</header>\n",
+        posts.len()
+    ));
+
+    for post in posts {
+        let context = options.context_post_ids.contains(&post.post_id);
+        out.push_str(&format!(
+            "<article id=\"post-{}\"{}>\n",
+            post.post_number,
+            if context { " class=\"context\"" } else { "" }
+        ));
+        let role = role_note(post)
+            .map(|note| format!(", <span class=\"role\">{}</span>", html_escape(&note)))
+            .unwrap_or_default();
+        let reply = match (post.reply_to_post_number, reply_note(post, options)) {
+            (Some(parent), Some(note)) => format!(
+                ", <a class=\"reply\" href=\"#post-{}\">{}</a>",
+                parent,
+                html_escape(&note)
+            ),
+            _ => String::new(),
+        };
+        let appreciation = appreciation_note(post)
+            .map(|note| format!(", {}", html_escape(&note)))
+            .unwrap_or_default();
+        let accepted = if options.is_accepted_answer(post) {
+            format!(
+                " — <strong class=\"accepted\">{}</strong>",
+                ACCEPTED_SOLUTION
+            )
+        } else {
+            String::new()
+        };
+        out.push_str(&format!(
+            "<h2><a href=\"#post-{n}\">Post #{n}</a> by @{} (<time datetime=\"{}\">{}</time>){}{}{}{}{}</h2>\n",
+            html_escape(&post.username),
+            post.created_at.to_rfc3339(),
+            options.dates.format(post.created_at),
+            role,
+            reply,
+            appreciation,
+            accepted,
+            if context { " [context]" } else { "" },
+            n = post.post_number,
+        ));
+        out.push_str(markdown_html(&post_body(post, options)).trim());
+        out.push_str("\n</article>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Start of the line carrying the time of the scrape in an Org document.
+const FETCHED_ORG_PREFIX: &str = "- Fetched: ";
+
+/// Render the topic as an Org document: a heading per post, with its details in
+/// a properties drawer and its body converted from Markdown.
+fn render_org(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> String {
+    let mut out = format!("#+TITLE: {}\n\n", title);
+    out.push_str(&format!("- Source: [[{}]]\n", source_url));
+    if let Some(category) = &options.category {
+        out.push_str(&format!("- Category: {}\n", category));
+    }
+    if !options.reproducible {
+        let now = options
+            .dates
+            .format_as(chrono::Utc::now(), "[%Y-%m-%d %a %H:%M]");
+        out.push_str(&format!("{}{}\n", FETCHED_ORG_PREFIX, now));
+    }
+    out.push_str(&format!("- Posts: {}\n", posts.len()));
+
+    for post in posts {
+        let context = options.context_post_ids.contains(&post.post_id);
+        out.push_str(&format!(
+            "\n* Post #{} by @{}{}{}\n",
+            post.post_number,
+            post.username,
+            if options.is_accepted_answer(post) {
+                format!(" — {}", ACCEPTED_SOLUTION)
+            } else {
+                String::new()
+            },
+            if context { " :context:" } else { "" }
+        ));
+        out.push_str(":PROPERTIES:\n");
+        out.push_str(&format!(":CUSTOM_ID: post-{}\n", post.post_number));
+        out.push_str(&format!(":AUTHOR: {}\n", post.username));
+        if let Some(role) = &post.role {
+            out.push_str(&format!(":ROLE: {}\n", role));
+        }
+        if let Some(parent) = post.reply_to_post_number {
+            out.push_str(&format!(":REPLY_TO: post-{}\n", parent));
+        }
+        if let Some(note) = appreciation_note(post) {
+            out.push_str(&format!(":APPRECIATION: {}\n", note));
+        }
+        out.push_str(&format!(
+            ":DATE: {}\n",
+            options
+                .dates
+                .format_as(post.created_at, "[%Y-%m-%d %a %H:%M]")
+        ));
+        out.push_str(&format!(":POST_ID: {}\n", post.post_id));
+        out.push_str(&format!(":PERMALINK: {}\n", permalink(options, post)));
+        out.push_str(":END:\n\n");
+        let body = org::from_markdown(&post_body(post, options));
+        out.push_str(body.trim_end_matches('\n'));
+        out.push('\n');
+    }
+    out
+}
+
+/// Start of the header line carrying the time of the scrape in a plain-text document.
+const FETCHED_TEXT_PREFIX: &str = "Fetched: ";
+
+/// Render a CSV table with a row of metadata per post: its number, ID, author,
+/// creation time, word count and likes. Bodies are left out.
+fn render_csv(posts: &[CachedPost]) -> String {
+    let mut out = String::from("post_number,post_id,username,created_at,words,like_count\n");
+    for post in posts {
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            post.post_number,
+            post.post_id,
+            csv_field(&post.username),
+            post.created_at.to_rfc3339(),
+            post.raw.split_whitespace().count(),
+            post.like_count
+        ));
+    }
+    out
+}
+
+/// Quote a CSV field if it holds a comma, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Render the topic as plain text: the title and details, then each post under a
+/// `Post #N by @user (date)` line with its Markdown stripped.
+fn render_text(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> String {
+    let mut out = format!("{}\n\n", title);
+    out.push_str(&format!("Source: {}\n", source_url));
+    if let Some(category) = &options.category {
+        out.push_str(&format!("Category: {}\n", category));
+    }
+    if !options.reproducible {
+        let now = options.dates.format(chrono::Utc::now());
+        out.push_str(&format!("{}{}\n", FETCHED_TEXT_PREFIX, now));
+    }
+    out.push_str(&format!("Posts: {}\n", posts.len()));
+
+    for post in posts {
+        let marker = if options.context_post_ids.contains(&post.post_id) {
+            " [context]"
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "\n\nPost #{} by @{} ({}){}{}\n\n",
+            post.post_number,
+            post.username,
+            options.dates.format(post.created_at),
+            header_suffix(post, options),
+            marker
+        ));
+        out.push_str(&plaintext::from_markdown(
+            &post_body(post, options),
+            options.collapse_code,
+        ));
+    }
+    out
+}
+
+/// Render a post's Markdown to HTML. Discourse's `[quote]` blocks become the
+/// `<aside class="quote">` the forum uses, and HTML written in the post is kept
+/// unless it could run script, which is shown as text instead.
+fn markdown_html(body: &str) -> String {
+    use pulldown_cmark::{Event, Options, Parser, Tag};
+
+    let open = Regex::new(r#"(?i)\[quote(?:="([^"\]]*)")?\]"#).expect("quote regex is valid");
+    let close = Regex::new(r"(?i)\[/quote\]").expect("quote regex is valid");
+    let body = markdown::map_prose(body, |text| {
+        let text = open.replace_all(text, |caps: &regex::Captures| {
+            let author = caps
+                .get(1)
+                .and_then(|attributes| attributes.as_str().split(',').next())
+                .map(str::trim)
+                .filter(|name| !name.is_empty() && !name.contains(':'));
+            match author {
+                Some(author) => format!(
+                    "\n\n<aside class=\"quote\">\n<div class=\"title\">{}:</div>\n\n",
+                    html_escape(author)
+                ),
+                None => "\n\n<aside class=\"quote\">\n\n".to_string(),
+            }
+        });
+        close.replace_all(&text, "\n\n</aside>\n\n").into_owned()
+    });
+
+    let unsafe_html =
+        Regex::new(r"(?i)<\s*/?\s*(script|style|iframe|object|embed)|javascript:|\son[a-z]+\s*=")
+            .expect("unsafe HTML regex is valid");
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(&body, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) if unsafe_html.is_match(&html) => {
+            Event::Text(html)
+        }
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: "#".into(),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: "#".into(),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::new();
+    pulldown_cmark::html::push_html(&mut out, events);
+    out
+}
+
+/// Whether a link target is relative or uses http, https or mailto, rather than
+/// a scheme such as `javascript:` that a browser would act on.
+fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore whitespace and control characters inside the scheme
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(i) if url[i..].starts_with(':') => {
+            let scheme = url[..i].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+/// Escape text for use in HTML content and attribute values.
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Push posts, each preceded by the annotations of failed posts numbered before it.
+/// `as_answers` heads them as answers to a question rendered in the header.
+fn push_posts<'a>(
+    out: &mut String,
+    posts: &[CachedPost],
+    failed: &mut std::iter::Peekable<impl Iterator<Item = &'a FailedPost>>,
+    as_answers: bool,
+    options: &RenderOptions,
+) {
+    if options.threaded {
+        for (post, depth) in thread(posts) {
+            while let Some(f) = failed.next_if(|f| f.post_number < post.post_number) {
+                out.push_str(&failure_annotation(f));
+            }
+            push_post(out, post, depth, as_answers, options);
+        }
+        return;
+    }
+    let group_size = options.group_size.unwrap_or(posts.len()).max(1);
+    for group in posts.chunks(group_size) {
+        if options.group_size.is_some() {
+            let range = post_range(group);
+            out.push_str(&format!(
+                "{} {}{}\n\n",
+                "#".repeat(options.heading_level.unwrap_or(2)),
+                range[..1].to_uppercase(),
+                &range[1..]
+            ));
+        }
+        for post in group {
+            while let Some(f) = failed.next_if(|f| f.post_number < post.post_number) {
+                out.push_str(&failure_annotation(f));
+            }
+            push_post(out, post, 0, as_answers, options);
+        }
+    }
+}
+
+/// Order posts depth-first along the reply tree, pairing each with how deeply
+/// it's nested. A post replying to one that isn't in `posts` starts a thread of
+/// its own; siblings stay in post order.
+pub fn thread(posts: &[CachedPost]) -> Vec<(&CachedPost, usize)> {
+    let numbers: HashSet<u64> = posts.iter().map(|post| post.post_number).collect();
+    let parent = |post: &CachedPost| {
+        post.reply_to_post_number
+            .filter(|parent| *parent < post.post_number && numbers.contains(parent))
+    };
+    let mut children: HashMap<u64, Vec<&CachedPost>> = HashMap::new();
+    let mut roots = Vec::new();
+    for post in posts {
+        match parent(post) {
+            Some(parent) => children.entry(parent).or_default().push(post),
+            None => roots.push(post),
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(posts.len());
+    // Reversed, so the stack pops them in order
+    let mut stack: Vec<(&CachedPost, usize)> = roots.into_iter().rev().map(|p| (p, 0)).collect();
+    while let Some((post, depth)) = stack.pop() {
+        ordered.push((post, depth));
+        if let Some(replies) = children.get(&post.post_number) {
+            stack.extend(replies.iter().rev().map(|reply| (*reply, depth + 1)));
+        }
+    }
+    ordered
+}
+
+/// Length of the post excerpts in the table of contents.
+const TOC_EXCERPT_CHARS: usize = 80;
+
+fn push_toc(out: &mut String, posts: &[CachedPost], options: &RenderOptions) {
+    out.push_str("## Contents\n\n");
+    for post in posts {
+        let entry = format!("Post #{} by @{}", post.post_number, post.username);
+        if options.anchors() {
+            out.push_str(&format!("- [{}](#post-{})", entry, post.post_number));
+        } else {
+            out.push_str(&format!("- {}", entry));
+        }
+        if let Some(excerpt) = excerpt(&post.raw, TOC_EXCERPT_CHARS) {
+            out.push_str(&format!(" — {}", excerpt));
+        }
+        out.push('\n');
+    }
+}
 
-    // Posts
+/// Length of the post excerpts in the digest.
+const DIGEST_EXCERPT_CHARS: usize = 300;
+
+fn push_digest(out: &mut String, posts: &[CachedPost]) {
+    out.push_str("## Digest\n\n");
     for post in posts {
-        let date = post.created_at.format("%Y-%m-%d %H:%M UTC");
+        let excerpt = excerpt(&post.raw, DIGEST_EXCERPT_CHARS);
+        out.push_str(&format!(
+            "- **#{} @{}**: {}\n",
+            post.post_number,
+            post.username,
+            excerpt.as_deref().unwrap_or("*(no text)*")
+        ));
+    }
+}
+
+/// The participants, each with whether they started the topic or are on the
+/// forum's staff, their post count and the days they posted on, e.g. `- @alice
+/// — OP, 3 posts, active 2026-02-20 to 2026-02-24`.
+fn push_roster(out: &mut String, posts: &[CachedPost], options: &RenderOptions) {
+    out.push_str("## Participants\n\n");
+    let op = options.post_authors.get(&1);
+    for (username, count) in &options.participants {
+        let theirs: Vec<&CachedPost> = posts.iter().filter(|p| p.username == *username).collect();
+        let mut notes = Vec::new();
+        if op == Some(username) {
+            notes.push("OP".to_string());
+        }
+        // The role as of their latest post
+        if let Some(role) = theirs
+            .iter()
+            .max_by_key(|p| p.fetched_at)
+            .and_then(|p| role_note(p))
+        {
+            notes.push(role);
+        }
+        notes.push(format!(
+            "{} post{}",
+            count,
+            if *count == 1 { "" } else { "s" }
+        ));
+        let first = theirs.iter().map(|p| p.created_at).min();
+        let last = theirs.iter().map(|p| p.created_at).max();
+        if let (Some(first), Some(last)) = (first, last) {
+            let (first, last) = (
+                options.dates.format_as(first, "%Y-%m-%d"),
+                options.dates.format_as(last, "%Y-%m-%d"),
+            );
+            if first == last {
+                notes.push(format!("active {}", first));
+            } else {
+                notes.push(format!("active {} to {}", first, last));
+            }
+        }
+        out.push_str(&format!("- @{} — {}\n", username, notes.join(", ")));
+    }
+}
+
+fn push_stats(out: &mut String, posts: &[CachedPost], options: &RenderOptions) {
+    let authors: HashSet<&str> = posts.iter().map(|p| p.username.as_str()).collect();
+    let words: usize = posts.iter().map(|p| p.raw.split_whitespace().count()).sum();
+    out.push_str("## Stats\n\n");
+    out.push_str(&format!("- **Posts**: {}\n", posts.len()));
+    out.push_str(&format!("- **Participants**: {}\n", authors.len()));
+    out.push_str(&format!("- **Words**: {}\n", words));
+    let first = posts.iter().map(|p| p.created_at).min();
+    let last = posts.iter().map(|p| p.created_at).max();
+    if let (Some(first), Some(last)) = (first, last) {
         out.push_str(&format!(
-            "## Post #{} by @{} ({})\n\n",
-            post.post_number, post.username, date
+            "- **Dates**: {} to {}\n",
+            options.dates.format_as(first, "%Y-%m-%d"),
+            options.dates.format_as(last, "%Y-%m-%d")
         ));
-        out.push_str(&post.raw);
-        if !post.raw.ends_with('\n') {
+    }
+}
+
+/// Another topic on the forum, scraped because the document links to it.
+pub struct LinkedTopic {
+    pub title: String,
+    pub url: String,
+    pub posts: Vec<CachedPost>,
+}
+
+/// Render linked topics as an appendix to follow the main document.
+///
+/// Their posts get `###` headings, so they aren't mistaken for the main topic's
+/// posts when the document is read back by `--diff-against`.
+pub fn render_appendix(linked: &[LinkedTopic], options: &RenderOptions) -> String {
+    let mut out = String::from("# Appendix: Linked Topics\n\n");
+    for topic in linked {
+        out.push_str(&format!("## {}\n\n", topic.title));
+        out.push_str(&format!("- **Source**: {}\n", topic.url));
+        out.push_str(&format!("- **Posts**: {}\n\n", topic.posts.len()));
+        for post in &topic.posts {
+            let date = options.dates.format(post.created_at);
+            out.push_str(&format!(
+                "### Post #{} by @{} ({})\n\n",
+                post.post_number, post.username, date
+            ));
+            let body = post_body(post, options);
+            out.push_str(&body);
+            if !body.ends_with('\n') {
+                out.push('\n');
+            }
             out.push('\n');
         }
-        out.push_str("\n---\n\n");
+        out.push_str("---\n\n");
+    }
+    out
+}
+
+/// Render runs of posts into shards, plus an `index.md`.
+///
+/// Returns `(file_name, content)` pairs. Each shard repeats the title header so it
+/// can be read on its own.
+pub fn render_shards(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    chunks: &[&[CachedPost]],
+    options: &RenderOptions,
+) -> Vec<(String, String)> {
+    let total = chunks.len();
+
+    let mut files = Vec::new();
+    let mut index = String::new();
+    push_header(&mut index, title, source_url, posts.len(), options);
+    index.push_str(&format!("- **Parts**: {}\n", total));
+    index.push_str(header_rule(options));
+
+    let mut failed = options.failed_posts.iter().peekable();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let file_name = format!("part-{:03}.md", i + 1);
+        let range = post_range(chunk);
+
+        let mut out = String::new();
+        push_header(&mut out, title, source_url, chunk.len(), options);
+        out.push_str(&format!("- **Part**: {} of {} ({})\n", i + 1, total, range));
+        out.push_str(header_rule(options));
+        push_posts(&mut out, chunk, &mut failed, false, options);
+        if i + 1 == total {
+            for failed in failed.by_ref() {
+                out.push_str(&failure_annotation(failed));
+            }
+        }
+
+        index.push_str(&format!("- [Part {}]({}) — {}\n", i + 1, file_name, range));
+        files.push((file_name, out));
+    }
+
+    files.push(("index.md".to_string(), index));
+    files
+}
+
+/// Group posts into shards of at most `max_tokens` tokens each, once rendered by
+/// [`render_shards`] with its part header, keeping their order.
+///
+/// Also returns the numbers of posts too large to fit on their own; each gets a
+/// shard of its own anyway.
+pub fn token_chunks<'a>(
+    title: &str,
+    source_url: &str,
+    posts: &'a [CachedPost],
+    max_tokens: usize,
+    options: &RenderOptions,
+) -> (Vec<&'a [CachedPost]>, Vec<u64>) {
+    // A header with the widest part line any shard could have, plus every
+    // failure annotation in case they all land in one shard
+    let mut header = String::new();
+    push_header(&mut header, title, source_url, posts.len(), options);
+    let widest = posts.len().to_string();
+    let last = posts.last().map_or(0, |post| post.post_number);
+    header.push_str(&format!(
+        "- **Part**: {0} of {0} (posts #{1}–#{1})\n",
+        widest, last
+    ));
+    header.push_str(header_rule(options));
+    for failed in &options.failed_posts {
+        header.push_str(&failure_annotation(failed));
+    }
+    let budget = max_tokens.saturating_sub(tokens::count(&header));
+
+    let mut chunks = Vec::new();
+    let mut oversized = Vec::new();
+    let (mut start, mut used) = (0, 0);
+    for (i, post) in posts.iter().enumerate() {
+        let mut rendered = String::new();
+        push_posts(
+            &mut rendered,
+            std::slice::from_ref(post),
+            &mut [].iter().peekable(),
+            false,
+            options,
+        );
+        let cost = tokens::count(&rendered);
+        if cost > budget {
+            oversized.push(post.post_number);
+        }
+        if i > start && used + cost > budget {
+            chunks.push(&posts[start..i]);
+            (start, used) = (i, 0);
+        }
+        used += cost;
+    }
+    if start < posts.len() {
+        chunks.push(&posts[start..]);
+    }
+    (chunks, oversized)
+}
+
+/// Render each post to its own file, plus an `index.md` linking them.
+///
+/// Returns `(file_name, content)` pairs. Files are named by post number, padded
+/// so they sort in order, and each repeats the title header and the post's
+/// permalink so it can be read on its own.
+pub fn render_post_files(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> Vec<(String, String)> {
+    let width = posts
+        .iter()
+        .map(|post| post.post_number.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+
+    let mut files = Vec::new();
+    let mut index = String::new();
+    push_header(&mut index, title, source_url, posts.len(), options);
+    index.push_str(header_rule(options));
+
+    let mut failed = options.failed_posts.iter().peekable();
+    for (i, post) in posts.iter().enumerate() {
+        let file_name = format!("post-{:0width$}.md", post.post_number, width = width);
+
+        let mut out = String::new();
+        push_header(&mut out, title, source_url, 1, options);
+        out.push_str(&format!("- **Permalink**: {}\n", permalink(options, post)));
+        out.push_str(header_rule(options));
+        push_posts(
+            &mut out,
+            std::slice::from_ref(post),
+            &mut failed,
+            false,
+            options,
+        );
+        if i + 1 == posts.len() {
+            for failed in failed.by_ref() {
+                out.push_str(&failure_annotation(failed));
+            }
+        }
+
+        index.push_str(&format!(
+            "- [Post #{} by @{}]({})\n",
+            post.post_number, post.username, file_name
+        ));
+        files.push((file_name, out));
+    }
+
+    files.push(("index.md".to_string(), index));
+    files
+}
+
+/// Describe the post numbers covered by a run of posts, e.g. `posts #41–#80`.
+fn post_range(posts: &[CachedPost]) -> String {
+    match (posts.first(), posts.last()) {
+        (Some(first), Some(last)) if first.post_number != last.post_number => {
+            format!("posts #{}–#{}", first.post_number, last.post_number)
+        }
+        (Some(first), _) => format!("post #{}", first.post_number),
+        _ => "no posts".to_string(),
+    }
+}
+
+/// Start of the header line carrying the time of the scrape.
+const FETCHED_PREFIX: &str = "- **Fetched**: ";
+
+/// Start of the frontmatter line carrying the time of the scrape.
+const FETCHED_FRONTMATTER_PREFIX: &str = "fetched: ";
+
+/// Start of the line carrying the time of the scrape in a JSON document.
+const FETCHED_JSON_PREFIX: &str = "  \"fetched_at\": ";
+
+/// SHA-256 of a rendered document, as `sha256:<hex>`, ignoring the `Fetched`
+/// timestamp so an unchanged thread hashes the same on every run.
+///
+/// Only the first `Fetched` line of the header or frontmatter is skipped, which
+/// ends at its second blank line (or `</header>` in HTML); the same text in a
+/// post still counts.
+pub fn checksum(rendered: &str) -> String {
+    let mut hasher = Sha256::new();
+    let mut in_header = true;
+    let mut blank_lines = 0;
+    for line in rendered.split_inclusive('\n') {
+        let fetched = [
+            FETCHED_PREFIX,
+            FETCHED_JSON_PREFIX,
+            FETCHED_HTML_PREFIX,
+            FETCHED_ORG_PREFIX,
+            FETCHED_TEXT_PREFIX,
+            FETCHED_FRONTMATTER_PREFIX,
+        ]
+        .iter()
+        .any(|prefix| line.starts_with(prefix));
+        if in_header && fetched {
+            in_header = false;
+            continue;
+        }
+        if line.trim().is_empty() {
+            blank_lines += 1;
+        }
+        if blank_lines == 2 || line.starts_with("</header>") {
+            in_header = false;
+        }
+        hasher.update(line.as_bytes());
     }
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+fn push_header(
+    out: &mut String,
+    title: &str,
+    source_url: &str,
+    post_count: usize,
+    options: &RenderOptions,
+) {
+    out.push_str(&format!("# {}\n\n", title));
+    push_details(out, source_url, Some(post_count), options);
+}
+
+/// Render the start of a document whose posts are written as they're fetched:
+/// the title and the topic's details, without the post count, which isn't
+/// known yet.
+pub fn render_stream_header(title: &str, source_url: &str, options: &RenderOptions) -> String {
+    let mut out = format!("# {}\n\n", title);
+    push_details(&mut out, source_url, None, options);
+    out.push_str(header_rule(options));
+    out
+}
 
+/// Render one post of a document whose posts are written as they're fetched.
+pub fn render_stream_post(post: &CachedPost, options: &RenderOptions) -> String {
+    let mut out = String::new();
+    push_post(&mut out, post, 0, false, options);
     out
 }
+
+/// The topic details listed under the title.
+fn push_details(
+    out: &mut String,
+    source_url: &str,
+    post_count: Option<usize>,
+    options: &RenderOptions,
+) {
+    out.push_str(&format!("- **Source**: {}\n", source_url));
+    if let Some(category) = &options.category {
+        out.push_str(&format!("- **Category**: {}\n", category));
+    }
+    if !options.reproducible {
+        let now = options.dates.format(chrono::Utc::now());
+        out.push_str(&format!("{}{}\n", FETCHED_PREFIX, now));
+    }
+    if let Some(post_count) = post_count {
+        out.push_str(&format!("- **Posts**: {}\n", post_count));
+    }
+    if !options.participants.is_empty() && !options.sections().contains(&Section::Roster) {
+        let roster: Vec<String> = options
+            .participants
+            .iter()
+            .map(|(username, count)| format!("@{} ({})", username, count))
+            .collect();
+        out.push_str(&format!("- **Participants**: {}\n", roster.join(", ")));
+    }
+    if let Some(warning) = &options.stale_warning {
+        out.push_str(&format!("- ⚠️ {}\n", warning));
+    }
+}
+
+/// The topic details as a `---`-delimited YAML frontmatter block, for
+/// note-taking apps and static site generators. For Pandoc, it also gives the
+/// participants as `author`s and the topic's first day as its `date`; for Hugo
+/// and Jekyll, the first post's time as its `date`, and its `slug`, `tags`,
+/// `categories` and `canonical_url`.
+fn push_frontmatter(
+    out: &mut String,
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) {
+    let usernames: Vec<String> = options
+        .participants
+        .iter()
+        .map(|(username, _)| username.clone())
+        .collect();
+    out.push_str("---\n");
+    out.push_str(&format!("title: {}\n", yaml_string(title)));
+    if options.pandoc {
+        if !usernames.is_empty() {
+            push_yaml_list(out, "author", &usernames);
+        }
+        if let Some(first) = posts.iter().map(|post| post.created_at).min() {
+            let date = options.dates.format_as(first, "%Y-%m-%d");
+            out.push_str(&format!("date: {}\n", yaml_string(&date)));
+        }
+    }
+    if options.static_site {
+        if let Some(first) = posts.iter().map(|post| post.created_at).min() {
+            let date = first.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            out.push_str(&format!("date: {}\n", date));
+        }
+        if !options.slug.is_empty() {
+            out.push_str(&format!("slug: {}\n", yaml_string(&options.slug)));
+        }
+        push_yaml_list(out, "tags", &options.tags);
+        push_yaml_list(out, "categories", options.category.as_slice());
+        out.push_str(&format!(
+            "canonical_url: {}\n",
+            yaml_string(&options.topic_url)
+        ));
+    }
+    out.push_str(&format!("source: {}\n", yaml_string(source_url)));
+    if let Some(category) = &options.category {
+        out.push_str(&format!("category: {}\n", yaml_string(category)));
+    }
+    if !options.reproducible {
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        out.push_str(&format!("{}{}\n", FETCHED_FRONTMATTER_PREFIX, now));
+    }
+    out.push_str(&format!("post_count: {}\n", posts.len()));
+    push_yaml_list(out, "participants", &usernames);
+    if let Some(warning) = &options.stale_warning {
+        out.push_str(&format!("warning: {}\n", yaml_string(warning)));
+    }
+    out.push_str("---\n\n");
+}
+
+/// A frontmatter field listing `values`, or `[]` when there are none.
+fn push_yaml_list(out: &mut String, key: &str, values: &[String]) {
+    if values.is_empty() {
+        out.push_str(&format!("{}: []\n", key));
+        return;
+    }
+    out.push_str(&format!("{}:\n", key));
+    for value in values {
+        out.push_str(&format!("  - {}\n", yaml_string(value)));
+    }
+}
+
+/// Count the posts by each author, ordered by `order`.
+///
+/// Usernames are compared with Unicode collation rather than byte order, so
+/// accented and non-Latin names sort where readers expect them.
+pub fn participant_roster(posts: &[CachedPost], order: ParticipantOrder) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for post in posts {
+        match counts.iter_mut().find(|(name, _)| *name == post.username) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((post.username.clone(), 1)),
+        }
+    }
+
+    let mut collator = feruca::Collator::default();
+    counts.sort_by(|(a, a_count), (b, b_count)| {
+        let by_count = match order {
+            ParticipantOrder::Count => b_count.cmp(a_count),
+            ParticipantOrder::Name => std::cmp::Ordering::Equal,
+        };
+        by_count.then_with(|| collator.collate(a.as_str(), b.as_str()))
+    });
+    counts
+}
+
+/// Describe an age in days roughly, e.g. `3 years`, `5 months` or `12 days`.
+pub fn describe_age(days: i64) -> String {
+    let (n, unit) = if days >= 365 {
+        (days / 365, "year")
+    } else if days >= 30 {
+        (days / 30, "month")
+    } else {
+        (days, "day")
+    };
+    format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+}
+
+/// Separator between the document header and the first post. YAML blocks bring
+/// their own `---` delimiters, so an extra rule would read as an empty block.
+fn header_rule(options: &RenderOptions) -> &'static str {
+    match options.format {
+        OutputFormat::YamlBlocks => "\n",
+        _ => "\n---\n\n",
+    }
+}
+
+/// Post #1 as the lead of a Q&A layout: its body, then who asked and when.
+fn push_question(out: &mut String, post: &CachedPost, options: &RenderOptions) {
+    push_anchor(out, post, options);
+    let body = post_body(post, options);
+    out.push_str(body.trim_matches('\n'));
+    if options.citations {
+        out.push_str(&format!("\n\n{}", citation_marker(post)));
+    }
+    out.push_str(&format!(
+        "\n\n*Asked by @{} ({})*\n\n",
+        post.username,
+        options.dates.format(post.created_at)
+    ));
+}
+
+/// The anchor the table of contents links a post to, when it has one.
+fn push_anchor(out: &mut String, post: &CachedPost, options: &RenderOptions) {
+    if options.anchors() {
+        out.push_str(&format!("<a id=\"post-{}\"></a>\n", post.post_number));
+    }
+}
+
+/// Whether the author is on the forum's staff: `staff (admin)`, `staff
+/// (moderator)`, or just `staff`.
+pub fn role_note(post: &CachedPost) -> Option<String> {
+    match post.role.as_deref()? {
+        "staff" => Some("staff".to_string()),
+        role => Some(format!("staff ({})", role)),
+    }
+}
+
+/// Who a post replies to, e.g. `in reply to #3 by @alice`, or just `in reply
+/// to #3` if the author of #3 isn't known.
+pub fn reply_note(post: &CachedPost, options: &RenderOptions) -> Option<String> {
+    let parent = post.reply_to_post_number?;
+    Some(match options.post_authors.get(&parent) {
+        Some(author) => format!("in reply to #{} by @{}", parent, author),
+        None => format!("in reply to #{}", parent),
+    })
+}
+
+/// How the community received a post: `4 likes`, or with the reactions plugin
+/// `4 reactions (:heart: 3, :tada: 1)`. `None` for a post with neither.
+pub fn appreciation_note(post: &CachedPost) -> Option<String> {
+    let plural = |n: u64, noun: &str| format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" });
+    if !post.reactions.is_empty() {
+        let total = post.reactions.iter().map(|r| r.count).sum();
+        let each: Vec<String> = post
+            .reactions
+            .iter()
+            .map(|r| format!(":{}: {}", r.id, r.count))
+            .collect();
+        return Some(format!(
+            "{} ({})",
+            plural(total, "reaction"),
+            each.join(", ")
+        ));
+    }
+    (post.like_count > 0).then(|| plural(post.like_count, "like"))
+}
+
+/// Marks the topic's accepted solution in its header.
+pub const ACCEPTED_SOLUTION: &str = "✅ ACCEPTED SOLUTION";
+
+/// [`role_note`], [`reply_note`] and [`appreciation_note`] as they follow the
+/// date in a post header, then [`ACCEPTED_SOLUTION`] for the accepted solution.
+pub fn header_suffix(post: &CachedPost, options: &RenderOptions) -> String {
+    let notes = [
+        role_note(post),
+        reply_note(post, options),
+        appreciation_note(post),
+    ];
+    let mut suffix: String = notes
+        .into_iter()
+        .flatten()
+        .map(|note| format!(", {}", note))
+        .collect();
+    if options.is_accepted_answer(post) {
+        suffix.push_str(&format!(" — {}", ACCEPTED_SOLUTION));
+    }
+    suffix
+}
+
+/// Permalink to a post within the topic.
+pub fn permalink(options: &RenderOptions, post: &CachedPost) -> String {
+    format!("{}/{}", options.topic_url, post.post_number)
+}
+
+/// The placeholders a `--post-header` template may use.
+pub const POST_HEADER_FIELDS: [&str; 5] = ["post_number", "username", "date", "permalink", "notes"];
+
+/// Fill in a post header template's `{field}` placeholders; see
+/// [`RenderOptions::post_header`].
+fn fill_post_header(
+    template: &str,
+    post: &CachedPost,
+    date: &str,
+    notes: &str,
+    options: &RenderOptions,
+) -> String {
+    let placeholder = Regex::new(r"\{(\w+)\}").expect("placeholder regex is valid");
+    placeholder
+        .replace_all(template, |caps: &regex::Captures| match &caps[1] {
+            "post_number" => post.post_number.to_string(),
+            "username" => post.username.clone(),
+            "date" => date.to_string(),
+            "permalink" => permalink(options, post),
+            "notes" => notes.to_string(),
+            _ => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// Render one post; with `--threaded`, `depth` is how deeply it's nested in
+/// the reply tree. `as_answer` heads it as an answer in a Q&A layout.
+fn push_post(
+    out: &mut String,
+    post: &CachedPost,
+    depth: usize,
+    as_answer: bool,
+    options: &RenderOptions,
+) {
+    if options.metadata_separators {
+        let metadata = serde_json::json!({
+            "post_number": post.post_number,
+            "post_id": post.post_id,
+            "author": post.username,
+            "created_at": post.created_at,
+            "permalink": permalink(options, post),
+            "reply_to_post_number": post.reply_to_post_number,
+            "role": post.role,
+            "like_count": post.like_count,
+            "reactions": post.reactions,
+            "accepted_answer": options.is_accepted_answer(post),
+            "action_code": post.action_code,
+        });
+        out.push_str(&format!("<!-- disc-scrape:post {} -->\n", metadata));
+    }
+    push_anchor(out, post, options);
+
+    let date = options.dates.format(post.created_at);
+    let marker = if options.context_post_ids.contains(&post.post_id) {
+        " [context]"
+    } else {
+        ""
+    };
+    let body = post_body(post, options);
+    match options.format {
+        OutputFormat::Markdown => {
+            let base = options.heading_level.unwrap_or(2);
+            let level = if options.group_size.is_some() {
+                "#".repeat((base + 1).min(6))
+            } else {
+                // Replies nest a heading level deeper, as far as Markdown goes
+                "#".repeat((base + depth).min(6))
+            };
+            let notes = format!("{}{}", header_suffix(post, options), marker);
+            let heading = match &options.post_header {
+                Some(template) => fill_post_header(template, post, &date, &notes, options),
+                None => {
+                    let kind = if as_answer { "Answer" } else { "Post" };
+                    let mut label = format!("{} #{}", kind, post.post_number);
+                    if options.permalinks {
+                        label = format!("[{}]({})", label, permalink(options, post));
+                    }
+                    format!("{} by @{} ({}){}", label, post.username, date, notes)
+                }
+            };
+            out.push_str(&format!("{} {}\n\n", level, heading));
+            out.push_str(&body);
+        }
+        OutputFormat::Chat => {
+            out.push_str(&"↳ ".repeat(depth));
+            out.push_str(&format!("@{} ({})", post.username, date));
+            if options.permalinks {
+                out.push_str(&format!(
+                    " [#{}]({})",
+                    post.post_number,
+                    permalink(options, post)
+                ));
+            }
+            out.push_str(&format!("{}{}:", header_suffix(post, options), marker));
+            // Block-level Markdown only works at the start of a line
+            let body = body.trim_start_matches('\n');
+            if starts_with_block(body) {
+                out.push('\n');
+            } else {
+                out.push(' ');
+            }
+            out.push_str(body);
+        }
+        OutputFormat::YamlBlocks => {
+            out.push_str("---\n");
+            out.push_str(&format!("post_number: {}\n", post.post_number));
+            out.push_str(&format!("author: {}\n", yaml_string(&post.username)));
+            out.push_str(&format!(
+                "created_at: {}\n",
+                yaml_string(&post.created_at.to_rfc3339())
+            ));
+            out.push_str(&format!(
+                "permalink: {}\n",
+                yaml_string(&permalink(options, post))
+            ));
+            if let Some(parent) = post.reply_to_post_number {
+                out.push_str(&format!("reply_to_post_number: {}\n", parent));
+            }
+            if let Some(role) = &post.role {
+                out.push_str(&format!("role: {}\n", role));
+            }
+            if options.threaded {
+                out.push_str(&format!("depth: {}\n", depth));
+            }
+            if post.like_count > 0 {
+                out.push_str(&format!("like_count: {}\n", post.like_count));
+            }
+            if !post.reactions.is_empty() {
+                out.push_str("reactions:\n");
+                for reaction in &post.reactions {
+                    out.push_str(&format!(
+                        "  {}: {}\n",
+                        yaml_string(&reaction.id),
+                        reaction.count
+                    ));
+                }
+            }
+            if options.is_accepted_answer(post) {
+                out.push_str("accepted_answer: true\n");
+            }
+            if !marker.is_empty() {
+                out.push_str("context: true\n");
+            }
+            if let Some(code) = &post.action_code {
+                out.push_str(&format!("action_code: {}\n", yaml_string(code)));
+            }
+            out.push_str("---\n\n");
+            out.push_str(&body);
+        }
+        OutputFormat::Json
+        | OutputFormat::Jsonl
+        | OutputFormat::Html
+        | OutputFormat::Org
+        | OutputFormat::Epub
+        | OutputFormat::Pdf
+        | OutputFormat::Text
+        | OutputFormat::Sqlite
+        | OutputFormat::Csv => {
+            unreachable!("only Markdown documents are rendered post by post")
+        }
+    }
+    if !body.ends_with('\n') {
+        out.push('\n');
+    }
+    if options.citations {
+        // A paragraph of its own, so it can't run into a table or quote
+        out.push_str(&format!("\n{}\n", citation_marker(post)));
+    }
+    if options.metadata_separators {
+        out.push_str("<!-- disc-scrape:end-post -->\n");
+    }
+    match options.format {
+        OutputFormat::Markdown => match options.post_separator.as_deref() {
+            None => out.push_str("\n---\n\n"),
+            Some("") => out.push('\n'),
+            Some(separator) => out.push_str(&format!("\n{}\n\n", separator)),
+        },
+        OutputFormat::Chat | OutputFormat::YamlBlocks => out.push('\n'),
+        OutputFormat::Json
+        | OutputFormat::Jsonl
+        | OutputFormat::Html
+        | OutputFormat::Org
+        | OutputFormat::Epub
+        | OutputFormat::Pdf
+        | OutputFormat::Text
+        | OutputFormat::Sqlite
+        | OutputFormat::Csv => {
+            unreachable!("only Markdown documents are rendered post by post")
+        }
+    }
+}
+
+/// Quote a string as a YAML scalar. A JSON string is a valid YAML double-quoted
+/// scalar, with quotes, backslashes and control characters escaped.
+fn yaml_string(value: &str) -> String {
+    serde_json::to_string(value).expect("strings always serialize")
+}
+
+/// Whether text opens with a block-level construct (quote, code fence, list,
+/// heading, table, image or BBCode block) that must start its own line.
+fn starts_with_block(text: &str) -> bool {
+    let first = text.lines().next().unwrap_or("").trim_start();
+    markdown::fence_marker(first).is_some()
+        || first.starts_with(['>', '#', '|', '[', '!', '-', '*', '+', '<'])
+        || first
+            .split_once(". ")
+            .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Order posts by post number instead of the topic's stream order.
+///
+/// The stream is usually chronological, but merged topics and moved posts can
+/// leave it out of order.
+pub fn sort_by_post_number(posts: &mut [CachedPost]) {
+    posts.sort_by_key(|post| post.post_number);
+}
+
+/// Order posts by like count, most liked first. Posts with as many likes keep
+/// their order.
+pub fn sort_by_likes(posts: &mut [CachedPost]) {
+    posts.sort_by_key(|post| std::cmp::Reverse(post.like_count));
+}
+
+/// Keep the `n` most-liked posts, in their current order. Among posts with as
+/// many likes, earlier ones are kept.
+pub fn keep_most_liked(posts: &mut Vec<CachedPost>, n: usize) {
+    let mut ranked: Vec<&CachedPost> = posts.iter().collect();
+    ranked.sort_by_key(|post| std::cmp::Reverse(post.like_count));
+    let kept: HashSet<u64> = ranked.iter().take(n).map(|post| post.post_id).collect();
+    posts.retain(|post| kept.contains(&post.post_id));
+}
+
+/// Maximum length of a slug produced by [`slugify`].
+const MAX_SLUG_LEN: usize = 80;
+
+/// Turn a topic title into a lowercase, hyphenated slug safe for filenames.
+///
+/// Unicode is transliterated to ASCII, punctuation becomes hyphens, and the
+/// result is cut at a word boundary to at most 80 characters.
+pub fn slugify(title: &str) -> String {
+    let ascii = deunicode::deunicode(title).to_lowercase();
+    let words: Vec<&str> = ascii
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    let mut slug = String::new();
+    for word in words {
+        let extra = if slug.is_empty() { 0 } else { 1 };
+        if slug.len() + extra + word.len() > MAX_SLUG_LEN {
+            if slug.is_empty() {
+                slug.push_str(&word[..MAX_SLUG_LEN]);
+            }
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(word);
+    }
+
+    if slug.is_empty() {
+        "topic".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Expand the `{title}`, `{title_slug}` and `{topic_id}` placeholders in an output path.
+pub fn expand_output_path(template: &str, title: &str, topic_id: u64) -> String {
+    template
+        .replace("{title_slug}", &slugify(title))
+        .replace("{title}", title)
+        .replace("{topic_id}", &topic_id.to_string())
+}
+
+/// The body of a post as it appears in the rendered document.
+pub fn post_body(post: &CachedPost, options: &RenderOptions) -> String {
+    let mut body = if options.normalize_whitespace {
+        normalize_whitespace(&post.raw)
+    } else {
+        post.raw.clone()
+    };
+    if options.repair_markdown {
+        body = repair_markdown(&body);
+    }
+    if let Some(code) = &post.action_code {
+        body = format!("> ℹ️ {}\n\n{}", describe_action(code), body);
+    }
+    body
+}
+
+/// Explain a Discourse moderator action code, so the reader knows why a thread's
+/// chronology or context jumps.
+pub fn describe_action(code: &str) -> String {
+    match code {
+        "split_topic" => {
+            "Moderator action: posts were moved from here to another topic".to_string()
+        }
+        _ => format!("Moderator action: `{}`", code),
+    }
+}
+
+/// Close a code fence that a post leaves open, so it can't swallow the posts
+/// rendered after it.
+pub fn repair_markdown(text: &str) -> String {
+    let Some((ch, len)) = markdown::unclosed_fence(text) else {
+        return text.to_string();
+    };
+    let mut out = text.to_string();
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.extend(std::iter::repeat_n(ch, len));
+    out.push('\n');
+    out
+}
+
+/// Extract a one-line plain-text preview of a post, at most `max_chars` long.
+///
+/// Uses the first line of actual prose: quotes, headings, images, code blocks and
+/// horizontal rules are skipped, and inline Markdown is stripped.
+pub fn excerpt(raw: &str, max_chars: usize) -> Option<String> {
+    let mut quote_depth = 0usize;
+    for (line, in_code) in markdown::classify_lines(raw) {
+        if in_code {
+            continue;
+        }
+        let line = line.trim();
+        // Discourse `[quote]` blocks may span many lines
+        let opens = line.matches("[quote").count();
+        let closes = line.matches("[/quote]").count();
+        if opens > 0 || closes > 0 || quote_depth > 0 {
+            quote_depth = (quote_depth + opens).saturating_sub(closes);
+            continue;
+        }
+        if line.starts_with('>') || line.starts_with('#') || line.starts_with('|') {
+            continue;
+        }
+        if line.chars().all(|c| matches!(c, '-' | '*' | '_' | ' ')) {
+            continue;
+        }
+        let text = strip_inline_markdown(line);
+        if !text.is_empty() {
+            return Some(truncate(&text, max_chars));
+        }
+    }
+    None
+}
+
+/// Posts with at most this many characters of their own text besides quotes
+/// count as quote-only.
+const QUOTE_ONLY_MAX_CHARS: usize = 20;
+
+/// Whether a post is a `[quote]` of another post with little or nothing added,
+/// e.g. a quote followed by "this".
+pub fn is_quote_only(raw: &str) -> bool {
+    let rest = strip_quotes(raw);
+    if rest.len() == raw.len() {
+        return false;
+    }
+    let own_chars: usize = rest
+        .lines()
+        .map(|line| strip_inline_markdown(line).chars().count())
+        .sum();
+    own_chars <= QUOTE_ONLY_MAX_CHARS
+}
+
+/// Remove Discourse `[quote]...[/quote]` blocks, including nested ones, from Markdown.
+/// Quote tags inside code are not treated as quotes.
+pub fn strip_quotes(raw: &str) -> String {
+    replace_quotes(raw, |_, _, _| String::new())
+}
+
+/// Replace each Discourse quote with a note of who and what it quotes, e.g.
+/// `(quoting @alice, post #3)`, adding the topic when it's not `topic_id`.
+pub fn collapse_quotes(raw: &str, topic_id: u64) -> String {
+    replace_quotes(raw, |attrs, _, _| quote_note(attrs, topic_id))
+}
+
+/// Replace quotes whose text an earlier post already shows with a note pointing
+/// back to it, e.g. `(quoting @alice, post #1, shown above)`, so threads where
+/// everyone quotes the opening post don't repeat it each time. Returns how many
+/// quotes were replaced.
+///
+/// A quote is shown when its text, ignoring Markdown and spacing, appears in an
+/// earlier post, quotes included. Quotes no longer than their note are kept.
+pub fn dedupe_quotes(posts: &mut [CachedPost], topic_id: u64) -> usize {
+    let mut shown: Vec<String> = Vec::new();
+    let mut count = 0;
+    for post in posts.iter_mut() {
+        post.raw = replace_quotes(&post.raw, |attrs, body, quote| {
+            let text = comparable_text(body);
+            let note = quote_note(attrs, topic_id);
+            let note = format!("{}, shown above)", note.trim_end_matches(')'));
+            if text.chars().count() > note.chars().count()
+                && shown.iter().any(|earlier| earlier.contains(&text))
+            {
+                count += 1;
+                note
+            } else {
+                quote.to_string()
+            }
+        });
+        shown.push(comparable_text(&post.raw));
+    }
+    count
+}
+
+/// Markdown reduced to plain text with its whitespace collapsed, for finding
+/// the same words written differently.
+fn comparable_text(markdown: &str) -> String {
+    let text: Vec<String> = markdown.lines().map(strip_inline_markdown).collect();
+    text.join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A note of who and what a quote quotes, from its opening tag's attributes,
+/// e.g. `(quoting @alice, post #3)`; see [`collapse_quotes`].
+fn quote_note(attrs: &str, topic_id: u64) -> String {
+    let field = |attrs: &str, key: &str| {
+        attrs
+            .split(',')
+            .find_map(|part| part.trim().strip_prefix(key))
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    let attrs = attrs.trim_start_matches(['=', ' ']).trim_matches('"');
+    let username = attrs
+        .split(',')
+        .next()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.contains(':'));
+    let mut note = match username {
+        Some(username) => format!("(quoting @{}", username),
+        None => "(quoting".to_string(),
+    };
+    if let Some(post) = field(attrs, "post:") {
+        if username.is_some() {
+            note.push(',');
+        }
+        note.push_str(&format!(" post #{}", post));
+    }
+    match field(attrs, "topic:") {
+        Some(topic) if topic != topic_id => note.push_str(&format!(" in topic {}", topic)),
+        _ => {}
+    }
+    if note == "(quoting" {
+        note.push_str(" another post");
+    }
+    note.push(')');
+    note
+}
+
+/// Replace each outermost Discourse quote with what `f` makes of its opening
+/// tag's attributes (`="alice, post:3, topic:1"`), its contents (nested quotes
+/// included) and the whole quote as written. Tags in code don't count.
+fn replace_quotes(raw: &str, mut f: impl FnMut(&str, &str, &str) -> String) -> String {
+    let tag = Regex::new(r"(?i)\[quote([= ][^\]]*)?\]|\[/quote\]").expect("quote regex is valid");
+    let mut out = String::with_capacity(raw.len());
+    let mut depth = 0usize;
+    // The outermost open quote's attributes, contents and text so far
+    let mut attrs = String::new();
+    let mut body = String::new();
+    let mut quote = String::new();
+    for (line, in_code) in markdown::classify_lines(raw) {
+        if in_code {
+            if depth == 0 {
+                out.push_str(line);
+            } else {
+                body.push_str(line);
+                quote.push_str(line);
+            }
+            continue;
+        }
+        let mut last = 0;
+        for caps in tag.captures_iter(line) {
+            let m = caps.get(0).expect("a match has a whole");
+            if depth == 0 {
+                out.push_str(&line[last..m.start()]);
+            } else {
+                body.push_str(&line[last..m.start()]);
+                quote.push_str(&line[last..m.start()]);
+            }
+            if m.as_str().starts_with("[/") {
+                match depth {
+                    // A stray closing tag is dropped
+                    0 => {}
+                    1 => {
+                        quote.push_str(m.as_str());
+                        out.push_str(&f(&attrs, &body, &quote));
+                        body.clear();
+                        quote.clear();
+                        depth = 0;
+                    }
+                    _ => {
+                        body.push_str(m.as_str());
+                        quote.push_str(m.as_str());
+                        depth -= 1;
+                    }
+                }
+            } else {
+                if depth == 0 {
+                    attrs = caps.get(1).map_or("", |a| a.as_str()).to_string();
+                } else {
+                    body.push_str(m.as_str());
+                }
+                quote.push_str(m.as_str());
+                depth += 1;
+            }
+            last = m.end();
+        }
+        if depth == 0 {
+            out.push_str(&line[last..]);
+        } else {
+            body.push_str(&line[last..]);
+            quote.push_str(&line[last..]);
+        }
+    }
+    // A quote left open runs to the end of the post
+    if depth > 0 {
+        out.push_str(&f(&attrs, &body, &quote));
+    }
+    out
+}
+
+/// Reduce a line of Markdown to plain text.
+fn strip_inline_markdown(line: &str) -> String {
+    let rules = [
+        (r"!\[[^\]]*\]\([^)]*\)", ""),
+        (r"\[([^\]]*)\]\([^)]*\)", "$1"),
+        (r"<[^>]+>", ""),
+        (r"\[/?[a-zA-Z]+[^\]]*\]", ""),
+        (r"^(?:[-*+]|\d+\.)\s+", ""),
+        (r"\*+|~~|`", ""),
+        (r"(^|\s)_+|_+($|\s|[.,;:!?])", "$1$2"),
+        (r"\s+", " "),
+    ];
+    let mut text = line.to_string();
+    for (pattern, replacement) in rules {
+        let re = Regex::new(pattern).expect("markdown stripping regex is valid");
+        text = re.replace_all(&text, replacement).into_owned();
+    }
+    text.trim().to_string()
+}
+
+/// Shorten `text` to at most `max_chars` characters, ending with `…` if cut.
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    format!("{}…", cut.trim_end())
+}
+
+/// Normalize line endings to `\n`, trim trailing whitespace, collapse runs of
+/// blank lines into one, and drop leading/trailing blank lines.
+///
+/// Fenced code blocks keep their whitespace (apart from line endings).
+pub fn normalize_whitespace(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut out = String::with_capacity(text.len());
+    let mut pending_blank = false;
+
+    for (line, in_code) in markdown::classify_lines(&text) {
+        let trimmed = line.trim_end();
+        if !in_code && trimmed.is_empty() {
+            pending_blank = !out.is_empty();
+            continue;
+        }
+        if pending_blank {
+            out.push('\n');
+            pending_blank = false;
+        }
+        if in_code {
+            out.push_str(line);
+        } else {
+            out.push_str(trimmed);
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::test_post;
+
+    #[test]
+    fn test_is_quote_only() {
+        let quote =
+            "[quote=\"bob, post:2, topic:1\"]\nA long point\n[quote]nested[/quote]\n[/quote]\n";
+        assert!(is_quote_only(&format!("{}\nThis! :+1:", quote)));
+        assert!(is_quote_only(quote));
+        assert!(!is_quote_only(&format!(
+            "{}\nI disagree, because the cache is keyed by post ID.",
+            quote
+        )));
+        assert!(!is_quote_only("Thanks!"));
+        assert_eq!(strip_quotes("a [quote]b[/quote] c"), "a  c");
+    }
+
+    #[test]
+    fn test_collapse_quotes() {
+        let raw =
+            "[quote=\"bob, post:2, topic:1\"]\nA long point\n[quote]nested[/quote]\n[/quote]\n\
+                   Agreed.\n\n[quote=\"carol, post:5, topic:9, full:true\"]\nElsewhere\n[/quote]\n\
+                   [quote]anon[/quote] and [QUOTE=\"post:4, topic:1\"]x[/QUOTE]\n\
+                   ```\n[quote]code[/quote]\n```\n";
+        assert_eq!(
+            collapse_quotes(raw, 1),
+            "(quoting @bob, post #2)\nAgreed.\n\n(quoting @carol, post #5 in topic 9)\n\
+             (quoting another post) and (quoting post #4)\n\
+             ```\n[quote]code[/quote]\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_dedupe_quotes() {
+        let opening = "We should **move** the build to the new CI runners before the release.";
+        let mut posts = vec![
+            test_post(1, "alice", opening),
+            test_post(
+                2,
+                "bob",
+                "[quote=\"alice, post:1, topic:7\"]\nWe should move the build to the new CI\n\
+                 runners before the release.\n[/quote]\nAgreed.\n[quote=\"alice, post:1, topic:7\"]\nmove\n[/quote]\n",
+            ),
+            test_post(
+                3,
+                "carol",
+                "[QUOTE=\"dave, post:9, topic:8\"]\nSomething said in another topic entirely.\n[/QUOTE]\n\
+                 [quote=\"alice, post:1, topic:7\"]\nmove the build to the new CI runners before the release\n[/quote]\n",
+            ),
+        ];
+        assert_eq!(dedupe_quotes(&mut posts, 7), 2);
+        assert_eq!(posts[0].raw, opening);
+        assert_eq!(
+            posts[1].raw,
+            "(quoting @alice, post #1, shown above)\nAgreed.\n\
+             [quote=\"alice, post:1, topic:7\"]\nmove\n[/quote]\n"
+        );
+        assert_eq!(
+            posts[2].raw,
+            "[QUOTE=\"dave, post:9, topic:8\"]\nSomething said in another topic entirely.\n[/QUOTE]\n\
+             (quoting @alice, post #1, shown above)\n"
+        );
+    }
+
+    #[test]
+    fn test_participant_roster() {
+        let posts = [
+            test_post(1, "Zoë", "a"),
+            test_post(2, "bob", "b"),
+            test_post(3, "Émile", "c"),
+            test_post(4, "zack", "d"),
+            test_post(5, "zack", "e"),
+        ];
+        let names = |order| {
+            participant_roster(&posts, order)
+                .into_iter()
+                .map(|(name, count)| format!("{}:{}", name, count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(ParticipantOrder::Count),
+            ["zack:2", "bob:1", "Émile:1", "Zoë:1"]
+        );
+        assert_eq!(
+            names(ParticipantOrder::Name),
+            ["bob:1", "Émile:1", "zack:2", "Zoë:1"]
+        );
+    }
+
+    #[test]
+    fn test_render_frontmatter() {
+        let posts = [test_post(1, "alice", "Hi"), test_post(2, "bob", "Yo")];
+        let options = RenderOptions {
+            frontmatter: true,
+            reproducible: true,
+            category: Some("Help".to_string()),
+            participants: vec![("alice".to_string(), 1), ("bob \"b\"".to_string(), 1)],
+            ..Default::default()
+        };
+        let out = render("Title: \"quoted\"", "https://x.test/t/1", &posts, &options);
+        assert!(out.starts_with(
+            "---\ntitle: \"Title: \\\"quoted\\\"\"\nsource: \"https://x.test/t/1\"\n\
+             category: \"Help\"\npost_count: 2\nparticipants:\n  - \"alice\"\n  - \"bob \\\"b\\\"\"\n\
+             ---\n\n# Title: \"quoted\"\n\n---\n\n## Post #1"
+        ));
+        assert!(!out.contains("**Source**"));
+
+        let options = RenderOptions {
+            frontmatter: true,
+            ..Default::default()
+        };
+        let out = render("T", "src", &posts, &options);
+        assert!(out.contains("participants: []\n"));
+        let fetched = out.lines().find(|l| l.starts_with("fetched: ")).unwrap();
+        assert_eq!(
+            checksum(&out),
+            checksum(&out.replace(fetched, "fetched: x"))
+        );
+    }
+
+    #[test]
+    fn test_render_static_site() {
+        let posts = [test_post(1, "alice", "Hi"), test_post(2, "bob", "Yo")];
+        let options = RenderOptions {
+            frontmatter: true,
+            static_site: true,
+            reproducible: true,
+            slug: "my-topic".to_string(),
+            tags: vec!["nix".to_string()],
+            category: Some("Help".to_string()),
+            topic_url: "https://x.test/t/my-topic/1".to_string(),
+            ..Default::default()
+        };
+        let out = render("T", "https://x.test/t/1", &posts, &options);
+        assert!(out.starts_with(
+            "---\ntitle: \"T\"\ndate: 2026-02-20T10:00:00Z\nslug: \"my-topic\"\n\
+             tags:\n  - \"nix\"\ncategories:\n  - \"Help\"\n\
+             canonical_url: \"https://x.test/t/my-topic/1\"\nsource: \"https://x.test/t/1\"\n\
+             category: \"Help\"\npost_count: 2\nparticipants: []\n---\n\n## Post #1"
+        ));
+    }
+
+    #[test]
+    fn test_render_citations() {
+        let posts = [
+            test_post(1, "alice", "Hi"),
+            test_post(2, "bob", "| a |\n|---|\n| b |"),
+        ];
+        let options = RenderOptions {
+            sections: vec![Section::Posts],
+            citations: true,
+            topic_url: "https://x.test/t/t/1".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render("T", "src", &posts, &options),
+            "## Post #1 by @alice (2026-02-20 10:00 UTC)\n\nHi\n\n[^1]\n\n---\n\n\
+             ## Post #2 by @bob (2026-02-20 10:00 UTC)\n\n| a |\n|---|\n| b |\n\n[^2]\n\n---\n\n\
+             [^1]: Post #1 by @alice (2026-02-20 10:00 UTC), https://x.test/t/t/1/1\n\
+             [^2]: Post #2 by @bob (2026-02-20 10:00 UTC), https://x.test/t/t/1/2\n"
+        );
+    }
+
+    #[test]
+    fn test_compression_for_path() {
+        assert_eq!(Compression::for_path("t.md.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::for_path("t.md.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::for_path("t.md"), None);
+    }
+
+    #[test]
+    fn test_render_pandoc() {
+        let mut late = test_post(2, "bob", "Yo");
+        late.created_at = "2026-02-21T23:30:00Z".parse().unwrap();
+        let mut early = test_post(1, "alice", "Hi");
+        early.created_at = "2026-02-19T23:30:00Z".parse().unwrap();
+        let options = RenderOptions {
+            frontmatter: true,
+            pandoc: true,
+            reproducible: true,
+            participants: vec![("alice".to_string(), 1), ("bob".to_string(), 1)],
+            ..Default::default()
+        };
+        let out = render("T", "src", &[early, late], &options);
+        assert!(out.starts_with(
+            "---\ntitle: \"T\"\nauthor:\n  - \"alice\"\n  - \"bob\"\ndate: \"2026-02-19\"\n\
+             source: \"src\"\npost_count: 2\nparticipants:\n  - \"alice\"\n  - \"bob\"\n\
+             ---\n\n## Post #1"
+        ));
+        assert!(!out.contains("# T"));
+    }
+
+    #[test]
+    fn test_render_post_header_style() {
+        let mut reply = test_post(2, "bob", "Yo");
+        reply.reply_to_post_number = Some(1);
+        let posts = [test_post(1, "alice", "Hi"), reply];
+        let options = RenderOptions {
+            sections: vec![Section::Posts],
+            heading_level: Some(4),
+            post_header: Some("{username} wrote #{post_number} on {date}{notes}".to_string()),
+            post_separator: Some("* * *".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            render("Title", "src", &posts, &options),
+            "#### alice wrote #1 on 2026-02-20 10:00 UTC\n\nHi\n\n* * *\n\n\
+             #### bob wrote #2 on 2026-02-20 10:00 UTC, in reply to #1\n\nYo\n\n* * *\n\n"
+        );
+
+        let options = RenderOptions {
+            sections: vec![Section::Posts],
+            heading_level: Some(3),
+            group_size: Some(5),
+            post_separator: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(
+            render("Title", "src", &posts[..1], &options),
+            "### Post #1\n\n#### Post #1 by @alice (2026-02-20 10:00 UTC)\n\nHi\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_roster() {
+        let mut answer = test_post(2, "bob", "Answer");
+        answer.role = Some("moderator".to_string());
+        let mut follow_up = test_post(3, "alice", "Thanks");
+        follow_up.created_at += chrono::Duration::days(3);
+        let posts = [test_post(1, "alice", "Question"), answer, follow_up];
+        let options = RenderOptions {
+            participants: participant_roster(&posts, ParticipantOrder::Count),
+            post_authors: HashMap::from([(1, "alice".to_string())]),
+            sections: vec![Section::Roster],
+            ..Default::default()
+        };
+        assert_eq!(
+            render("Title", "src", &posts, &options),
+            "## Participants\n\n\
+             - @alice — OP, 2 posts, active 2026-02-20 to 2026-02-23\n\
+             - @bob — staff (moderator), 1 post, active 2026-02-20\n\n---\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_sections() {
+        let posts = [
+            test_post(1, "alice", "Question?"),
+            test_post(2, "bob", "Answer."),
+        ];
+        let options = RenderOptions {
+            reproducible: true,
+            participants: vec![("alice".to_string(), 1), ("bob".to_string(), 1)],
+            sections: vec![
+                Section::Roster,
+                Section::Toc,
+                Section::Posts,
+                Section::Stats,
+            ],
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.starts_with(
+            "## Participants\n\n- @alice — 1 post, active 2026-02-20\n\
+             - @bob — 1 post, active 2026-02-20\n\n---\n\n\
+             ## Contents\n\n- [Post #1 by @alice](#post-1) — Question?\n\
+             - [Post #2 by @bob](#post-2) — Answer.\n\n---\n\n\
+             <a id=\"post-1\"></a>\n## Post #1 by @alice"
+        ));
+        assert!(out.ends_with(
+            "## Stats\n\n- **Posts**: 2\n- **Participants**: 2\n- **Words**: 2\n\
+             - **Dates**: 2026-02-20 to 2026-02-20\n\n---\n\n"
+        ));
+        assert!(!out.contains("# Title"));
+
+        let options = RenderOptions {
+            sections: vec![Section::Digest],
+            ..Default::default()
+        };
+        let posts = [
+            test_post(1, "alice", "> quoted\n\nFirst **point**.\nMore."),
+            test_post(2, "bob", "![img](x.png)"),
+        ];
+        assert_eq!(
+            render("Title", "src", &posts, &options),
+            "## Digest\n\n- **#1 @alice**: First point.\n- **#2 @bob**: *(no text)*\n\n---\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_qa_layout() {
+        let posts = [
+            test_post(1, "alice", "How do I X?\n"),
+            test_post(2, "bob", "Like so."),
+        ];
+        let options = RenderOptions {
+            reproducible: true,
+            qa_layout: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert_eq!(
+            out,
+            "# Title\n\nHow do I X?\n\n*Asked by @alice (2026-02-20 10:00 UTC)*\n\n\
+             - **Source**: src\n- **Posts**: 2\n\n---\n\n\
+             ## Answer #2 by @bob (2026-02-20 10:00 UTC)\n\nLike so.\n\n---\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_qa_layout_without_header() {
+        let posts = [
+            test_post(1, "alice", "How do I X?"),
+            test_post(2, "bob", "Like so."),
+        ];
+        let options = RenderOptions {
+            reproducible: true,
+            qa_layout: true,
+            sections: vec![Section::Posts],
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains("## Post #1 by @alice"));
+        assert!(out.contains("## Post #2 by @bob"));
+        assert!(!out.contains("Answer"));
+    }
+
+    #[test]
+    fn test_render_stream_matches_render() {
+        let posts = [test_post(1, "alice", "Hi"), test_post(2, "bob", "Yo")];
+        let options = RenderOptions {
+            reproducible: true,
+            ..Default::default()
+        };
+        let mut streamed = render_stream_header("Title", "src", &options);
+        for post in &posts {
+            streamed.push_str(&render_stream_post(post, &options));
+        }
+        let rendered = render("Title", "src", &posts, &options);
+        assert_eq!(streamed, rendered.replace("- **Posts**: 2\n", ""));
+    }
+
+    #[test]
+    fn test_render_moved_posts() {
+        let mut moved = test_post(
+            2,
+            "mod",
+            "I moved 3 posts to a new topic: [Other](/t/other/9)",
+        );
+        moved.action_code = Some("split_topic".to_string());
+        let options = RenderOptions {
+            reproducible: true,
+            metadata_separators: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &[moved], &options);
+        assert!(out.contains(r#""action_code":"split_topic""#));
+        assert!(out.contains(
+            "> ℹ️ Moderator action: posts were moved from here to another topic\n\nI moved 3 posts"
+        ));
+    }
+
+    #[test]
+    fn test_render_failed_posts() {
+        let failed = |post_number| FailedPost {
+            post_number,
+            reason: "HTTP 404".to_string(),
+        };
+        let options = RenderOptions {
+            format: OutputFormat::Chat,
+            reproducible: true,
+            failed_posts: vec![failed(2), failed(4)],
+            ..Default::default()
+        };
+        let posts = [test_post(1, "alice", "Hi"), test_post(3, "bob", "Yo")];
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.ends_with(
+            "@alice (2026-02-20 10:00 UTC): Hi\n\n\
+             <!-- disc-scrape: post 2 failed: HTTP 404 -->\n\n\
+             @bob (2026-02-20 10:00 UTC): Yo\n\n\
+             <!-- disc-scrape: post 4 failed: HTTP 404 -->\n\n"
+        ));
+    }
+
+    #[test]
+    fn test_token_chunks() {
+        let options = RenderOptions {
+            reproducible: true,
+            ..Default::default()
+        };
+        let posts: Vec<CachedPost> = (1..=20)
+            .map(|n| test_post(n, "alice", &"word ".repeat(n as usize * 10)))
+            .collect();
+        let (chunks, oversized) = token_chunks("Title", "src", &posts, 600, &options);
+        assert!(oversized.is_empty());
+        assert!(chunks.len() > 2);
+        let numbers: Vec<u64> = chunks
+            .iter()
+            .flat_map(|c| c.iter().map(|p| p.post_number))
+            .collect();
+        assert_eq!(numbers, (1..=20).collect::<Vec<_>>());
+
+        let shards = render_shards("Title", "src", &posts, &chunks, &options);
+        for (name, content) in &shards[..chunks.len()] {
+            assert!(tokens::count(content) <= 600, "{} is too long", name);
+        }
+        assert!(shards[1]
+            .1
+            .contains(&format!("- **Part**: 2 of {} (posts #", chunks.len())));
+
+        let (chunks, oversized) = token_chunks("Title", "src", &posts, 150, &options);
+        assert_eq!(oversized, (10..=20).collect::<Vec<_>>());
+        assert_eq!(chunks.last().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_render_post_files() {
+        let options = RenderOptions {
+            reproducible: true,
+            topic_url: "https://x.test/t/t/1".to_string(),
+            failed_posts: vec![FailedPost {
+                post_number: 1200,
+                reason: "HTTP 404".to_string(),
+            }],
+            ..Default::default()
+        };
+        let posts = [test_post(1, "alice", "Hi"), test_post(1042, "bob", "Yo")];
+        let files = render_post_files("Title", "src", &posts, &options);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["post-0001.md", "post-1042.md", "index.md"]);
+
+        assert_eq!(
+            files[0].1,
+            "# Title\n\n- **Source**: src\n- **Posts**: 1\n\
+             - **Permalink**: https://x.test/t/t/1/1\n\n---\n\n\
+             ## Post #1 by @alice (2026-02-20 10:00 UTC)\n\nHi\n\n---\n\n"
+        );
+        assert!(files[1]
+            .1
+            .ends_with("Yo\n\n---\n\n<!-- disc-scrape: post 1200 failed: HTTP 404 -->\n\n"));
+        assert!(files[2].1.ends_with(
+            "- **Posts**: 2\n\n---\n\n\
+             - [Post #1 by @alice](post-0001.md)\n- [Post #1042 by @bob](post-1042.md)\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_groups() {
+        let posts = [
+            test_post(1, "alice", "One"),
+            test_post(2, "bob", "Two"),
+            test_post(3, "alice", "Three"),
+        ];
+        let options = RenderOptions {
+            reproducible: true,
+            group_size: Some(2),
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        let headings: Vec<&str> = out.lines().filter(|l| l.starts_with('#')).collect();
+        assert_eq!(
+            headings,
+            [
+                "# Title",
+                "## Posts #1–#2",
+                "### Post #1 by @alice (2026-02-20 10:00 UTC)",
+                "### Post #2 by @bob (2026-02-20 10:00 UTC)",
+                "## Post #3",
+                "### Post #3 by @alice (2026-02-20 10:00 UTC)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        let options = RenderOptions {
+            format: OutputFormat::Json,
+            topic_url: "https://discuss.example.com/t/title/7".to_string(),
+            ..Default::default()
+        };
+        let out = render("Title", "src", &[test_post(1, "alice", "Hi")], &options);
+        let doc: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(doc["title"], "Title");
+        assert!(doc["fetched_at"].is_string());
+        assert_eq!(doc["posts"][0]["username"], "alice");
+        assert_eq!(doc["posts"][0]["raw"], "Hi");
+        assert_eq!(
+            doc["posts"][0]["permalink"],
+            "https://discuss.example.com/t/title/7/1"
+        );
+        assert_eq!(
+            checksum(&out),
+            checksum(&render(
+                "Title",
+                "src",
+                &[test_post(1, "alice", "Hi")],
+                &options
+            ))
+        );
+    }
+
+    #[test]
+    fn test_merge_json() {
+        let options = RenderOptions {
+            format: OutputFormat::Json,
+            reproducible: true,
+            ..Default::default()
+        };
+        let existing = render(
+            "Old title",
+            "src",
+            &[
+                test_post(1, "alice", "Hi"),
+                test_post(2, "bob", "Old"),
+                test_post(3, "carol", "Only in the archive"),
+            ],
+            &options,
+        );
+        let fresh = [test_post(2, "bob", "Edited"), test_post(4, "dave", "New")];
+        let rendered = render("Title", "src", &fresh, &options);
+
+        let merged: serde_json::Value =
+            serde_json::from_str(&merge_json(&existing, &rendered).unwrap()).unwrap();
+        assert_eq!(merged["title"], "Title");
+        let posts = merged["posts"].as_array().unwrap();
+        let numbers: Vec<_> = posts.iter().map(|p| p["post_number"].clone()).collect();
+        assert_eq!(numbers, [1, 2, 3, 4]);
+        assert_eq!(posts[1]["raw"], "Edited");
+        assert_eq!(posts[2]["raw"], "Only in the archive");
+        assert!(merge_json("not json", &rendered).is_err());
+
+        let other = render("Other", "https://x.test/t/other/8", &fresh, &options);
+        let same = render("Title", "https://x.test/t/title/7/2", &fresh, &options);
+        let fresh = render("Title", "https://x.test/t/title/7", &fresh, &options);
+        assert!(merge_json(&other, &fresh).is_err());
+        assert!(merge_json(&existing, &fresh).is_err());
+        assert!(merge_json(&same, &fresh).is_ok());
+    }
+
+    #[test]
+    fn test_render_jsonl() {
+        let options = RenderOptions {
+            format: OutputFormat::Jsonl,
+            ..Default::default()
+        };
+        let posts = [
+            test_post(1, "alice", "Hi\nthere"),
+            test_post(2, "bob", "Yo"),
+        ];
+        let out = render("Title", "src", &posts, &options);
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["title"], "Title");
+        assert_eq!(lines[0]["raw"], "Hi\nthere");
+        assert_eq!(lines[1]["post_number"], 2);
+        assert_eq!(lines[1]["username"], "bob");
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let options = RenderOptions {
+            format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        let mut liked = test_post(2, "bob,\"b\"", "Yo  there\nfriend");
+        liked.like_count = 3;
+        let posts = [test_post(1, "alice", "Hi"), liked];
+        assert_eq!(
+            render("Title", "src", &posts, &options),
+            "post_number,post_id,username,created_at,words,like_count\n\
+             1,1001,alice,2026-02-20T10:00:00+00:00,1,0\n\
+             2,1002,\"bob,\"\"b\"\"\",2026-02-20T10:00:00+00:00,3,3\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        let options = RenderOptions {
+            format: OutputFormat::Html,
+            topic_url: "https://discuss.example.com/t/title/7".to_string(),
+            reproducible: true,
+            ..Default::default()
+        };
+        let mut cooked = test_post(1, "alice", "**Hi**");
+        cooked.cooked = Some("<p>ignored</p>".to_string());
+        let quoting = test_post(
+            3,
+            "carol",
+            "[quote=\"bob, post:2, topic:7\"]\n*no*\n[/quote]\n\nYes <script>x</script>",
+        );
+        let posts = [cooked, test_post(2, "bob", "a < b & \"c\""), quoting];
+        let out = render("Q&A", "src", &posts, &options);
+        assert!(out.starts_with("<!DOCTYPE html>"));
+        assert!(out.contains("<title>Q&amp;A</title>"));
+        assert!(out.contains("<base href=\"https://discuss.example.com/\">"));
+        assert!(
+            out.contains("<article id=\"post-1\">\n<h2><a href=\"#post-1\">Post #1</a> by @alice")
+        );
+        assert!(out.contains("</h2>\n<p><strong>Hi</strong></p>\n</article>"));
+        assert!(out.contains("<p>a &lt; b &amp; \"c\"</p>"));
+        assert!(out.contains(
+            "<aside class=\"quote\">\n<div class=\"title\">bob:</div>\n<p><em>no</em></p>\n</aside>"
+        ));
+        assert!(out.contains("Yes &lt;script&gt;x&lt;/script&gt;"));
+        assert!(out.ends_with("</html>\n"));
+    }
+
+    #[test]
+    fn test_markdown_html_link_schemes() {
+        let out = markdown_html(
+            "[a](javascript:alert(1)) [b](JavaScript:x) ![c](data:text/html,x) \
+             [d](https://example.com/) [e](/t/7) [f](mailto:a@example.com) [g](#post-2)",
+        );
+        assert!(!out.to_lowercase().contains("javascript:"));
+        assert!(!out.contains("data:"));
+        assert!(out.contains("<a href=\"#\">a</a>"));
+        assert!(out.contains("<img src=\"#\" alt=\"c\" />"));
+        assert!(out.contains("<a href=\"https://example.com/\">d</a>"));
+        assert!(out.contains("<a href=\"/t/7\">e</a>"));
+        assert!(out.contains("<a href=\"mailto:a@example.com\">f</a>"));
+        assert!(out.contains("<a href=\"#post-2\">g</a>"));
+        assert!(!is_safe_url("java\tscript:x"));
+        assert!(is_safe_url("page?next=a:b"));
+    }
+
+    #[test]
+    fn test_render_org() {
+        let options = RenderOptions {
+            format: OutputFormat::Org,
+            topic_url: "https://discuss.example.com/t/title/7".to_string(),
+            reproducible: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &[test_post(2, "bob", "**Yes**")], &options);
+        assert_eq!(
+            out,
+            "#+TITLE: Title\n\n- Source: [[src]]\n- Posts: 1\n\n\
+             * Post #2 by @bob\n:PROPERTIES:\n:CUSTOM_ID: post-2\n:AUTHOR: bob\n\
+             :DATE: [2026-02-20 Fri 10:00]\n:POST_ID: 1002\n\
+             :PERMALINK: https://discuss.example.com/t/title/7/2\n:END:\n\n*Yes*\n"
+        );
+    }
+
+    #[test]
+    fn test_render_appendix() {
+        let linked = [LinkedTopic {
+            title: "Other".to_string(),
+            url: "https://example.com/t/other/7".to_string(),
+            posts: vec![test_post(1, "carol", "Linked body")],
+        }];
+        let out = render_appendix(&linked, &RenderOptions::default());
+        assert_eq!(
+            out,
+            "# Appendix: Linked Topics\n\n## Other\n\n\
+             - **Source**: https://example.com/t/other/7\n- **Posts**: 1\n\n\
+             ### Post #1 by @carol (2026-02-20 10:00 UTC)\n\nLinked body\n\n---\n\n"
+        );
+        assert!(crate::diff::parse_rendered_posts(&out).is_empty());
+    }
+
+    #[test]
+    fn test_render_reproducible() {
+        let posts = [test_post(1, "alice", "Hi")];
+        let options = RenderOptions {
+            reproducible: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(!out.contains("Fetched"));
+        assert_eq!(out, render("Title", "src", &posts, &options));
+    }
+
+    #[test]
+    fn test_checksum_ignores_fetched_time() {
+        let doc = |fetched: &str| format!("# Title\n\n- **Fetched**: {}\n\nBody\n", fetched);
+        let a = checksum(&doc("2026-02-20 10:00 UTC"));
+        assert_eq!(a, checksum(&doc("2026-03-01 12:30 UTC")));
+        assert_ne!(a, checksum("# Title\n\nBody\n\n"));
+        // A later line that looks like the header's still counts
+        let later = |fetched: &str| {
+            format!(
+                "# Title\n\n- **Fetched**: 2026-02-20 10:00 UTC\n\n---\n\n- **Fetched**: {}\n",
+                fetched
+            )
+        };
+        assert_ne!(checksum(&later("a")), checksum(&later("b")));
+        let reproducible =
+            |fetched: &str| format!("# Title\n\n- Source: x\n\nFetched: {}\n", fetched);
+        assert_ne!(checksum(&reproducible("a")), checksum(&reproducible("b")));
+
+        for format in [OutputFormat::Html, OutputFormat::Org, OutputFormat::Text] {
+            let options = RenderOptions {
+                format,
+                ..Default::default()
+            };
+            let out = render("Title", "src", &[test_post(1, "alice", "Hi")], &options);
+            let fetched = out.lines().find(|l| l.contains("Fetched: ")).unwrap();
+            let (prefix, _) = fetched.split_once("Fetched: ").unwrap();
+            let other = out.replace(fetched, &format!("{}Fetched: x", prefix));
+            assert_eq!(checksum(&out), checksum(&other));
+        }
+        assert!(a.starts_with("sha256:") && a.len() == 7 + 64);
+    }
+
+    #[test]
+    fn test_describe_age() {
+        assert_eq!(describe_age(1), "1 day");
+        assert_eq!(describe_age(45), "1 month");
+        assert_eq!(describe_age(364), "12 months");
+        assert_eq!(describe_age(800), "2 years");
+    }
+
+    #[test]
+    fn test_excerpt_skips_quotes_and_images() {
+        let raw = "[quote=\"bob, post:2, topic:12345\"]\nQuoted text\n[/quote]\n\n\
+                   ![screenshot|690x400](upload://abc.png)\n\
+                   > more quoting\n\
+                   ## Heading\n\
+                   I tried **the fix** from [the docs](https://example.com) and `it_works`.\n";
+        assert_eq!(
+            excerpt(raw, 200).as_deref(),
+            Some("I tried the fix from the docs and it_works.")
+        );
+        assert_eq!(excerpt(raw, 12).as_deref(), Some("I tried the…"));
+        assert_eq!(excerpt("```\ncode\n```\n", 80), None);
+    }
+
+    #[test]
+    fn test_render_permalinks() {
+        let posts = [test_post(3, "alice", "Hi")];
+        let mut options = RenderOptions {
+            topic_url: "https://x.test/t/title/7".to_string(),
+            permalinks: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains(
+            "## [Post #3](https://x.test/t/title/7/3) by @alice (2026-02-20 10:00 UTC)\n\nHi\n"
+        ));
+
+        options.format = OutputFormat::Chat;
+        let out = render("Title", "src", &posts, &options);
+        assert!(
+            out.contains("@alice (2026-02-20 10:00 UTC) [#3](https://x.test/t/title/7/3): Hi\n")
+        );
+    }
+
+    #[test]
+    fn test_render_reply_to() {
+        let mut reply = test_post(3, "bob", "Sure");
+        reply.reply_to_post_number = Some(2);
+        let mut orphan = test_post(4, "carol", "Me too");
+        orphan.reply_to_post_number = Some(9);
+        let posts = [test_post(1, "alice", "Hi"), reply, orphan];
+        let mut options = RenderOptions {
+            post_authors: HashMap::from([(1, "alice".to_string()), (2, "dave".to_string())]),
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains("## Post #1 by @alice (2026-02-20 10:00 UTC)\n\nHi\n"));
+        // #2 was filtered out, but its author is still named
+        assert!(out.contains(
+            "## Post #3 by @bob (2026-02-20 10:00 UTC), in reply to #2 by @dave\n\nSure\n"
+        ));
+        assert!(out.contains("## Post #4 by @carol (2026-02-20 10:00 UTC), in reply to #9\n"));
+
+        options.format = OutputFormat::Chat;
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains("@bob (2026-02-20 10:00 UTC), in reply to #2 by @dave: Sure\n"));
+
+        options.format = OutputFormat::YamlBlocks;
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains("reply_to_post_number: 2\n"));
+    }
+
+    #[test]
+    fn test_render_appreciation() {
+        let mut liked = test_post(1, "alice", "Hi");
+        liked.like_count = 1;
+        let mut reacted = test_post(2, "bob", "Yo");
+        reacted.like_count = 3;
+        reacted.reactions = vec![
+            Reaction {
+                id: "heart".to_string(),
+                count: 3,
+            },
+            Reaction {
+                id: "tada".to_string(),
+                count: 1,
+            },
+        ];
+        let posts = [liked, reacted, test_post(3, "carol", "Meh")];
+        let out = render("Title", "src", &posts, &RenderOptions::default());
+        assert!(out.contains("## Post #1 by @alice (2026-02-20 10:00 UTC), 1 like\n"));
+        assert!(out.contains(
+            "## Post #2 by @bob (2026-02-20 10:00 UTC), 4 reactions (:heart: 3, :tada: 1)\n"
+        ));
+        assert!(out.contains("## Post #3 by @carol (2026-02-20 10:00 UTC)\n"));
+    }
+
+    #[test]
+    fn test_render_role() {
+        let mut admin = test_post(2, "bob", "Fixed");
+        admin.role = Some("admin".to_string());
+        admin.reply_to_post_number = Some(1);
+        let mut staff = test_post(3, "carol", "Thanks");
+        staff.role = Some("staff".to_string());
+        let posts = [test_post(1, "alice", "Help"), admin, staff];
+        let out = render("Title", "src", &posts, &RenderOptions::default());
+        assert!(out.contains(
+            "## Post #2 by @bob (2026-02-20 10:00 UTC), staff (admin), in reply to #1\n"
+        ));
+        assert!(out.contains("## Post #3 by @carol (2026-02-20 10:00 UTC), staff\n"));
+        assert!(out.contains("## Post #1 by @alice (2026-02-20 10:00 UTC)\n"));
+    }
+
+    #[test]
+    fn test_render_accepted_answer() {
+        let mut solution = test_post(2, "bob", "Restart it");
+        solution.like_count = 2;
+        let posts = [test_post(1, "alice", "Help"), solution];
+        let mut options = RenderOptions {
+            accepted_answer: Some(2),
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains(
+            "## Post #2 by @bob (2026-02-20 10:00 UTC), 2 likes — ✅ ACCEPTED SOLUTION\n"
+        ));
+        assert!(out.contains("## Post #1 by @alice (2026-02-20 10:00 UTC)\n"));
+
+        options.format = OutputFormat::Json;
+        let json: serde_json::Value =
+            serde_json::from_str(&render("Title", "src", &posts, &options)).unwrap();
+        assert_eq!(json["posts"][1]["accepted_answer"], true);
+        assert!(json["posts"][0].get("accepted_answer").is_none());
+    }
+
+    #[test]
+    fn test_thread() {
+        let reply = |n, parent| {
+            let mut post = test_post(n, "alice", "");
+            post.reply_to_post_number = Some(parent);
+            post
+        };
+        // #6 answers a post that isn't here, so starts its own thread
+        let posts = [
+            test_post(1, "alice", ""),
+            reply(2, 1),
+            test_post(3, "alice", ""),
+            reply(4, 2),
+            reply(5, 1),
+            reply(6, 99),
+            reply(7, 3),
+        ];
+        let order: Vec<(u64, usize)> = thread(&posts)
+            .into_iter()
+            .map(|(post, depth)| (post.post_number, depth))
+            .collect();
+        assert_eq!(
+            order,
+            [(1, 0), (2, 1), (4, 2), (5, 1), (3, 0), (7, 1), (6, 0)]
+        );
+    }
+
+    #[test]
+    fn test_render_threaded() {
+        let mut answer = test_post(3, "bob", "Answer");
+        answer.reply_to_post_number = Some(1);
+        let posts = [
+            test_post(1, "alice", "Question"),
+            test_post(2, "carol", "Aside"),
+            answer,
+        ];
+        let mut options = RenderOptions {
+            threaded: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains(
+            "## Post #1 by @alice (2026-02-20 10:00 UTC)\n\nQuestion\n\n---\n\n\
+             ### Post #3 by @bob (2026-02-20 10:00 UTC), in reply to #1\n\nAnswer\n\n---\n\n\
+             ## Post #2 by @carol"
+        ));
+
+        options.format = OutputFormat::Chat;
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains("Question\n\n↳ @bob (2026-02-20 10:00 UTC), in reply to #1: Answer\n"));
+    }
+
+    #[test]
+    fn test_render_chat() {
+        let posts = [
+            test_post(1, "alice", "How do I do X?\n"),
+            test_post(2, "bob", "> How do I do X?\n\nLike this."),
+        ];
+        let options = RenderOptions {
+            format: OutputFormat::Chat,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.ends_with(
+            "@alice (2026-02-20 10:00 UTC): How do I do X?\n\n\
+             @bob (2026-02-20 10:00 UTC):\n> How do I do X?\n\nLike this.\n\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_yaml_blocks() {
+        let posts = [
+            test_post(1, "alice", "Hi\n"),
+            test_post(2, "o'\"brien\\", "Hello"),
+        ];
+        let options = RenderOptions {
+            format: OutputFormat::YamlBlocks,
+            topic_url: "https://example.com/t/title/1".to_string(),
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        assert!(out.contains("- **Posts**: 2\n\n---\npost_number: 1\n"));
+        assert!(out.ends_with(
+            "---\npost_number: 2\n\
+             author: \"o'\\\"brien\\\\\"\n\
+             created_at: \"2026-02-20T10:00:00+00:00\"\n\
+             permalink: \"https://example.com/t/title/1/2\"\n\
+             ---\n\nHello\n\n"
+        ));
+    }
+
+    #[test]
+    fn test_sort_by_post_number() {
+        // A merged topic's stream: posts 4 and 5 were moved in ahead of 2 and 3
+        let mut posts = vec![
+            test_post(1, "alice", "a"),
+            test_post(4, "carol", "d"),
+            test_post(5, "dave", "e"),
+            test_post(2, "bob", "b"),
+            test_post(3, "alice", "c"),
+        ];
+        sort_by_post_number(&mut posts);
+        let out = render("Title", "src", &posts, &RenderOptions::default());
+        let headers: Vec<&str> = out.lines().filter(|l| l.starts_with("## ")).collect();
+        assert_eq!(
+            headers,
+            vec![
+                "## Post #1 by @alice (2026-02-20 10:00 UTC)",
+                "## Post #2 by @bob (2026-02-20 10:00 UTC)",
+                "## Post #3 by @alice (2026-02-20 10:00 UTC)",
+                "## Post #4 by @carol (2026-02-20 10:00 UTC)",
+                "## Post #5 by @dave (2026-02-20 10:00 UTC)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_likes() {
+        let liked = |n, likes| {
+            let mut post = test_post(n, "alice", "");
+            post.like_count = likes;
+            post
+        };
+        let numbers = |posts: &[CachedPost]| -> Vec<u64> {
+            posts.iter().map(|post| post.post_number).collect()
+        };
+        let mut posts = vec![
+            liked(1, 2),
+            liked(2, 0),
+            liked(3, 5),
+            liked(4, 2),
+            liked(5, 1),
+        ];
+        keep_most_liked(&mut posts, 3);
+        assert_eq!(numbers(&posts), [1, 3, 4]);
+        sort_by_likes(&mut posts);
+        assert_eq!(numbers(&posts), [3, 1, 4]);
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(
+            slugify("How do I configure SMTP?"),
+            "how-do-i-configure-smtp"
+        );
+        assert_eq!(slugify("Café — Ünïcode & “quotes”"), "cafe-unicode-quotes");
+        assert_eq!(slugify("!!!"), "topic");
+        let long = slugify(&"word ".repeat(40));
+        assert!(long.len() <= MAX_SLUG_LEN && !long.ends_with('-'));
+    }
+
+    #[test]
+    fn test_repair_markdown_closes_fence() {
+        assert_eq!(
+            repair_markdown("Try this:\n````rust\nfn main() {}\n```\nstill code"),
+            "Try this:\n````rust\nfn main() {}\n```\nstill code\n````\n"
+        );
+        let balanced = "```\ncode\n```\ntext\n";
+        assert_eq!(repair_markdown(balanced), balanced);
+    }
+
+    #[test]
+    fn test_repair_markdown_protects_following_posts() {
+        let posts = [
+            test_post(1, "alice", "```\nunterminated"),
+            test_post(2, "bob", "Normal reply"),
+        ];
+        let options = RenderOptions {
+            repair_markdown: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &posts, &options);
+        let header = out
+            .lines()
+            .zip(markdown::classify_lines(&out))
+            .find(|(line, _)| line.starts_with("## Post #2"))
+            .map(|(_, (_, in_code))| in_code);
+        assert_eq!(header, Some(false));
+    }
+
+    #[test]
+    fn test_normalize_whitespace() {
+        let raw = "\r\n\nHello  \r\n\r\n\r\n\nWorld\t\n```\ncode  \n\n\n  end\n```\n\n\n";
+        assert_eq!(
+            normalize_whitespace(raw),
+            "Hello\n\nWorld\n```\ncode  \n\n\n  end\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_render_metadata_separators() {
+        let options = RenderOptions {
+            topic_url: "https://discuss.example.com/t/my-topic/12345".to_string(),
+            metadata_separators: true,
+            ..Default::default()
+        };
+        let out = render("Title", "src", &[test_post(3, "alice", "Hello")], &options);
+        let begin = out
+            .lines()
+            .find_map(|l| l.strip_prefix("<!-- disc-scrape:post "))
+            .and_then(|l| l.strip_suffix(" -->"))
+            .unwrap();
+        let metadata: serde_json::Value = serde_json::from_str(begin).unwrap();
+        assert_eq!(metadata["post_number"], 3);
+        assert_eq!(metadata["post_id"], 1003);
+        assert_eq!(metadata["author"], "alice");
+        assert_eq!(
+            metadata["permalink"],
+            "https://discuss.example.com/t/my-topic/12345/3"
+        );
+        assert!(out.contains("Hello\n<!-- disc-scrape:end-post -->\n"));
+    }
+}
@@ -0,0 +1,384 @@
+use crate::cache::CachedPost;
+use crate::output::{header_suffix, post_body, RenderOptions};
+
+/// US Letter, in points.
+const PAGE_WIDTH: f32 = 612.0;
+const PAGE_HEIGHT: f32 = 792.0;
+const MARGIN: f32 = 54.0;
+/// Height reserved at the bottom of each page for its number.
+const FOOTER: f32 = 36.0;
+
+const BODY_SIZE: f32 = 10.0;
+const TITLE_SIZE: f32 = 18.0;
+const FOOTER_SIZE: f32 = 9.0;
+const LEADING: f32 = 1.3;
+
+/// Courier glyphs are all 600/1000 em wide, which keeps line wrapping simple.
+const CHAR_WIDTH: f32 = 0.6;
+
+/// A laid-out line of text.
+struct Line {
+    text: String,
+    bold: bool,
+    size: f32,
+}
+
+impl Line {
+    fn new(text: String, bold: bool, size: f32) -> Self {
+        Self { text, bold, size }
+    }
+
+    fn height(&self) -> f32 {
+        self.size * LEADING
+    }
+}
+
+/// Render the topic as a printable PDF: a title page with the topic's details,
+/// then the posts under bold headers, with numbered pages.
+///
+/// Text is set in the standard Courier fonts, so no fonts are embedded; post
+/// bodies are their Markdown, wrapped to the page. Characters outside Latin-1
+/// are transliterated.
+pub fn render(
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+    options: &RenderOptions,
+) -> Vec<u8> {
+    let mut title_page = Vec::new();
+    push_wrapped(&mut title_page, title, true, TITLE_SIZE);
+    push_blank(&mut title_page);
+    let mut details = vec![format!("Source: {}", source_url)];
+    if let Some(category) = &options.category {
+        details.push(format!("Category: {}", category));
+    }
+    if !options.reproducible {
+        let now = options.dates.format(chrono::Utc::now());
+        details.push(format!("Fetched: {}", now));
+    }
+    details.push(format!("Posts: {}", posts.len()));
+    for detail in details {
+        push_wrapped(&mut title_page, &detail, false, BODY_SIZE);
+    }
+
+    let mut body = Vec::new();
+    for post in posts {
+        let marker = if options.context_post_ids.contains(&post.post_id) {
+            " [context]"
+        } else {
+            ""
+        };
+        let header = format!(
+            "Post #{} by @{} ({}){}{}",
+            post.post_number,
+            post.username,
+            options.dates.format(post.created_at),
+            header_suffix(post, options),
+            marker
+        );
+        push_wrapped(&mut body, &header, true, BODY_SIZE);
+        push_blank(&mut body);
+        for line in post_body(post, options).trim_end().lines() {
+            push_wrapped(&mut body, line, false, BODY_SIZE);
+        }
+        push_blank(&mut body);
+    }
+
+    let mut pages = paginate(title_page);
+    pages.extend(paginate(body));
+    write_pdf(title, &pages, options.reproducible)
+}
+
+fn push_blank(lines: &mut Vec<Line>) {
+    lines.push(Line::new(String::new(), false, BODY_SIZE));
+}
+
+/// Word-wrap `text` to the page width, keeping its indentation on the first line
+/// and splitting words too long for a line.
+fn push_wrapped(lines: &mut Vec<Line>, text: &str, bold: bool, size: f32) {
+    let max = ((PAGE_WIDTH - 2.0 * MARGIN) / (size * CHAR_WIDTH)) as usize;
+    // Transliterate first: one character can become several
+    let text = to_latin1(&text.replace('\t', "    "));
+    let len = |s: &str| s.chars().count();
+
+    let mut current = text[..text.len() - text.trim_start().len()].to_string();
+    let mut has_words = false;
+    for word in text.split_whitespace() {
+        if has_words {
+            if len(&current) + 1 + len(word) > max {
+                lines.push(Line::new(std::mem::take(&mut current), bold, size));
+            } else {
+                current.push(' ');
+            }
+        }
+        has_words = true;
+        let mut word = word;
+        while len(&current) + len(word) > max {
+            let split = max.saturating_sub(len(&current)).max(1);
+            let byte = word
+                .char_indices()
+                .nth(split)
+                .map_or(word.len(), |(i, _)| i);
+            current.push_str(&word[..byte]);
+            lines.push(Line::new(std::mem::take(&mut current), bold, size));
+            word = &word[byte..];
+        }
+        current.push_str(word);
+    }
+    lines.push(Line::new(current, bold, size));
+}
+
+fn paginate(lines: Vec<Line>) -> Vec<Vec<Line>> {
+    let usable = PAGE_HEIGHT - 2.0 * MARGIN - FOOTER;
+    let mut pages = vec![Vec::new()];
+    let mut used = 0.0;
+    for line in lines {
+        if used + line.height() > usable {
+            pages.push(Vec::new());
+            used = 0.0;
+        }
+        let page = pages.last_mut().expect("there is always a page");
+        // Blank lines at the top of a page would only push the text down
+        if page.is_empty() && line.text.is_empty() {
+            continue;
+        }
+        used += line.height();
+        page.push(line);
+    }
+    pages
+}
+
+/// Keep the characters the Courier fonts can show, printable Latin-1, and
+/// transliterate anything else to ASCII.
+fn to_latin1(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            ' '..='~' | '\u{a0}'..='\u{ff}' => out.push(c),
+            c => {
+                let ascii = deunicode::deunicode_char(c).unwrap_or("?");
+                out.extend(ascii.chars().filter(|c| (' '..='~').contains(c)));
+            }
+        }
+    }
+    out
+}
+
+/// Encode text as a PDF string literal in WinAnsiEncoding: Latin-1 characters
+/// as bytes, anything else transliterated to ASCII.
+fn pdf_string(text: &str) -> String {
+    let mut out = String::from("(");
+    for c in to_latin1(text).chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            c => out.push_str(&format!("\\{:03o}", c as u32)),
+        }
+    }
+    out.push(')');
+    out
+}
+
+fn write_pdf(title: &str, pages: &[Vec<Line>], reproducible: bool) -> Vec<u8> {
+    // Objects: 1 catalog, 2 page tree, 3 info, 4-5 fonts, then a page and its
+    // content stream per page
+    let first_page = 6;
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| first_page + 2 * i).collect();
+
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    objects.push(
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    );
+    let mut info = format!(
+        "<< /Title {} /Producer (disc-scrape {})",
+        pdf_string(title),
+        env!("CARGO_PKG_VERSION")
+    );
+    if !reproducible {
+        info.push_str(&format!(
+            " /CreationDate (D:{})",
+            chrono::Utc::now().format("%Y%m%d%H%M%SZ")
+        ));
+    }
+    info.push_str(" >>");
+    objects.push(info.into_bytes());
+    for font in ["Courier", "Courier-Bold"] {
+        objects.push(
+            format!(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                font
+            )
+            .into_bytes(),
+        );
+    }
+
+    for (i, lines) in pages.iter().enumerate() {
+        let mut content = String::new();
+        let mut y = PAGE_HEIGHT - MARGIN;
+        for line in lines {
+            y -= line.height();
+            if line.text.is_empty() {
+                continue;
+            }
+            let font = if line.bold { "F2" } else { "F1" };
+            content.push_str(&format!(
+                "BT /{} {} Tf {} {:.1} Td {} Tj ET\n",
+                font,
+                line.size,
+                MARGIN,
+                y,
+                pdf_string(&line.text)
+            ));
+        }
+        let number = format!("Page {} of {}", i + 1, pages.len());
+        let width = number.len() as f32 * FOOTER_SIZE * CHAR_WIDTH;
+        content.push_str(&format!(
+            "BT /F1 {} Tf {:.1} {} Td {} Tj ET\n",
+            FOOTER_SIZE,
+            (PAGE_WIDTH - width) / 2.0,
+            MARGIN,
+            pdf_string(&number)
+        ));
+
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 4 0 R /F2 5 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                page_ids[i] + 1
+            )
+            .into_bytes(),
+        );
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(content.as_bytes());
+        stream.extend_from_slice(b"endstream");
+        objects.push(stream);
+    }
+
+    let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        out.extend_from_slice(object);
+        out.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n", objects.len() + 1).as_bytes());
+    out.extend_from_slice(b"0000000000 65535 f \n");
+    for offset in offsets {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info 3 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf_string() {
+        assert_eq!(pdf_string("a (b) \\ c"), "(a \\(b\\) \\\\ c)");
+        assert_eq!(pdf_string("café"), "(caf\\351)");
+        assert_eq!(pdf_string("東京"), "(Dong Jing )");
+    }
+
+    #[test]
+    fn test_push_wrapped() {
+        let mut lines = Vec::new();
+        let long = "word ".repeat(40);
+        push_wrapped(&mut lines, &format!("  {}", long), false, BODY_SIZE);
+        push_wrapped(&mut lines, &"x".repeat(100), false, BODY_SIZE);
+        let texts: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+        // 84 characters fit on a line at 10pt
+        assert!(texts.iter().all(|t| t.chars().count() <= 84));
+        assert!(texts[0].starts_with("  word word"));
+        assert_eq!(texts[texts.len() - 2], "x".repeat(84));
+        assert_eq!(texts[texts.len() - 1], "x".repeat(16));
+    }
+
+    #[test]
+    fn test_push_wrapped_transliterated() {
+        // Each of these becomes several characters, so wrap what's printed
+        let mut lines = Vec::new();
+        push_wrapped(&mut lines, &"東京".repeat(30), false, BODY_SIZE);
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|l| l.text.chars().count() <= 84));
+        assert!(lines[0].text.starts_with("Dong Jing Dong Jing"));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let lines: Vec<Line> = (0..60)
+            .map(|i| Line::new(i.to_string(), false, BODY_SIZE))
+            .collect();
+        let pages = paginate(lines);
+        // 648pt usable at 13pt a line
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].len(), 49);
+        assert_eq!(pages[1][0].text, "49");
+    }
+
+    #[test]
+    fn test_write_pdf() {
+        let pages = vec![
+            vec![Line::new("Title (draft)".to_string(), true, TITLE_SIZE)],
+            vec![Line::new("body".to_string(), false, BODY_SIZE)],
+        ];
+        let pdf = write_pdf("Title", &pages, true);
+        // Byte for byte, so offsets into it are offsets into the file
+        let text: String = pdf
+            .iter()
+            .map(|&b| if b.is_ascii() { b as char } else { '?' })
+            .collect();
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("(Page 2 of 2) Tj"));
+        assert!(text.contains("(Title \\(draft\\)) Tj"));
+        assert!(!text.contains("/CreationDate"));
+
+        // Every xref entry points at the start of its object
+        let xref = text.rfind("\nxref\n").unwrap() + 1;
+        let startxref: usize = text
+            .split("startxref\n")
+            .nth(1)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(startxref, xref);
+        let entries = text[xref..]
+            .lines()
+            .skip(3)
+            .take_while(|l| l.ends_with(" n "));
+        let mut count = 0;
+        for (i, entry) in entries.enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj\n", i + 1)));
+            count += 1;
+        }
+        assert_eq!(count, 5 + 2 * pages.len());
+    }
+}
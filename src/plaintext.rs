@@ -0,0 +1,321 @@
+use crate::markdown;
+use regex::Regex;
+
+/// Strip Markdown syntax from a post, leaving its prose.
+///
+/// Headings, quote markers, rules and Discourse's `[quote]`/`[details]` tags are
+/// removed, bullets become `-`, and links, images, emphasis and code spans give
+/// way to their text. Fenced code keeps its contents verbatim without the fences,
+/// or with `collapse_code` is replaced by a `[code]` line.
+pub fn from_markdown(text: &str, collapse_code: bool) -> String {
+    let bbcode = Regex::new(r"(?i)^\[/?(?:quote|details|spoiler)(?:[= ][^\]]*)?\]$")
+        .expect("BBCode tag regex is valid");
+    let mut out = String::with_capacity(text.len());
+    let mut in_code = false;
+
+    for (line, code) in markdown::classify_lines(text) {
+        let content = line.trim_end_matches(['\n', '\r']);
+        if code {
+            if !in_code {
+                // The opening fence
+                in_code = true;
+                if collapse_code {
+                    let language = content
+                        .trim_start()
+                        .trim_start_matches(['`', '~'])
+                        .split_whitespace()
+                        .next();
+                    match language {
+                        Some(language) => out.push_str(&format!("[code: {}]\n", language)),
+                        None => out.push_str("[code]\n"),
+                    }
+                }
+            } else if markdown::fence_marker(content).is_some()
+                && content.trim().chars().all(|c| c == '`' || c == '~')
+            {
+                in_code = false;
+            } else if !collapse_code {
+                out.push_str(content);
+                out.push('\n');
+            }
+            continue;
+        }
+        in_code = false;
+
+        let mut rest = content.trim_start();
+        // Quote markers, however deeply nested
+        while let Some(quoted) = rest.strip_prefix('>') {
+            rest = quoted.trim_start();
+        }
+        if bbcode.is_match(rest.trim_end()) || is_rule(rest) {
+            out.push('\n');
+            continue;
+        }
+        let indent = if rest.len() == content.trim_start().len() {
+            &content[..content.len() - rest.len()]
+        } else {
+            ""
+        };
+        out.push_str(indent);
+        out.push_str(&strip_block_marker(rest));
+        out.push('\n');
+    }
+
+    collapse_blank_lines(&out)
+}
+
+/// A thematic break (`---`, `***`, `___`) or a setext heading underline (`===`).
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    let rule = |mark: char| marks.len() >= 3 && marks.chars().all(|c| c == mark);
+    rule('-') || rule('*') || rule('_') || rule('=')
+}
+
+/// Strip a line's heading or bullet marker, then its inline markup.
+fn strip_block_marker(line: &str) -> String {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with([' ', '\t']) {
+        let heading = line[hashes..].trim().trim_end_matches('#').trim_end();
+        return strip_inline(heading);
+    }
+    if let Some(item) = line.strip_prefix("* ").or_else(|| line.strip_prefix("+ ")) {
+        return format!("- {}", strip_inline(item));
+    }
+    strip_inline(line)
+}
+
+/// Strip inline markup: code spans, images, links, autolinks, emphasis and
+/// backslash escapes.
+fn strip_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|c| c.is_ascii_punctuation()) => {
+                out.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' => {
+                let run = run_length(&chars, i, '`');
+                if let Some(close) = find_backtick_run(&chars, i + run, run) {
+                    let code: String = chars[i + run..close].iter().collect();
+                    out.push_str(code.trim());
+                    i = close + run;
+                } else {
+                    out.extend(&chars[i..i + run]);
+                    i += run;
+                }
+                continue;
+            }
+            '!' if chars.get(i + 1) == Some(&'[') => {
+                if let Some((label, end)) = parse_link(&chars, i + 1) {
+                    out.push_str(&strip_inline(&label));
+                    i = end;
+                    continue;
+                }
+            }
+            '[' => {
+                if let Some((label, end)) = parse_link(&chars, i) {
+                    out.push_str(&strip_inline(&label));
+                    i = end;
+                    continue;
+                }
+            }
+            '<' => {
+                if let Some(close) = chars[i + 1..].iter().position(|c| *c == '>') {
+                    let inner: String = chars[i + 1..i + 1 + close].iter().collect();
+                    let is_autolink = !inner.is_empty()
+                        && !inner.contains(char::is_whitespace)
+                        && (inner.contains("://") || inner.contains('@'));
+                    if is_autolink {
+                        out.push_str(inner.trim_start_matches("mailto:"));
+                        i += close + 2;
+                        continue;
+                    }
+                }
+            }
+            '*' | '_' | '~' => {
+                if let Some((inner, end)) = parse_emphasis(&chars, i) {
+                    out.push_str(&strip_inline(&inner));
+                    i = end;
+                    continue;
+                }
+                let run = run_length(&chars, i, c);
+                out.extend(&chars[i..i + run]);
+                i += run;
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+fn run_length(chars: &[char], start: usize, c: char) -> usize {
+    chars[start..].iter().take_while(|&&x| x == c).count()
+}
+
+/// The start of the next run of exactly `len` backticks at or after `from`.
+fn find_backtick_run(chars: &[char], from: usize, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            let run = run_length(chars, i, '`');
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Parse an inline link or image's `[label](destination)` starting at the `[`,
+/// returning the label and the index just past the closing parenthesis.
+fn parse_link(chars: &[char], open: usize) -> Option<(String, usize)> {
+    let close = matching(chars, open, '[', ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = matching(chars, close + 1, '(', ')')?;
+    Some((chars[open + 1..close].iter().collect(), end + 1))
+}
+
+/// The index of the bracket closing the one at `open`, allowing nesting and
+/// skipping escaped brackets.
+fn matching(chars: &[char], open: usize, left: char, right: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            c if c == left => depth += 1,
+            c if c == right => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Parse emphasis (`*a*`, `__a__`, `***a***`) or strikethrough (`~~a~~`) opening
+/// at `start`, returning its contents and the index just past the closing run.
+///
+/// As in CommonMark, the opening run must be followed and the closing run
+/// preceded by a non-space, and `_` doesn't work inside words, so `snake_case`
+/// and `2 * 3 * 4` are left alone.
+fn parse_emphasis(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let c = chars[start];
+    let run = run_length(chars, start, c);
+    if run > 3 || (c == '~' && run != 2) {
+        return None;
+    }
+    let intraword = |i: Option<&char>| i.is_some_and(|c| c.is_alphanumeric());
+    if c == '_' && start > 0 && intraword(chars.get(start - 1)) {
+        return None;
+    }
+    let inner_start = start + run;
+    if chars.get(inner_start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    let mut i = inner_start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '`' => {
+                let code = run_length(chars, i, '`');
+                i = find_backtick_run(chars, i + code, code).map_or(i + code, |close| close + code);
+            }
+            x if x == c => {
+                let close = run_length(chars, i, c);
+                let closes = close == run
+                    && !chars[i - 1].is_whitespace()
+                    && !(c == '_' && intraword(chars.get(i + close)));
+                if closes && i > inner_start {
+                    return Some((chars[inner_start..i].iter().collect(), i + close));
+                }
+                i += close;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Squeeze runs of blank lines to one and trim blank lines at either end.
+fn collapse_blank_lines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_markdown() {
+        let md = "## Setup ##\n\n\
+                  Use **this** and *that*, see [the *docs*](https://x.test/(d)) or `a*b`.\n\
+                  ![diagram](x.png) <https://x.test> \\*not emphasis\\* 2 * 3 * 4 snake_case_name\n\
+                  * one\n  + two\n\n\
+                  [quote=\"bob, post:2, topic:1\"]\n> quoted\n> > nested ~~old~~\n[/quote]\n\n\
+                  ---\n\n\n\
+                  ```rust\nlet x = **y;\n```\nDone";
+        assert_eq!(
+            from_markdown(md, false),
+            "Setup\n\n\
+             Use this and that, see the docs or a*b.\n\
+             diagram https://x.test *not emphasis* 2 * 3 * 4 snake_case_name\n\
+             - one\n  - two\n\n\
+             quoted\nnested old\n\n\
+             let x = **y;\nDone\n"
+        );
+        assert_eq!(
+            from_markdown(
+                "Before\n\n```rust\nlet x = 1;\n```\n\n~~~\nplain\n~~~\n",
+                true
+            ),
+            "Before\n\n[code: rust]\n\n[code]\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_inline() {
+        assert_eq!(strip_inline("``a `b` c``"), "a `b` c");
+        assert_eq!(
+            strip_inline("unclosed `tick and *star"),
+            "unclosed `tick and *star"
+        );
+        assert_eq!(strip_inline("***both*** and __strong__"), "both and strong");
+        assert_eq!(strip_inline("[link](<no close>"), "[link](<no close>");
+        assert_eq!(strip_inline("mail <mailto:a@b.test>"), "mail a@b.test");
+        assert_eq!(strip_inline("x < y > z"), "x < y > z");
+    }
+}
@@ -0,0 +1,165 @@
+use crate::discourse::Poll;
+use crate::markdown;
+use regex::Regex;
+
+/// Replace each `[poll]` block in a post with a table of the poll's results:
+/// each option with its votes and share of the voters.
+///
+/// Options keep their Markdown from the block when it lists as many as the poll
+/// has, and fall back to the text of Discourse's HTML otherwise. Blocks with no
+/// matching poll in `polls` (such as posts cached before polls were kept) are
+/// left alone.
+pub fn render_results(raw: &str, polls: &[Poll]) -> String {
+    if polls.is_empty() {
+        return raw.to_string();
+    }
+    let open = Regex::new(r"^\[poll(?:\s+([^\]]*))?\]\s*$").expect("poll regex is valid");
+    let name = Regex::new(r#"(?:^|\s)name=(?:"([^"]*)"|(\S+))"#).expect("name regex is valid");
+    let item = Regex::new(r"^\s*(?:[*+-]|\d+[.)])\s+(.*?)\s*$").expect("item regex is valid");
+
+    let mut out = String::with_capacity(raw.len());
+    // The block's lines so far, its poll's name and its options
+    let mut block: Option<(String, String, Vec<String>)> = None;
+    let mut after_table = false;
+    for (line, code) in markdown::classify_lines(raw) {
+        let content = line.trim_end_matches(['\n', '\r']);
+        // A table runs on into the next line unless a blank one ends it
+        if std::mem::take(&mut after_table) && !content.trim().is_empty() {
+            out.push('\n');
+        }
+        match &mut block {
+            None => {
+                let caps = (!code).then(|| open.captures(content.trim())).flatten();
+                match caps {
+                    Some(caps) => {
+                        let poll_name = caps
+                            .get(1)
+                            .and_then(|attrs| name.captures(attrs.as_str()))
+                            .and_then(|n| n.get(1).or(n.get(2)))
+                            .map_or("poll", |n| n.as_str());
+                        block = Some((line.to_string(), poll_name.to_string(), Vec::new()));
+                    }
+                    None => out.push_str(line),
+                }
+            }
+            Some((lines, poll_name, items)) => {
+                lines.push_str(line);
+                if content.trim() == "[/poll]" {
+                    match polls.iter().find(|poll| poll.name == *poll_name) {
+                        Some(poll) => {
+                            out.push_str(&results_table(poll, items));
+                            after_table = true;
+                        }
+                        None => out.push_str(lines),
+                    }
+                    block = None;
+                } else if let Some(caps) = item.captures(content) {
+                    items.push(caps[1].to_string());
+                }
+            }
+        }
+    }
+    // An unclosed block stays as written
+    if let Some((lines, _, _)) = block {
+        out.push_str(&lines);
+    }
+    out
+}
+
+fn results_table(poll: &Poll, items: &[String]) -> String {
+    let hidden = poll.options.iter().all(|option| option.votes.is_none());
+    let mut out = format!(
+        "**Poll** ({}{} voter{}{}):\n\n",
+        match poll.status.as_deref() {
+            Some("closed") => "closed, ",
+            _ => "",
+        },
+        poll.voters,
+        if poll.voters == 1 { "" } else { "s" },
+        if hidden { ", results hidden" } else { "" }
+    );
+    if hidden {
+        out.push_str("| Option |\n|---|\n");
+    } else {
+        out.push_str("| Option | Votes | % |\n|---|---:|---:|\n");
+    }
+    for (i, option) in poll.options.iter().enumerate() {
+        let text = match items.get(i) {
+            Some(item) if items.len() == poll.options.len() => item.clone(),
+            _ => markdown::html_text(&option.html),
+        };
+        let text = text.replace('|', "\\|");
+        if hidden {
+            out.push_str(&format!("| {} |\n", text));
+            continue;
+        }
+        let votes = option.votes.unwrap_or(0);
+        let percent = if poll.voters == 0 {
+            0.0
+        } else {
+            votes as f64 * 100.0 / poll.voters as f64
+        };
+        out.push_str(&format!("| {} | {} | {:.0}% |\n", text, votes, percent));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discourse::PollOption;
+
+    fn poll(name: &str, voters: u64, options: &[(&str, Option<u64>)]) -> Poll {
+        Poll {
+            name: name.to_string(),
+            status: Some("open".to_string()),
+            voters,
+            options: options
+                .iter()
+                .map(|(html, votes)| PollOption {
+                    html: html.to_string(),
+                    votes: *votes,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_render_results() {
+        let raw =
+            "Which?\n\n[poll type=regular results=always]\n* **Red** | warm\n* Blue\n[/poll]\n\n\
+                   ```\n[poll]\n* in code\n[/poll]\n```\n";
+        let polls = [poll(
+            "poll",
+            3,
+            &[("<strong>Red</strong>", Some(2)), ("Blue", Some(1))],
+        )];
+        assert_eq!(
+            render_results(raw, &polls),
+            "Which?\n\n**Poll** (3 voters):\n\n\
+             | Option | Votes | % |\n|---|---:|---:|\n\
+             | **Red** \\| warm | 2 | 67% |\n| Blue | 1 | 33% |\n\n\
+             ```\n[poll]\n* in code\n[/poll]\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_render_results_by_name() {
+        let raw = "[poll name=lunch]\n[/poll]\n[poll name=\"other one\"]\n* x\n[/poll]\n";
+        let mut lunch = poll(
+            "lunch",
+            1,
+            &[(
+                "Tacos &amp; <img src=\"t.png\" title=\":taco:\" class=\"emoji\">",
+                None,
+            )],
+        );
+        lunch.status = Some("closed".to_string());
+        assert_eq!(
+            render_results(raw, &[lunch]),
+            "**Poll** (closed, 1 voter, results hidden):\n\n\
+             | Option |\n|---|\n| Tacos & :taco: |\n\n\
+             [poll name=\"other one\"]\n* x\n[/poll]\n"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+/// User-agent token matched against `robots.txt` groups.
+pub const USER_AGENT_TOKEN: &str = "disc-scrape";
+
+/// An `Allow` (`true`) or `Disallow` (`false`) rule with its path pattern.
+type Rule = (bool, String);
+
+/// The `robots.txt` rules that apply to disc-scrape.
+#[derive(Debug, Default)]
+pub struct Robots {
+    rules: Vec<Rule>,
+}
+
+impl Robots {
+    /// Parse `robots.txt`, keeping the rules of the groups addressed to
+    /// `disc-scrape`, or to `*` when no group names it.
+    pub fn parse(text: &str) -> Self {
+        // (user agents, rules) per group
+        let mut groups: Vec<(Vec<String>, Vec<Rule>)> = Vec::new();
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match field.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // A user-agent line after rules starts a new group
+                    match groups.last_mut() {
+                        Some((agents, rules)) if rules.is_empty() => {
+                            agents.push(value.to_ascii_lowercase())
+                        }
+                        _ => groups.push((vec![value.to_ascii_lowercase()], Vec::new())),
+                    }
+                }
+                directive @ ("allow" | "disallow") => {
+                    let Some((_, rules)) = groups.last_mut() else {
+                        continue;
+                    };
+                    // An empty Disallow allows everything; keep a no-op rule so the
+                    // group is closed
+                    rules.push((directive == "allow" || value.is_empty(), value.to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        // Agents are lowercased above, so this compares product tokens
+        // case-insensitively; a version after `/` doesn't matter
+        let names_us = |agent: &String| agent.split('/').next() == Some(USER_AGENT_TOKEN);
+        let specific = groups.iter().any(|(agents, _)| agents.iter().any(names_us));
+        let rules = groups
+            .into_iter()
+            .filter(|(agents, _)| {
+                if specific {
+                    agents.iter().any(names_us)
+                } else {
+                    agents.iter().any(|a| a == "*")
+                }
+            })
+            .flat_map(|(_, rules)| rules)
+            .filter(|(_, pattern)| !pattern.is_empty())
+            .collect();
+        Self { rules }
+    }
+
+    /// Whether `path` may be fetched. The longest matching rule wins, with
+    /// `Allow` winning ties.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        let mut best: Option<(usize, bool)> = None;
+        for (allow, pattern) in &self.rules {
+            if !matches(pattern, path) {
+                continue;
+            }
+            let len = pattern.len();
+            best = match best {
+                Some((best_len, best_allow))
+                    if best_len > len || (best_len == len && best_allow) =>
+                {
+                    Some((best_len, best_allow))
+                }
+                _ => Some((len, *allow)),
+            };
+        }
+        best.is_none_or(|(_, allow)| allow)
+    }
+}
+
+/// Match a robots.txt path pattern (with `*` wildcards and a `$` end anchor)
+/// against the start of `path`.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+
+    let Some(mut rest) = path.strip_prefix(parts[0]) else {
+        return false;
+    };
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        let last = i == parts.len() - 1;
+        if last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_group() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /admin/\nDisallow: /t/*/*.rss\nDisallow: /*?*api_key*\n",
+        );
+        assert!(robots.is_allowed("/t/12345.json"));
+        assert!(!robots.is_allowed("/admin/users"));
+        assert!(!robots.is_allowed("/t/some-topic/12345.rss"));
+        assert!(!robots.is_allowed("/t/12345.json?api_key=x"));
+    }
+
+    #[test]
+    fn test_specific_group_overrides_wildcard() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /\n\nUser-agent: disc-scrape\nDisallow: /raw/\nAllow: /raw/1/\n",
+        );
+        assert!(robots.is_allowed("/t/12345.json"));
+        assert!(!robots.is_allowed("/raw/12345/1"));
+        assert!(robots.is_allowed("/raw/1/2"));
+    }
+
+    #[test]
+    fn test_group_names_whole_token() {
+        // `scrape` is part of our token but names another crawler
+        let robots =
+            Robots::parse("User-agent: *\nDisallow: /raw/\n\nUser-agent: scrape\nDisallow: /\n");
+        assert!(robots.is_allowed("/t/12345.json"));
+        assert!(!robots.is_allowed("/raw/12345/1"));
+
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /\n\nUser-agent: Disc-Scrape\nDisallow: /raw/\n",
+        );
+        assert!(robots.is_allowed("/t/12345.json"));
+        assert!(!robots.is_allowed("/raw/12345/1"));
+    }
+
+    #[test]
+    fn test_anchored_pattern() {
+        let robots = Robots::parse("User-agent: *\nDisallow: /*.json$\n");
+        assert!(!robots.is_allowed("/t/12345.json"));
+        assert!(robots.is_allowed("/t/12345.json?page=2"));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::cache::CachedPost;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS topics (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    url TEXT NOT NULL,
+    fetched_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS posts (
+    post_id INTEGER PRIMARY KEY,
+    topic_id INTEGER NOT NULL REFERENCES topics (id),
+    post_number INTEGER NOT NULL,
+    username TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    raw TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS posts_topic ON posts (topic_id, post_number);
+";
+
+/// Add a topic and its posts to the SQLite database at `path`, creating it and
+/// its tables if needed. Rows are upserted by topic and post ID, so scraping a
+/// thread again updates it in place.
+pub fn export(
+    path: &Path,
+    topic_id: u64,
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+) -> Result<()> {
+    let mut db = Connection::open(path)
+        .with_context(|| format!("Failed to open database {}", path.display()))?;
+    // Topics scraped in parallel write to the same file
+    db.busy_timeout(std::time::Duration::from_secs(30))?;
+    db.execute_batch(SCHEMA)
+        .context("Failed to create database tables")?;
+    upsert(&mut db, topic_id, title, source_url, posts)
+        .with_context(|| format!("Failed to write to database {}", path.display()))
+}
+
+fn upsert(
+    db: &mut Connection,
+    topic_id: u64,
+    title: &str,
+    source_url: &str,
+    posts: &[CachedPost],
+) -> rusqlite::Result<()> {
+    let tx = db.transaction()?;
+    tx.execute(
+        "INSERT INTO topics (id, title, url, fetched_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (id) DO UPDATE SET
+             title = excluded.title, url = excluded.url, fetched_at = excluded.fetched_at",
+        params![topic_id, title, source_url, chrono::Utc::now().to_rfc3339()],
+    )?;
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO posts (post_id, topic_id, post_number, username, created_at, raw)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (post_id) DO UPDATE SET
+                 topic_id = excluded.topic_id, post_number = excluded.post_number,
+                 username = excluded.username, created_at = excluded.created_at,
+                 raw = excluded.raw",
+        )?;
+        for post in posts {
+            insert.execute(params![
+                post.post_id,
+                topic_id,
+                post.post_number,
+                post.username,
+                post.created_at.to_rfc3339(),
+                post.raw,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::test_post;
+
+    #[test]
+    fn test_export_upserts() {
+        let dir = std::env::temp_dir().join(format!("disc-scrape-sqlite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("archive.db");
+
+        export(
+            &path,
+            7,
+            "Old title",
+            "https://x.test/t/7",
+            &[test_post(1, "alice", "a")],
+        )
+        .unwrap();
+        export(
+            &path,
+            7,
+            "New title",
+            "https://x.test/t/7",
+            &[test_post(1, "alice", "edited"), test_post(2, "alice", "b")],
+        )
+        .unwrap();
+
+        let db = Connection::open(&path).unwrap();
+        let title: String = db
+            .query_row("SELECT title FROM topics WHERE id = 7", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(title, "New title");
+        let mut query = db
+            .prepare("SELECT post_number, raw FROM posts WHERE topic_id = 7 ORDER BY post_number")
+            .unwrap();
+        let rows: Vec<(u64, String)> = query
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows, [(1, "edited".to_string()), (2, "b".to_string())]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use crate::manifest::TopicReport;
+use owo_colors::OwoColorize;
+use std::time::Duration;
+
+/// Print a recap of the run to stderr: one entry per topic, then totals.
+pub fn print(
+    reports: &[TopicReport],
+    failures: &[(String, anyhow::Error)],
+    elapsed: Duration,
+    color: bool,
+) {
+    let paint = |text: String, style: fn(&String) -> String| {
+        if color {
+            style(&text)
+        } else {
+            text
+        }
+    };
+
+    eprintln!();
+    for report in reports {
+        eprintln!(
+            "{} {}",
+            paint("✔".to_string(), |s| s.green().to_string()),
+            paint(report.title.clone(), |s| s.bold().to_string())
+        );
+        let mut detail = format!(
+            "{} posts ({} cached, {} fetched)",
+            report.posts, report.cache_hits, report.cache_misses
+        );
+        if !report.failed_posts.is_empty() {
+            detail.push_str(&paint(
+                format!(", {} failed", report.failed_posts.len()),
+                |s| s.red().to_string(),
+            ));
+        }
+        if let Some(tokens) = report.tokens {
+            detail.push_str(&format!(", {} tokens", tokens));
+        }
+        if let Some(redactions) = report.redactions {
+            detail.push_str(&format!(", {} redactions", redactions.total()));
+        }
+        detail.push_str(&format!(" in {:.1}s", report.elapsed_seconds));
+        eprintln!("  {}", detail);
+        for path in &report.output_paths {
+            eprintln!("  → {}", paint(path.clone(), |s| s.cyan().to_string()));
+        }
+    }
+    for (url, e) in failures {
+        eprintln!(
+            "{} {}",
+            paint("✘".to_string(), |s| s.red().to_string()),
+            paint(url.clone(), |s| s.bold().to_string())
+        );
+        eprintln!("  {:#}", e);
+    }
+
+    let posts: usize = reports.iter().map(|r| r.posts).sum();
+    let fetched: usize = reports.iter().map(|r| r.cache_misses).sum();
+    eprintln!(
+        "{}",
+        paint(
+            format!(
+                "Done: {} topic(s), {} posts ({} fetched) in {:.1}s",
+                reports.len(),
+                posts,
+                fetched,
+                elapsed.as_secs_f64()
+            ),
+            |s| s.dimmed().to_string()
+        )
+    );
+}
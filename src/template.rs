@@ -0,0 +1,205 @@
+use crate::cache::CachedPost;
+use crate::discourse::Reaction;
+use crate::output::{permalink, post_body, RenderOptions};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+/// A user-supplied Tera template for the whole document.
+pub struct Template {
+    tera: tera::Tera,
+    name: String,
+}
+
+/// The variables a template sees.
+#[derive(Serialize)]
+struct Document<'a> {
+    title: &'a str,
+    source_url: &'a str,
+    category: Option<&'a str>,
+    /// `None` with `--reproducible`
+    fetched_at: Option<chrono::DateTime<chrono::Utc>>,
+    post_count: usize,
+    participants: Vec<Participant<'a>>,
+    posts: Vec<Post<'a>>,
+}
+
+#[derive(Serialize)]
+struct Participant<'a> {
+    username: &'a str,
+    posts: usize,
+}
+
+#[derive(Serialize)]
+struct Post<'a> {
+    post_number: u64,
+    post_id: u64,
+    username: &'a str,
+    created_at: chrono::DateTime<chrono::Utc>,
+    permalink: String,
+    reply_to_post_number: Option<u64>,
+    /// `admin`, `moderator` or `staff` for posts by the forum's staff
+    role: Option<&'a str>,
+    like_count: u64,
+    /// With the reactions plugin, each emoji's `id` and `count`
+    reactions: &'a [Reaction],
+    /// Whether the post is the topic's accepted solution
+    accepted_answer: bool,
+    /// Whether the post is only included as reply context
+    context: bool,
+    action_code: Option<&'a str>,
+    /// The post's Markdown as the built-in formats render it
+    body: String,
+    /// The post's Markdown as fetched
+    raw: &'a str,
+    /// Discourse's rendered HTML, when cached
+    cooked: Option<&'a str>,
+}
+
+impl Template {
+    /// Read and parse the template at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        // Tera picks HTML escaping by the name's extension, so drop `.tera`
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default()
+            .trim_end_matches(".tera")
+            .to_string();
+        let mut tera = tera::Tera::default();
+        tera.add_raw_template(&name, &source)
+            .map_err(|e| anyhow!("Invalid template {}: {}", path.display(), error_chain(&e)))?;
+        Ok(Self { tera, name })
+    }
+
+    /// Extension for documents rendered from this template: the one before
+    /// `.tera` (`notes.org.tera` gives `org`), or `md`.
+    pub fn extension(&self) -> &str {
+        Path::new(&self.name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("md")
+    }
+
+    pub fn render(
+        &self,
+        title: &str,
+        source_url: &str,
+        posts: &[CachedPost],
+        options: &RenderOptions,
+    ) -> Result<String> {
+        let document = Document {
+            title,
+            source_url,
+            category: options.category.as_deref(),
+            fetched_at: (!options.reproducible).then(chrono::Utc::now),
+            post_count: posts.len(),
+            participants: options
+                .participants
+                .iter()
+                .map(|(username, posts)| Participant {
+                    username,
+                    posts: *posts,
+                })
+                .collect(),
+            posts: posts
+                .iter()
+                .map(|post| Post {
+                    post_number: post.post_number,
+                    post_id: post.post_id,
+                    username: &post.username,
+                    created_at: post.created_at,
+                    permalink: permalink(options, post),
+                    reply_to_post_number: post.reply_to_post_number,
+                    role: post.role.as_deref(),
+                    like_count: post.like_count,
+                    reactions: &post.reactions,
+                    accepted_answer: options.is_accepted_answer(post),
+                    context: options.context_post_ids.contains(&post.post_id),
+                    action_code: post.action_code.as_deref(),
+                    body: post_body(post, options),
+                    raw: &post.raw,
+                    cooked: post.cooked.as_deref(),
+                })
+                .collect(),
+        };
+        let context = tera::Context::from_serialize(&document).expect("documents always serialize");
+        self.tera
+            .render(&self.name, &context)
+            .map_err(|e| anyhow!("Failed to render template: {}", error_chain(&e)))
+    }
+}
+
+/// Tera's own message is only "Failed to render 'name'"; the reason is in its
+/// sources, and anyhow's context chain wouldn't show them on one line.
+fn error_chain(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::test_post;
+
+    fn load(name: &str, source: &str) -> Template {
+        let dir = std::env::temp_dir().join(format!(
+            "disc-scrape-template-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        let template = Template::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        template
+    }
+
+    #[test]
+    fn test_render() {
+        let template = load(
+            "notes.html.tera",
+            "<h1>{{ title }}</h1>{% if fetched_at %}fetched{% endif %}\n\
+             {% for post in posts %}{{ post.post_number }} {{ post.username }} \
+             {{ post.created_at | date(format=\"%Y-%m-%d\") }}: {{ post.body }}\n{% endfor %}\
+             {{ post_count }} posts from {{ source_url }}",
+        );
+        assert_eq!(template.extension(), "html");
+
+        let options = RenderOptions {
+            reproducible: true,
+            ..Default::default()
+        };
+        let posts = [test_post(1, "alice", "Hi <b>"), test_post(2, "bob", "Bye")];
+        let out = template
+            .render("Q & A", "https://x.test/t/1", &posts, &options)
+            .unwrap();
+        // Escaped, as the template is HTML
+        assert_eq!(
+            out,
+            "<h1>Q &amp; A</h1>\n\
+             1 alice 2026-02-20: Hi &lt;b&gt;\n\
+             2 bob 2026-02-20: Bye\n\
+             2 posts from https:&#x2F;&#x2F;x.test&#x2F;t&#x2F;1"
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let template = load("plain.tera", "{{ missing }}");
+        assert_eq!(template.extension(), "md");
+        let err = template
+            .render("T", "u", &[], &RenderOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("Variable `missing` not found"));
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+/// Name of the encoding tokens are counted with.
+pub const ENCODING: &str = "o200k_base";
+
+static BPE: OnceLock<CoreBPE> = OnceLock::new();
+
+/// Count the tokens in `text` with the [`ENCODING`] encoding used by GPT-4o and
+/// later OpenAI models. Other models' tokenizers differ, so take it as an
+/// estimate for them.
+pub fn count(text: &str) -> usize {
+    BPE.get_or_init(|| tiktoken_rs::o200k_base().expect("the o200k_base encoding is bundled"))
+        .encode_ordinary(text)
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        assert_eq!(count(""), 0);
+        assert_eq!(count("Hello, world!"), 4);
+        // Special tokens in posts are plain text
+        assert!(count("<|endoftext|>") > 1);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::markdown;
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Markdown images, `![alt](url "title")`, with the URL in group 2.
+fn markdown_image_regex() -> Regex {
+    Regex::new(r#"(!\[[^\]]*\]\(\s*<?)([^)\s>]+)(>?(?:\s+"[^"]*")?\s*\))"#)
+        .expect("image regex is valid")
+}
+
+/// HTML images, `<img src="url">`, with the URL in group 2.
+fn html_image_regex() -> Regex {
+    Regex::new(r#"(<img\b[^>]*?\bsrc=["'])([^"']+)(["'])"#).expect("img regex is valid")
+}
+
+/// The images `raw` references outside code, in order and without repeats, as
+/// written.
+pub fn image_references(raw: &str) -> Vec<String> {
+    let patterns = [markdown_image_regex(), html_image_regex()];
+    let mut references: Vec<String> = Vec::new();
+    markdown::map_prose(raw, |text| {
+        for re in &patterns {
+            for caps in re.captures_iter(text) {
+                if !references.iter().any(|r| *r == caps[2]) {
+                    references.push(caps[2].to_string());
+                }
+            }
+        }
+        text.to_string()
+    });
+    references
+}
+
+/// Point the images `raw` references at other locations, by reference as
+/// written. References missing from `locations` are left alone.
+pub fn rewrite_images(raw: &str, locations: &HashMap<String, String>) -> String {
+    let patterns = [markdown_image_regex(), html_image_regex()];
+    markdown::map_prose(raw, |text| {
+        let mut text = text.to_string();
+        for re in &patterns {
+            text = re
+                .replace_all(&text, |caps: &Captures| match locations.get(&caps[2]) {
+                    Some(location) => format!("{}{}{}", &caps[1], location, &caps[3]),
+                    None => caps[0].to_string(),
+                })
+                .into_owned();
+        }
+        text
+    })
+}
+
+/// Markdown links, `[text](url "title")`, with the URL in group 3. Group 1 holds
+/// the `!` of an image.
+fn markdown_link_regex() -> Regex {
+    Regex::new(r#"(!?)(\[[^\]]*\]\(\s*<?)([^)\s>]+)(>?(?:\s+"[^"]*")?\s*\))"#)
+        .expect("link regex is valid")
+}
+
+/// The forum uploads `raw` links to outside code, such as Discourse's
+/// `[log.txt|attachment](upload://…)` attachments, in order and without
+/// repeats, as written. Images shown inline aren't included.
+pub fn attachment_references(raw: &str, base_url: &str) -> Vec<String> {
+    let re = markdown_link_regex();
+    let is_upload = |reference: &str| {
+        reference.starts_with("upload://")
+            || resolve(reference, base_url).is_some_and(|url| {
+                url.strip_prefix(base_url)
+                    .is_some_and(|path| path.starts_with("/uploads/"))
+            })
+    };
+    let mut references: Vec<String> = Vec::new();
+    markdown::map_prose(raw, |text| {
+        for caps in re.captures_iter(text) {
+            let reference = &caps[3];
+            if caps[1].is_empty()
+                && is_upload(reference)
+                && !references.iter().any(|r| r == reference)
+            {
+                references.push(reference.to_string());
+            }
+        }
+        text.to_string()
+    });
+    references
+}
+
+/// Point the links (not images) in `raw` at other locations, by reference as
+/// written. References missing from `locations` are left alone.
+pub fn rewrite_links(raw: &str, locations: &HashMap<String, String>) -> String {
+    let re = markdown_link_regex();
+    markdown::map_prose(raw, |text| {
+        re.replace_all(text, |caps: &Captures| match locations.get(&caps[3]) {
+            Some(location) if caps[1].is_empty() => {
+                format!("{}{}{}", &caps[2], location, &caps[4])
+            }
+            _ => caps[0].to_string(),
+        })
+        .into_owned()
+    })
+}
+
+/// One downloaded attachment in a directory's `manifest.json`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The file's name in the directory
+    pub file: String,
+    pub url: String,
+    pub topic_url: String,
+    pub post_number: u64,
+}
+
+/// Record `entries` in `dir`'s `manifest.json`, replacing earlier entries for the
+/// same files, so the manifest covers every topic downloaded there.
+pub fn update_manifest(dir: &Path, entries: Vec<ManifestEntry>) -> Result<()> {
+    let path = dir.join("manifest.json");
+    let mut manifest: Vec<ManifestEntry> = match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    manifest.retain(|old| !entries.iter().any(|new| new.file == old.file));
+    manifest.extend(entries);
+    let json = serde_json::to_string_pretty(&manifest).expect("manifests always serialize");
+    std::fs::write(&path, json + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The URL a reference in a post resolves to, or `None` for ones that aren't
+/// fetched over HTTP, such as `data:` URIs.
+///
+/// Discourse's `upload://` short URLs go through `/uploads/short-url/`, which
+/// redirects to the file; protocol-relative and root-relative URLs are resolved
+/// against `base_url`.
+pub fn resolve(reference: &str, base_url: &str) -> Option<String> {
+    if let Some(short) = reference.strip_prefix("upload://") {
+        return Some(format!("{}/uploads/short-url/{}", base_url, short));
+    }
+    if let Some(rest) = reference.strip_prefix("//") {
+        return Some(format!("https://{}", rest));
+    }
+    if reference.starts_with('/') {
+        return Some(format!("{}{}", base_url, reference));
+    }
+    let url = url::Url::parse(reference).ok()?;
+    matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+/// Longest file name kept from a URL, before the hash prefix.
+const MAX_NAME_CHARS: usize = 80;
+
+/// A file name for the file at `url`: its last path segment made safe for any
+/// file system, prefixed with a hash of the URL so different files never share
+/// a name.
+pub fn local_name(url: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(url.as_bytes()));
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let segment = path.rsplit('/').next().unwrap_or_default();
+    let mut name: String = segment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.chars().count() > MAX_NAME_CHARS {
+        // Keep the extension
+        let skip = name.chars().count() - MAX_NAME_CHARS;
+        name = name.chars().skip(skip).collect();
+    }
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        digest[..16].to_string()
+    } else {
+        format!("{}-{}", &digest[..8], name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_references() {
+        let raw = "![diagram|690x400](upload://aBc.png) and ![x](<https://x.test/a b.png>)\n\
+                   <img src=\"/uploads/default/1.jpg\" width=\"20\"> ![again](upload://aBc.png)\n\
+                   `![code](upload://no.png)`\n```\n![fenced](upload://no.png)\n```\n\
+                   ![titled](https://x.test/t.gif \"Title\")";
+        assert_eq!(
+            image_references(raw),
+            [
+                "upload://aBc.png",
+                "/uploads/default/1.jpg",
+                "https://x.test/t.gif"
+            ]
+        );
+
+        let locations = HashMap::from([
+            (
+                "upload://aBc.png".to_string(),
+                "images/1-aBc.png".to_string(),
+            ),
+            (
+                "https://x.test/t.gif".to_string(),
+                "images/2-t.gif".to_string(),
+            ),
+            (
+                "/uploads/default/1.jpg".to_string(),
+                "images/3-1.jpg".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            rewrite_images(raw, &locations),
+            "![diagram|690x400](images/1-aBc.png) and ![x](<https://x.test/a b.png>)\n\
+             <img src=\"images/3-1.jpg\" width=\"20\"> ![again](images/1-aBc.png)\n\
+             `![code](upload://no.png)`\n```\n![fenced](upload://no.png)\n```\n\
+             ![titled](images/2-t.gif \"Title\")"
+        );
+    }
+
+    #[test]
+    fn test_attachment_references() {
+        let base = "https://forum.test";
+        let raw = "[log.txt|attachment](upload://q1.txt) (2.1 KB)\n\
+                   ![shot](upload://img.png) [shot](upload://img.png)\n\
+                   [report](/uploads/default/original/1X/r.pdf)[site](https://other.test/x.zip)\n\
+                   `[code](upload://no.txt)`";
+        assert_eq!(
+            attachment_references(raw, base),
+            [
+                "upload://q1.txt",
+                "upload://img.png",
+                "/uploads/default/original/1X/r.pdf"
+            ]
+        );
+
+        let locations = HashMap::from([
+            ("upload://q1.txt".to_string(), "files/1-q1.txt".to_string()),
+            (
+                "upload://img.png".to_string(),
+                "files/2-img.png".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            rewrite_links(raw, &locations),
+            "[log.txt|attachment](files/1-q1.txt) (2.1 KB)\n\
+             ![shot](upload://img.png) [shot](files/2-img.png)\n\
+             [report](/uploads/default/original/1X/r.pdf)[site](https://other.test/x.zip)\n\
+             `[code](upload://no.txt)`"
+        );
+    }
+
+    #[test]
+    fn test_update_manifest() {
+        let dir = std::env::temp_dir().join(format!("disc-scrape-uploads-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let entry = |file: &str, post_number| ManifestEntry {
+            file: file.to_string(),
+            url: format!("https://forum.test/{}", file),
+            topic_url: "https://forum.test/t/x/1".to_string(),
+            post_number,
+        };
+        update_manifest(&dir, vec![entry("a.txt", 1), entry("b.zip", 2)]).unwrap();
+        update_manifest(&dir, vec![entry("a.txt", 3)]).unwrap();
+        let text = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+        let manifest: Vec<ManifestEntry> = serde_json::from_str(&text).unwrap();
+        assert_eq!(manifest, [entry("b.zip", 2), entry("a.txt", 3)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = "https://forum.test";
+        assert_eq!(
+            resolve("upload://aBc.png", base).as_deref(),
+            Some("https://forum.test/uploads/short-url/aBc.png")
+        );
+        assert_eq!(
+            resolve("//cdn.test/a.png", base).as_deref(),
+            Some("https://cdn.test/a.png")
+        );
+        assert_eq!(
+            resolve("/uploads/a.png", base).as_deref(),
+            Some("https://forum.test/uploads/a.png")
+        );
+        assert_eq!(resolve("data:image/png;base64,AAAA", base), None);
+        assert_eq!(resolve("relative.png", base), None);
+    }
+
+    #[test]
+    fn test_local_name() {
+        let name = local_name("https://forum.test/uploads/short-url/aBc.png?dl=1");
+        assert!(name.ends_with("-aBc.png"), "{}", name);
+        assert_ne!(name, local_name("https://other.test/aBc.png"));
+        assert_eq!(local_name("https://x.test/").len(), 16);
+        assert!(local_name("https://x.test/a%20b.png").ends_with("-a_20b.png"));
+    }
+}